@@ -0,0 +1,402 @@
+//! Serial console commands: parsing a line and executing it against the audio controls and the application.
+
+use core::fmt::{self, Write};
+use core::str::{FromStr, SplitWhitespace};
+
+use uac2::{ControlChanged, Downmix, Latency, Signal, StreamTopology, Terminal, Topology};
+
+/// Console commands, one per line.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Command<'a> {
+    Help,
+    /// Sample rate, stream formats, Feature Unit and buffer state
+    Status,
+    /// Underrun, overrun and overload counters
+    Stats,
+    /// List the DSP parameters, or set one by name
+    Dsp(Option<(&'a str, i32)>),
+    /// Interfaces, entities and alternate settings of the audio function
+    Descriptors,
+    /// Show the speaker-to-microphone loopback, or change it
+    Loopback(Option<LoopbackMode>),
+    /// Measure the round-trip latency through the loopback
+    Latency,
+    /// Show the microphone test signal, or change it
+    Generator(Option<GeneratorSetting>),
+}
+
+/// Argument of the loopback command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LoopbackMode {
+    Off,
+    On,
+    /// Switch the loopback on with this downmix
+    Downmix(Downmix),
+}
+
+/// Argument of the generator command.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GeneratorSetting {
+    Signal(Signal),
+    /// Level in dBFS
+    Level(i32),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    InvalidArgument,
+}
+
+/// Parse a command line, words are separated by whitespace.
+pub fn parse(line: &str) -> Result<Command<'_>, ParseError> {
+    let mut words = line.split_whitespace();
+    let command = match words.next().ok_or(ParseError::Empty)? {
+        "help" | "?" => Command::Help,
+        "status" => Command::Status,
+        "stats" => Command::Stats,
+        "descriptors" => Command::Descriptors,
+        "dsp" => match (words.next(), words.next()) {
+            (None, _) => Command::Dsp(None),
+            (Some(name), Some(value)) => {
+                let value = value.parse().map_err(|_| ParseError::InvalidArgument)?;
+                Command::Dsp(Some((name, value)))
+            }
+            (Some(_), None) => return Err(ParseError::InvalidArgument),
+        },
+        "loopback" => Command::Loopback(match words.next() {
+            None => None,
+            Some("off") => Some(LoopbackMode::Off),
+            Some("on") => Some(LoopbackMode::On),
+            Some("mono") => Some(LoopbackMode::Downmix(Downmix::Mono)),
+            Some("direct") => Some(LoopbackMode::Downmix(Downmix::Direct)),
+            Some("channel") => {
+                let channel = argument(&mut words)?;
+                Some(LoopbackMode::Downmix(Downmix::Channel(channel)))
+            }
+            Some(_) => return Err(ParseError::InvalidArgument),
+        }),
+        "latency" => Command::Latency,
+        "generator" => Command::Generator(match words.next() {
+            None => None,
+            Some("level") => Some(GeneratorSetting::Level(argument(&mut words)?)),
+            Some(signal) => Some(GeneratorSetting::Signal(match signal {
+                "silence" => Signal::Silence,
+                "sine" => Signal::Sine(argument(&mut words)?),
+                "sweep" => Signal::Sweep {
+                    start: argument(&mut words)?,
+                    end: argument(&mut words)?,
+                    duration_ms: argument(&mut words)?,
+                },
+                "white" => Signal::WhiteNoise,
+                "pink" => Signal::PinkNoise,
+                "identify" => Signal::Identify,
+                _ => return Err(ParseError::InvalidArgument),
+            })),
+        }),
+        _ => return Err(ParseError::UnknownCommand),
+    };
+    match words.next() {
+        Some(_) => Err(ParseError::InvalidArgument),
+        None => Ok(command),
+    }
+}
+
+/// The next word parsed as a number.
+fn argument<T: FromStr>(words: &mut SplitWhitespace) -> Result<T, ParseError> {
+    words
+        .next()
+        .and_then(|word| word.parse().ok())
+        .ok_or(ParseError::InvalidArgument)
+}
+
+/// Application state shown and changed through the console.
+pub trait ConsoleBackend {
+    /// Fill level and capacity in bytes of the application buffer of `terminal`, `None` if there is none.
+    fn buffer_fill(&self, terminal: Terminal) -> Option<(usize, usize)> {
+        let _ = terminal;
+        None
+    }
+
+    /// Write the DSP parameters, one `name value` line each.
+    fn write_dsp_parameters(&self, out: &mut dyn Write) -> fmt::Result;
+
+    /// Set a DSP parameter by name. Returning `false` rejects the name or value.
+    fn set_dsp_parameter(&mut self, name: &str, value: i32) -> bool;
+
+    /// Downmix of the speaker-to-microphone loopback, `None` while it is off.
+    fn loopback(&self) -> Option<Downmix>;
+
+    fn set_loopback(&mut self, enabled: bool);
+
+    fn set_downmix(&mut self, downmix: Downmix);
+
+    /// Start a latency measurement through the loopback. Returning `false` means the loopback is off.
+    fn measure_latency(&mut self) -> bool;
+
+    /// Result of the last latency measurement.
+    fn latency(&self) -> Latency;
+
+    /// Signal and level in dBFS of the microphone test signal generator.
+    fn generator(&self) -> (Signal, i32);
+
+    /// Returning `false` rejects the signal.
+    fn set_signal(&mut self, signal: Signal) -> bool;
+
+    /// Returning `false` rejects the level.
+    fn set_level(&mut self, level: i32) -> bool;
+}
+
+/// Execute `command`, the response is written to `out` with CRLF line endings.
+pub fn execute(
+    command: Command,
+    control: &ControlChanged,
+    backend: &mut impl ConsoleBackend,
+    out: &mut impl Write,
+) -> fmt::Result {
+    match command {
+        Command::Help => out.write_str(HELP),
+        Command::Status => write_status(control, backend, out),
+        Command::Stats => {
+            for terminal in [Terminal::Speaker, Terminal::Microphone] {
+                let stats = control.stats(terminal);
+                write!(
+                    out,
+                    "{}: underruns {}, overruns {}, overloads {}\r\n",
+                    terminal_name(terminal),
+                    stats.underruns,
+                    stats.overruns,
+                    stats.overloads
+                )?;
+            }
+            Ok(())
+        }
+        Command::Dsp(None) => backend.write_dsp_parameters(out),
+        Command::Dsp(Some((name, value))) => {
+            if backend.set_dsp_parameter(name, value) {
+                write!(out, "{} = {}\r\n", name, value)
+            } else {
+                write!(out, "invalid parameter or value: {} {}\r\n", name, value)
+            }
+        }
+        Command::Descriptors => write_descriptor_tree(control.topology(), out),
+        Command::Loopback(mode) => {
+            match mode {
+                None => {}
+                Some(LoopbackMode::Off) => backend.set_loopback(false),
+                Some(LoopbackMode::On) => backend.set_loopback(true),
+                Some(LoopbackMode::Downmix(downmix)) => {
+                    backend.set_downmix(downmix);
+                    backend.set_loopback(true);
+                }
+            }
+            write_loopback(control, backend, out)
+        }
+        Command::Latency => {
+            if backend.measure_latency() {
+                out.write_str(
+                    "measuring, loop the microphone to the speaker on the host and run loopback\r\n",
+                )
+            } else {
+                out.write_str("the loopback is off\r\n")
+            }
+        }
+        Command::Generator(setting) => {
+            let accepted = match setting {
+                None => true,
+                Some(GeneratorSetting::Signal(signal)) => backend.set_signal(signal),
+                Some(GeneratorSetting::Level(level)) => backend.set_level(level),
+            };
+            if !accepted {
+                out.write_str("invalid generator setting\r\n")?;
+            }
+            write_generator(backend, out)
+        }
+    }
+}
+
+fn write_generator(backend: &impl ConsoleBackend, out: &mut impl Write) -> fmt::Result {
+    let (signal, level) = backend.generator();
+    out.write_str("generator: ")?;
+    match signal {
+        Signal::Silence => out.write_str("silence")?,
+        Signal::Sine(frequency) => write!(out, "sine {} Hz", frequency)?,
+        Signal::Sweep {
+            start,
+            end,
+            duration_ms,
+        } => write!(out, "sweep {} to {} Hz in {} ms", start, end, duration_ms)?,
+        Signal::WhiteNoise => out.write_str("white noise")?,
+        Signal::PinkNoise => out.write_str("pink noise")?,
+        Signal::Identify => out.write_str("channel identification")?,
+    }
+    if signal != Signal::Silence {
+        write!(out, ", {} dBFS", level)?;
+    }
+    if backend.loopback().is_some() {
+        out.write_str(", replaced by the loopback")?;
+    }
+    out.write_str("\r\n")
+}
+
+fn write_loopback(
+    control: &ControlChanged,
+    backend: &impl ConsoleBackend,
+    out: &mut impl Write,
+) -> fmt::Result {
+    match backend.loopback() {
+        None => out.write_str("loopback: off\r\n")?,
+        Some(Downmix::Mono) => out.write_str("loopback: mono\r\n")?,
+        Some(Downmix::Direct) => out.write_str("loopback: direct\r\n")?,
+        Some(Downmix::Channel(channel)) => write!(out, "loopback: channel {}\r\n", channel)?,
+    }
+    match backend.latency() {
+        Latency::Idle => Ok(()),
+        Latency::Measuring => out.write_str("latency: measuring\r\n"),
+        Latency::TimedOut => out.write_str("latency: timed out\r\n"),
+        Latency::Measured(frames) => {
+            //  In 1/100 ms at the current sample rate
+            let time = frames as u64 * 100_000 / control.sample_rate().max(1) as u64;
+            write!(
+                out,
+                "latency: {} samples, {}.{:02} ms\r\n",
+                frames,
+                time / 100,
+                time % 100
+            )
+        }
+    }
+}
+
+fn write_status(
+    control: &ControlChanged,
+    backend: &impl ConsoleBackend,
+    out: &mut impl Write,
+) -> fmt::Result {
+    write!(out, "sample rate: {} Hz\r\n", control.sample_rate())?;
+    for terminal in [Terminal::Speaker, Terminal::Microphone] {
+        write!(out, "{}: ", terminal_name(terminal))?;
+        match control.format(terminal) {
+            Some(format) => write!(
+                out,
+                "{} bit in {} byte subslots",
+                format.bit_resolution, format.subslot_size
+            )?,
+            None => out.write_str("idle")?,
+        }
+        let connected = if control.connected(terminal) {
+            "connected"
+        } else {
+            "unplugged"
+        };
+        write!(out, ", {}\r\n", connected)?;
+        if let Some((fill, capacity)) = backend.buffer_fill(terminal) {
+            write!(out, "  buffer: {}/{} bytes\r\n", fill, capacity)?;
+        }
+    }
+
+    let channels = control.topology().speaker.channels;
+    for channel in 0..=channels {
+        let volume = control.volume(channel) as i32;
+        write!(
+            out,
+            "speaker channel {}: mute {}, volume {}{}.{:02} dB\r\n",
+            channel,
+            if control.muted(channel) { "on" } else { "off" },
+            if volume < 0 { "-" } else { "" },
+            volume.abs() / 256,
+            volume.abs() % 256 * 100 / 256
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the interfaces of the function with their entities and alternate settings.
+pub fn write_descriptor_tree(topology: &Topology, out: &mut impl Write) -> fmt::Result {
+    write!(out, "AudioControl interface {}\r\n", topology.ac_interface)?;
+    write!(out, "  Clock Source {}:", topology.clock_source)?;
+    for sample_rate in &topology.sample_rates {
+        write!(out, " {}", sample_rate)?;
+    }
+    out.write_str(" Hz\r\n")?;
+
+    for terminal in [Terminal::Speaker, Terminal::Microphone] {
+        let stream = stream(topology, terminal);
+        write!(
+            out,
+            "  {}: Input Terminal {}",
+            terminal_name(terminal),
+            stream.input_terminal
+        )?;
+        if let Some(feature_unit) = stream.feature_unit {
+            write!(out, " -> Feature Unit {}", feature_unit)?;
+        }
+        if terminal == Terminal::Speaker {
+            for id in &topology.extension_units {
+                write!(out, " -> Extension Unit {}", id)?;
+            }
+        }
+        write!(out, " -> Output Terminal {}\r\n", stream.output_terminal)?;
+    }
+
+    for terminal in [Terminal::Speaker, Terminal::Microphone] {
+        let stream = stream(topology, terminal);
+        write!(
+            out,
+            "AudioStreaming interface {}, {}, endpoint {:#04x}, {} channels ({:#010x})\r\n",
+            stream.interface,
+            terminal_name(terminal),
+            stream.endpoint,
+            stream.channels,
+            stream.channel_config
+        )?;
+        out.write_str("  alt 0: zero bandwidth\r\n")?;
+        for (i, (format, packet_size)) in stream.alt_settings.iter().enumerate() {
+            write!(
+                out,
+                "  alt {}: {} bit in {} byte subslots, {} byte packets\r\n",
+                i + 1,
+                format.bit_resolution,
+                format.subslot_size,
+                packet_size
+            )?;
+        }
+    }
+
+    if let Some(interface) = topology.midi_interface {
+        write!(out, "MIDIStreaming interface {}\r\n", interface)?;
+    }
+    Ok(())
+}
+
+fn stream(topology: &Topology, terminal: Terminal) -> &StreamTopology {
+    match terminal {
+        Terminal::Speaker => &topology.speaker,
+        Terminal::Microphone => &topology.microphone,
+    }
+}
+
+fn terminal_name(terminal: Terminal) -> &'static str {
+    match terminal {
+        Terminal::Speaker => "speaker",
+        Terminal::Microphone => "microphone",
+    }
+}
+
+const HELP: &str = "help                 this text\r\n\
+status               sample rate, stream formats, mute and volume\r\n\
+stats                underrun, overrun and overload counters\r\n\
+dsp                  list the DSP parameters\r\n\
+dsp <name> <value>   set a DSP parameter\r\n\
+descriptors          interfaces, entities and alternate settings\r\n\
+loopback             speaker-to-microphone loopback and latency\r\n\
+loopback <mode>      off, on, mono, direct or channel <n>\r\n\
+latency              measure the round trip through the host\r\n\
+generator            microphone test signal and level\r\n\
+generator <signal>   silence, sine <hz>, sweep <hz> <hz> <ms>, white, pink or identify\r\n\
+generator level <db> test signal level in dBFS, -120 to 0\r\n";
@@ -0,0 +1,234 @@
+//! DFU download state machine, independent of the USB class that drives it.
+
+use embedded_storage::nor_flash::NorFlash;
+use heapless::Vec;
+
+use crate::settings::crc32_update;
+
+/// Bytes per DFU_DNLOAD block, the control buffer of the DFU mode device must hold one block.
+pub const TRANSFER_SIZE: usize = 256;
+
+/// Device state of the DFU state machine (DFU 1.1 6.1.2)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum DfuState {
+    AppIdle = 0,
+    AppDetach = 1,
+    Idle = 2,
+    DnloadSync = 3,
+    DnloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    ManifestWaitReset = 8,
+    Error = 10,
+}
+
+/// Status of the last request (DFU 1.1 6.1.2)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum DfuStatus {
+    Ok = 0x00,
+    ErrWrite = 0x03,
+    ErrErase = 0x04,
+    ErrVerify = 0x07,
+    ErrAddress = 0x08,
+    ErrNotDone = 0x09,
+    ErrStalledPkt = 0x0F,
+}
+
+/// DFU download state machine, writes the image into a staging partition and verifies it.
+///
+/// Each block is read back after programming, the manifestation phase checks the CRC of the
+/// whole partition content against the received image. Once the image is manifested the
+/// firmware marks it updated, and the bootloader swaps it into the active partition.
+pub struct Updater<F: NorFlash> {
+    flash: F,
+    offset: u32,
+    capacity: u32,
+    state: DfuState,
+    status: DfuStatus,
+    /// Block received with the last DFU_DNLOAD, programmed on the following DFU_GETSTATUS
+    pending: Vec<u8, TRANSFER_SIZE>,
+    block: Option<u16>,
+    written: u32,
+    crc: u32,
+}
+
+impl<F: NorFlash> Updater<F> {
+    /// Updater writing to `capacity` bytes of `flash` at `offset`, both aligned to erase sectors.
+    pub fn new(flash: F, offset: u32, capacity: u32) -> Self {
+        assert!(
+            (offset as usize).is_multiple_of(F::ERASE_SIZE)
+                && (capacity as usize).is_multiple_of(F::ERASE_SIZE)
+        );
+        assert!(F::ERASE_SIZE.is_multiple_of(TRANSFER_SIZE));
+        assert!(
+            TRANSFER_SIZE.is_multiple_of(F::WRITE_SIZE)
+                && VERIFY_CHUNK.is_multiple_of(F::READ_SIZE)
+        );
+        Self {
+            flash,
+            offset,
+            capacity,
+            state: DfuState::Idle,
+            status: DfuStatus::Ok,
+            pending: Vec::new(),
+            block: None,
+            written: 0,
+            crc: !0,
+        }
+    }
+
+    pub fn state(&self) -> DfuState {
+        self.state
+    }
+
+    /// Length and CRC-32 of the image once it has been manifested.
+    pub fn image(&self) -> Option<(u32, u32)> {
+        match self.state {
+            DfuState::ManifestWaitReset => Some((self.written, !self.crc)),
+            _ => None,
+        }
+    }
+
+    /// DFU_DNLOAD, returns `false` if the request is stalled.
+    pub fn download(&mut self, block: u16, data: &[u8]) -> bool {
+        match (self.state, data.is_empty()) {
+            (DfuState::Idle, false) => {
+                self.written = 0;
+                self.crc = !0;
+                self.block = None;
+            }
+            (DfuState::DnloadIdle, false) => {}
+            (DfuState::DnloadIdle, true) => {
+                self.state = DfuState::ManifestSync;
+                return true;
+            }
+            _ => return self.fail(DfuStatus::ErrStalledPkt),
+        }
+
+        if self
+            .block
+            .is_some_and(|previous| block != previous.wrapping_add(1))
+        {
+            return self.fail(DfuStatus::ErrStalledPkt);
+        }
+        //  Only the last block may be short, so every block starts on a block boundary
+        if !(self.written as usize).is_multiple_of(TRANSFER_SIZE)
+            || data.len() > TRANSFER_SIZE
+            || self.written + data.len() as u32 > self.capacity
+        {
+            return self.fail(DfuStatus::ErrAddress);
+        }
+
+        self.pending.clear();
+        let _ = self.pending.extend_from_slice(data);
+        self.block = Some(block);
+        self.state = DfuState::DnloadSync;
+        true
+    }
+
+    /// DFU_GETSTATUS, finishes the pending programming or manifestation.
+    pub fn get_status(&mut self) -> (DfuStatus, DfuState) {
+        match self.state {
+            DfuState::DnloadSync => match self.program() {
+                Ok(()) => self.state = DfuState::DnloadIdle,
+                Err(status) => {
+                    self.fail(status);
+                }
+            },
+            DfuState::ManifestSync => match self.verify() {
+                Ok(()) => {
+                    info!("DFU image of {} bytes verified", self.written);
+                    self.state = DfuState::ManifestWaitReset;
+                    return (DfuStatus::Ok, DfuState::Manifest);
+                }
+                Err(status) => {
+                    self.fail(status);
+                }
+            },
+            _ => {}
+        }
+        (self.status, self.state)
+    }
+
+    /// DFU_CLRSTATUS
+    pub fn clear_status(&mut self) {
+        if self.state == DfuState::Error {
+            self.status = DfuStatus::Ok;
+            self.state = DfuState::Idle;
+        }
+    }
+
+    /// DFU_ABORT
+    pub fn abort(&mut self) {
+        if self.state != DfuState::Error && self.state != DfuState::ManifestWaitReset {
+            self.state = DfuState::Idle;
+        }
+    }
+
+    fn fail(&mut self, status: DfuStatus) -> bool {
+        info!("DFU error {} in {}", status, self.state);
+        self.status = status;
+        self.state = DfuState::Error;
+        false
+    }
+
+    fn program(&mut self) -> Result<(), DfuStatus> {
+        let address = self.offset + self.written;
+        if (self.written as usize).is_multiple_of(F::ERASE_SIZE) {
+            self.flash
+                .erase(address, address + F::ERASE_SIZE as u32)
+                .map_err(|_| DfuStatus::ErrErase)?;
+        }
+
+        let len = self.pending.len();
+        let _ = self
+            .pending
+            .resize(len.next_multiple_of(F::WRITE_SIZE), 0xFF);
+        self.flash
+            .write(address, &self.pending)
+            .map_err(|_| DfuStatus::ErrWrite)?;
+
+        let mut readback = [0; VERIFY_CHUNK];
+        for (i, chunk) in self.pending.chunks(VERIFY_CHUNK).enumerate() {
+            let readback = &mut readback[..chunk.len().next_multiple_of(F::READ_SIZE)];
+            self.flash
+                .read(address + (i * VERIFY_CHUNK) as u32, readback)
+                .map_err(|_| DfuStatus::ErrVerify)?;
+            if readback[..chunk.len()] != *chunk {
+                return Err(DfuStatus::ErrVerify);
+            }
+        }
+
+        self.crc = crc32_update(self.crc, &self.pending[..len]);
+        self.written += len as u32;
+        Ok(())
+    }
+
+    fn verify(&mut self) -> Result<(), DfuStatus> {
+        if self.written == 0 {
+            return Err(DfuStatus::ErrNotDone);
+        }
+        let mut crc = !0;
+        let mut readback = [0; VERIFY_CHUNK];
+        let mut position = 0;
+        while position < self.written {
+            let len = (self.written - position).min(VERIFY_CHUNK as u32) as usize;
+            let readback = &mut readback[..len.next_multiple_of(F::READ_SIZE)];
+            self.flash
+                .read(self.offset + position, readback)
+                .map_err(|_| DfuStatus::ErrVerify)?;
+            crc = crc32_update(crc, &readback[..len]);
+            position += len as u32;
+        }
+        if crc != self.crc {
+            return Err(DfuStatus::ErrVerify);
+        }
+        Ok(())
+    }
+}
+
+const VERIFY_CHUNK: usize = 64;
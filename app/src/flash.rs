@@ -0,0 +1,135 @@
+//! Partitions of a flash shared by the settings, the bootloader state and the DFU image.
+
+use core::cell::RefCell;
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// `size` bytes of a shared flash starting at `offset`, addressed from 0.
+///
+/// Every operation borrows the flash for its duration only, so partitions of one flash can be
+/// handed to different users on the same executor.
+pub struct Partition<'a, F> {
+    flash: &'a RefCell<F>,
+    offset: u32,
+    size: u32,
+}
+
+impl<'a, F: NorFlash> Partition<'a, F> {
+    /// Partition at `offset` of `flash`, both `offset` and `size` aligned to erase sectors.
+    pub fn new(flash: &'a RefCell<F>, offset: u32, size: u32) -> Self {
+        assert!(
+            (offset as usize).is_multiple_of(F::ERASE_SIZE)
+                && (size as usize).is_multiple_of(F::ERASE_SIZE)
+        );
+        Self {
+            flash,
+            offset,
+            size,
+        }
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Flash address of `len` bytes at `offset`, if they are inside the partition.
+    fn address(&self, offset: u32, len: usize) -> Result<u32, Error<F::Error>> {
+        if offset as u64 + len as u64 > self.size as u64 {
+            return Err(Error::OutOfBounds);
+        }
+        Ok(self.offset + offset)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<E> {
+    /// Access beyond the end of the partition
+    OutOfBounds,
+    Flash(E),
+}
+
+impl<E: NorFlashError> NorFlashError for Error<E> {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Error::Flash(error) => error.kind(),
+        }
+    }
+}
+
+impl<F: NorFlash> ErrorType for Partition<'_, F> {
+    type Error = Error<F::Error>;
+}
+
+impl<F: NorFlash> ReadNorFlash for Partition<'_, F> {
+    const READ_SIZE: usize = F::READ_SIZE;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        let address = self.address(offset, bytes.len())?;
+        self.flash
+            .borrow_mut()
+            .read(address, bytes)
+            .map_err(Error::Flash)
+    }
+
+    fn capacity(&self) -> usize {
+        self.size as usize
+    }
+}
+
+impl<F: NorFlash> NorFlash for Partition<'_, F> {
+    const WRITE_SIZE: usize = F::WRITE_SIZE;
+    const ERASE_SIZE: usize = F::ERASE_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        if to < from {
+            return Err(Error::OutOfBounds);
+        }
+        let address = self.address(from, (to - from) as usize)?;
+        self.flash
+            .borrow_mut()
+            .erase(address, address + (to - from))
+            .map_err(Error::Flash)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        let address = self.address(offset, bytes.len())?;
+        self.flash
+            .borrow_mut()
+            .write(address, bytes)
+            .map_err(Error::Flash)
+    }
+}
+
+/// Flash layout of the firmware on a 2 MiB flash, the `memory.x` files of the firmware and of the
+/// bootloader must match it.
+///
+/// The bootloader follows the second stage boot loader in the first 256 bytes. It swaps an image
+/// the firmware marked updated from the DFU partition into the active one, page by page through
+/// the spare sector at the end of the DFU partition, and keeps its progress in its state partition.
+pub mod layout {
+    pub const FLASH_SIZE: u32 = 2 * 1024 * 1024;
+    pub const SECTOR_SIZE: u32 = 4096;
+
+    pub const BOOTLOADER_SIZE: u32 = 24 * 1024;
+    pub const BOOTLOADER_STATE_OFFSET: u32 = BOOTLOADER_SIZE;
+    pub const BOOTLOADER_STATE_SIZE: u32 = SECTOR_SIZE;
+    /// The running firmware, `FLASH` in its `memory.x`
+    pub const ACTIVE_OFFSET: u32 = BOOTLOADER_STATE_OFFSET + BOOTLOADER_STATE_SIZE;
+    pub const ACTIVE_SIZE: u32 = 1004 * 1024;
+    /// Staged DFU image, one sector larger than the active partition
+    pub const DFU_OFFSET: u32 = ACTIVE_OFFSET + ACTIVE_SIZE;
+    pub const DFU_SIZE: u32 = ACTIVE_SIZE + SECTOR_SIZE;
+    /// Settings log in the last sectors
+    pub const SETTINGS_OFFSET: u32 = DFU_OFFSET + DFU_SIZE;
+    pub const SETTINGS_SIZE: u32 = 2 * SECTOR_SIZE;
+
+    const _: () = assert!(SETTINGS_OFFSET + SETTINGS_SIZE == FLASH_SIZE);
+}
@@ -0,0 +1,13 @@
+//! Logging through defmt when the `defmt` feature is enabled, compiled out otherwise.
+#![allow(unused_macros)]
+
+macro_rules! info {
+    ($s:literal $(, $x:expr)* $(,)?) => {
+        {
+            #[cfg(feature = "defmt")]
+            ::defmt::info!($s $(, $x)*);
+            #[cfg(not(feature = "defmt"))]
+            let _ = ($( & $x ),*);
+        }
+    };
+}
@@ -0,0 +1,12 @@
+//! Parts of the firmware that do not touch the hardware, so they build and run their tests on the host.
+//!
+//! The workspace targets the RP2040, `cargo test -p app --target host-tuple` runs the tests.
+#![no_std]
+
+#[macro_use]
+mod fmt;
+
+pub mod console;
+pub mod dfu;
+pub mod flash;
+pub mod settings;
@@ -0,0 +1,273 @@
+//! User settings and the flash log they are saved in.
+
+use embedded_storage::nor_flash::NorFlash;
+
+use uac2::{ControlChanged, MAX_CHANNELS};
+
+/// Number of application parameters stored with the audio controls.
+pub const MAX_PARAMETERS: usize = 8;
+
+const CHANNELS: usize = MAX_CHANNELS as usize + 1;
+
+/// User settings restored at boot.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Settings {
+    /// Sampling frequency of the clock source in Hz
+    pub sample_rate: u32,
+    /// Mute controls of the speaker Feature Unit, master channel first
+    pub mute: [bool; CHANNELS],
+    /// Volume controls of the speaker Feature Unit in 1/256 dB, master channel first
+    pub volume: [i16; CHANNELS],
+    /// Application state such as DSP, EQ or selector settings, in an order defined by the application
+    pub parameters: [i32; MAX_PARAMETERS],
+}
+
+impl Settings {
+    /// Current state of the audio controls together with the application `parameters`.
+    pub fn capture(control: &ControlChanged, parameters: [i32; MAX_PARAMETERS]) -> Self {
+        let mut settings = Self {
+            sample_rate: control.sample_rate(),
+            mute: [false; CHANNELS],
+            volume: [0; CHANNELS],
+            parameters,
+        };
+        for channel in 0..CHANNELS {
+            settings.mute[channel] = control.muted(channel as u8);
+            settings.volume[channel] = control.volume(channel as u8);
+        }
+        settings
+    }
+
+    /// Restore the audio controls, values the function does not support are skipped.
+    ///
+    /// The application parameters are left to the caller.
+    pub fn apply(&self, control: &ControlChanged) {
+        control.set_sample_rate(self.sample_rate);
+        let channels = control.topology().speaker.channels as usize;
+        for channel in 0..=channels {
+            control.set_muted(channel as u8, self.mute[channel]);
+            control.set_volume(channel as u8, self.volume[channel]);
+        }
+    }
+
+    fn encode(&self, payload: &mut [u8; PAYLOAD_SIZE]) {
+        let mut mute = 0u16;
+        for (channel, &muted) in self.mute.iter().enumerate() {
+            mute |= (muted as u16) << channel;
+        }
+        payload[0..4].copy_from_slice(&self.sample_rate.to_le_bytes());
+        payload[4..6].copy_from_slice(&mute.to_le_bytes());
+        let (volume, parameters) = payload[6..].split_at_mut(2 * CHANNELS);
+        for (bytes, value) in volume.as_chunks_mut().0.iter_mut().zip(self.volume) {
+            *bytes = value.to_le_bytes();
+        }
+        for (bytes, value) in parameters.as_chunks_mut().0.iter_mut().zip(self.parameters) {
+            *bytes = value.to_le_bytes();
+        }
+    }
+
+    /// Decode the payload of a record, `None` if its version is unknown.
+    fn decode(version: u8, payload: &[u8]) -> Option<Self> {
+        if version != VERSION || payload.len() != PAYLOAD_SIZE {
+            return None;
+        }
+        let mute = u16::from_le_bytes([payload[4], payload[5]]);
+        let (volume, parameters) = payload[6..].split_at(2 * CHANNELS);
+        let mut settings = Self {
+            sample_rate: u32::from_le_bytes(payload[0..4].try_into().ok()?),
+            mute: [false; CHANNELS],
+            volume: [0; CHANNELS],
+            parameters: [0; MAX_PARAMETERS],
+        };
+        for channel in 0..CHANNELS {
+            settings.mute[channel] = mute & (1 << channel) != 0;
+        }
+        for (value, bytes) in settings.volume.iter_mut().zip(volume.as_chunks().0) {
+            *value = i16::from_le_bytes(*bytes);
+        }
+        for (value, bytes) in settings.parameters.iter_mut().zip(parameters.as_chunks().0) {
+            *value = i32::from_le_bytes(*bytes);
+        }
+        Some(settings)
+    }
+}
+
+/// Settings log in reserved flash sectors.
+///
+/// Every save appends a record with an increasing sequence number and a CRC, the valid record with the
+/// highest sequence number wins. A sector is erased only when the log wraps into it, so the writes are
+/// spread over all sectors and the previous record survives a power loss during a save.
+pub struct SettingsStore<F: NorFlash> {
+    flash: F,
+    offset: u32,
+    sectors: u32,
+    /// Slot of the next record, `None` until the log has been scanned
+    next: Option<u32>,
+    sequence: u32,
+}
+
+impl<F: NorFlash> SettingsStore<F> {
+    /// Store in `sectors` erase sectors starting at `offset`, which must be reserved for the settings.
+    pub fn new(flash: F, offset: u32, sectors: u32) -> Self {
+        assert!(sectors >= 2);
+        assert!((offset as usize).is_multiple_of(F::ERASE_SIZE));
+        assert!(F::ERASE_SIZE.is_multiple_of(RECORD_SIZE));
+        assert!(
+            RECORD_SIZE.is_multiple_of(F::WRITE_SIZE) && RECORD_SIZE.is_multiple_of(F::READ_SIZE)
+        );
+        Self {
+            flash,
+            offset,
+            sectors,
+            next: None,
+            sequence: 0,
+        }
+    }
+
+    /// Latest saved settings, `None` if there are none in a known version.
+    pub fn load(&mut self) -> Result<Option<Settings>, F::Error> {
+        let mut latest = None;
+        let mut record = [0; RECORD_SIZE];
+        for slot in 0..self.slots() {
+            self.flash.read(self.address(slot), &mut record)?;
+            let Some((sequence, version, payload)) = parse_record(&record) else {
+                continue;
+            };
+            //  Sequence numbers wrap, a record is newer if it is less than half the number range ahead
+            if latest.is_some_and(|(_, latest_sequence, _)| {
+                sequence.wrapping_sub(latest_sequence) as i32 <= 0
+            }) {
+                continue;
+            }
+            latest = Some((slot, sequence, Settings::decode(version, payload)));
+        }
+
+        match latest {
+            Some((slot, sequence, settings)) => {
+                self.next = Some((slot + 1) % self.slots());
+                self.sequence = sequence.wrapping_add(1);
+                Ok(settings)
+            }
+            None => {
+                self.next = Some(0);
+                self.sequence = 0;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Append `settings` to the log.
+    pub fn save(&mut self, settings: &Settings) -> Result<(), F::Error> {
+        let mut slot = match self.next {
+            Some(slot) => slot,
+            None => {
+                self.load()?;
+                self.next.unwrap_or(0)
+            }
+        };
+
+        //  Skip slots left dirty by an interrupted save, up to the next sector which is then erased
+        loop {
+            let address = self.address(slot);
+            if (address as usize).is_multiple_of(F::ERASE_SIZE) {
+                self.flash.erase(address, address + F::ERASE_SIZE as u32)?;
+                break;
+            }
+            if self.blank(address)? {
+                break;
+            }
+            slot = (slot + 1) % self.slots();
+        }
+
+        let mut record = [0xFF; RECORD_SIZE];
+        encode_record(&mut record, self.sequence, settings);
+        self.flash.write(self.address(slot), &record)?;
+        info!(
+            "Settings saved, sequence {} in slot {}",
+            self.sequence, slot
+        );
+
+        self.next = Some((slot + 1) % self.slots());
+        self.sequence = self.sequence.wrapping_add(1);
+        Ok(())
+    }
+
+    fn slots(&self) -> u32 {
+        self.sectors * (F::ERASE_SIZE / RECORD_SIZE) as u32
+    }
+
+    fn address(&self, slot: u32) -> u32 {
+        self.offset + slot * RECORD_SIZE as u32
+    }
+
+    fn blank(&mut self, address: u32) -> Result<bool, F::Error> {
+        let mut record = [0; RECORD_SIZE];
+        self.flash.read(address, &mut record)?;
+        Ok(record.iter().all(|&byte| byte == 0xFF))
+    }
+}
+
+/// Encode `settings` as the log record with `sequence`.
+///
+/// Record layout: magic, version, payload length, sequence number, payload, CRC-32 of everything before it.
+pub fn encode_record(record: &mut [u8; RECORD_SIZE], sequence: u32, settings: &Settings) {
+    let mut payload = [0; PAYLOAD_SIZE];
+    settings.encode(&mut payload);
+    record[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+    record[2] = VERSION;
+    record[3] = PAYLOAD_SIZE as u8;
+    record[4..8].copy_from_slice(&sequence.to_le_bytes());
+    record[HEADER_SIZE..HEADER_SIZE + PAYLOAD_SIZE].copy_from_slice(&payload);
+    let end = HEADER_SIZE + PAYLOAD_SIZE;
+    let crc = crc32(&record[..end]);
+    record[end..end + 4].copy_from_slice(&crc.to_le_bytes());
+}
+
+/// Sequence number, version and payload of a record with a valid CRC.
+pub fn parse_record(record: &[u8; RECORD_SIZE]) -> Option<(u32, u8, &[u8])> {
+    if u16::from_le_bytes([record[0], record[1]]) != MAGIC {
+        return None;
+    }
+    let end = HEADER_SIZE + record[3] as usize;
+    if end + 4 > RECORD_SIZE {
+        return None;
+    }
+    let crc = u32::from_le_bytes(record[end..end + 4].try_into().ok()?);
+    if crc32(&record[..end]) != crc {
+        return None;
+    }
+    let sequence = u32::from_le_bytes(record[4..8].try_into().ok()?);
+    Some((sequence, record[2], &record[HEADER_SIZE..end]))
+}
+
+/// CRC-32 (IEEE 802.3), bitwise to keep the table out of flash.
+pub fn crc32(data: &[u8]) -> u32 {
+    !crc32_update(!0, data)
+}
+
+/// Feed `data` into a running CRC-32 that starts out as `!0` and is inverted at the end.
+pub fn crc32_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+const MAGIC: u16 = 0x5553;
+/// Schema version of the payload, bump when the layout of [`Settings`] changes
+pub const VERSION: u8 = 1;
+
+const HEADER_SIZE: usize = 8;
+const PAYLOAD_SIZE: usize = 4 + 2 + 2 * CHANNELS + 4 * MAX_PARAMETERS;
+/// Bytes per record, every record starts on a multiple of it
+pub const RECORD_SIZE: usize = 128;
+
+const _: () = assert!(CHANNELS <= 16 && HEADER_SIZE + PAYLOAD_SIZE + 4 <= RECORD_SIZE);
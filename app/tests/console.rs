@@ -0,0 +1,122 @@
+//! Parsing of console command lines.
+
+use app::console::{parse, Command, GeneratorSetting, LoopbackMode, ParseError};
+use uac2::{Downmix, Signal};
+
+#[test]
+fn commands_without_arguments() {
+    assert_eq!(parse("help"), Ok(Command::Help));
+    assert_eq!(parse("?"), Ok(Command::Help));
+    assert_eq!(parse("status"), Ok(Command::Status));
+    assert_eq!(parse("stats"), Ok(Command::Stats));
+    assert_eq!(parse("descriptors"), Ok(Command::Descriptors));
+    assert_eq!(parse("latency"), Ok(Command::Latency));
+    assert_eq!(parse("dsp"), Ok(Command::Dsp(None)));
+    assert_eq!(parse("loopback"), Ok(Command::Loopback(None)));
+    assert_eq!(parse("generator"), Ok(Command::Generator(None)));
+}
+
+#[test]
+fn words_are_separated_by_any_whitespace() {
+    assert_eq!(parse("  status \t"), Ok(Command::Status));
+    assert_eq!(
+        parse("dsp\tgain   -6"),
+        Ok(Command::Dsp(Some(("gain", -6))))
+    );
+}
+
+#[test]
+fn empty_lines() {
+    assert_eq!(parse(""), Err(ParseError::Empty));
+    assert_eq!(parse("   "), Err(ParseError::Empty));
+}
+
+#[test]
+fn unknown_commands() {
+    assert_eq!(parse("reboot"), Err(ParseError::UnknownCommand));
+    assert_eq!(parse("Status"), Err(ParseError::UnknownCommand));
+}
+
+#[test]
+fn dsp_parameters() {
+    assert_eq!(parse("dsp gain 12"), Ok(Command::Dsp(Some(("gain", 12)))));
+    assert_eq!(parse("dsp gain"), Err(ParseError::InvalidArgument));
+    assert_eq!(parse("dsp gain loud"), Err(ParseError::InvalidArgument));
+    assert_eq!(parse("dsp gain 1 2"), Err(ParseError::InvalidArgument));
+}
+
+#[test]
+fn loopback_modes() {
+    let mode = |line| match parse(line) {
+        Ok(Command::Loopback(mode)) => Ok(mode),
+        Ok(command) => panic!("{line}: {command:?}"),
+        Err(error) => Err(error),
+    };
+    assert_eq!(mode("loopback off"), Ok(Some(LoopbackMode::Off)));
+    assert_eq!(mode("loopback on"), Ok(Some(LoopbackMode::On)));
+    assert_eq!(
+        mode("loopback mono"),
+        Ok(Some(LoopbackMode::Downmix(Downmix::Mono)))
+    );
+    assert_eq!(
+        mode("loopback direct"),
+        Ok(Some(LoopbackMode::Downmix(Downmix::Direct)))
+    );
+    assert_eq!(
+        mode("loopback channel 2"),
+        Ok(Some(LoopbackMode::Downmix(Downmix::Channel(2))))
+    );
+    assert_eq!(mode("loopback channel"), Err(ParseError::InvalidArgument));
+    assert_eq!(
+        mode("loopback channel -1"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(mode("loopback stereo"), Err(ParseError::InvalidArgument));
+    assert_eq!(mode("loopback on now"), Err(ParseError::InvalidArgument));
+}
+
+#[test]
+fn generator_settings() {
+    let setting = |line| match parse(line) {
+        Ok(Command::Generator(setting)) => Ok(setting),
+        Ok(command) => panic!("{line}: {command:?}"),
+        Err(error) => Err(error),
+    };
+    let signal = |signal| Ok(Some(GeneratorSetting::Signal(signal)));
+    assert_eq!(setting("generator silence"), signal(Signal::Silence));
+    assert_eq!(setting("generator sine 440"), signal(Signal::Sine(440)));
+    assert_eq!(
+        setting("generator sweep 20 20000 5000"),
+        signal(Signal::Sweep {
+            start: 20,
+            end: 20000,
+            duration_ms: 5000
+        })
+    );
+    assert_eq!(setting("generator white"), signal(Signal::WhiteNoise));
+    assert_eq!(setting("generator pink"), signal(Signal::PinkNoise));
+    assert_eq!(setting("generator identify"), signal(Signal::Identify));
+    assert_eq!(
+        setting("generator level -20"),
+        Ok(Some(GeneratorSetting::Level(-20)))
+    );
+
+    assert_eq!(setting("generator sine"), Err(ParseError::InvalidArgument));
+    assert_eq!(
+        setting("generator sine -1"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        setting("generator sweep 20 20000"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(setting("generator level"), Err(ParseError::InvalidArgument));
+    assert_eq!(
+        setting("generator square 440"),
+        Err(ParseError::InvalidArgument)
+    );
+    assert_eq!(
+        setting("generator white 440"),
+        Err(ParseError::InvalidArgument)
+    );
+}
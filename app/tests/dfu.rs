@@ -0,0 +1,169 @@
+//! DFU download into a partition of an in-memory flash.
+
+mod flash;
+
+use std::cell::RefCell;
+
+use app::dfu::{DfuState, DfuStatus, Updater, TRANSFER_SIZE};
+use app::flash::Partition;
+use app::settings::crc32;
+use flash::{MemFlash, SECTOR_SIZE};
+
+/// The staging partition spans sectors 1 and 2 of a 4 sector flash.
+const OFFSET: u32 = SECTOR_SIZE as u32;
+const CAPACITY: u32 = 2 * SECTOR_SIZE as u32;
+
+#[test]
+fn image_is_staged_and_verified() {
+    let flash = RefCell::new(MemFlash::new(4));
+    let mut updater = updater(&flash);
+    //  The last block is short and not a multiple of the write size
+    let image = image(5001);
+    download(&mut updater, &image);
+    assert_eq!(updater.image(), None);
+
+    assert!(updater.download(20, &[]));
+    assert_eq!(updater.get_status(), (DfuStatus::Ok, DfuState::Manifest));
+    assert_eq!(updater.state(), DfuState::ManifestWaitReset);
+    assert_eq!(updater.image(), Some((5001, crc32(&image))));
+
+    let flash = flash.borrow();
+    let start = OFFSET as usize;
+    assert_eq!(flash.data[start..start + image.len()], image);
+    assert!(flash.data[start + image.len()..].iter().all(|&b| b == 0xFF));
+    assert!(flash.data[..start].iter().all(|&b| b == 0xFF));
+    assert_eq!(flash.erases, [0, 1, 1, 0]);
+}
+
+#[test]
+fn image_larger_than_the_partition() {
+    let flash = RefCell::new(MemFlash::new(4));
+    let mut updater = updater(&flash);
+    let image = image(CAPACITY as usize + 1);
+    download(&mut updater, &image[..CAPACITY as usize]);
+
+    assert!(!updater.download(
+        (CAPACITY as usize / TRANSFER_SIZE) as u16,
+        &image[CAPACITY as usize..]
+    ));
+    assert_eq!(
+        updater.get_status(),
+        (DfuStatus::ErrAddress, DfuState::Error)
+    );
+    assert!(flash.borrow().data[3 * SECTOR_SIZE..]
+        .iter()
+        .all(|&b| b == 0xFF));
+
+    updater.clear_status();
+    assert_eq!(updater.get_status(), (DfuStatus::Ok, DfuState::Idle));
+}
+
+#[test]
+fn blocks_out_of_sequence() {
+    let flash = RefCell::new(MemFlash::new(4));
+    let mut updater = updater(&flash);
+    let image = image(3 * TRANSFER_SIZE);
+    let blocks: Vec<_> = image.chunks(TRANSFER_SIZE).collect();
+
+    assert!(updater.download(7, blocks[0]));
+    assert_eq!(updater.get_status(), (DfuStatus::Ok, DfuState::DnloadIdle));
+    assert!(!updater.download(9, blocks[1]));
+    assert_eq!(
+        updater.get_status(),
+        (DfuStatus::ErrStalledPkt, DfuState::Error)
+    );
+}
+
+#[test]
+fn only_the_last_block_may_be_short() {
+    let flash = RefCell::new(MemFlash::new(4));
+    let mut updater = updater(&flash);
+
+    assert!(updater.download(0, &[0x55; 100]));
+    assert_eq!(updater.get_status(), (DfuStatus::Ok, DfuState::DnloadIdle));
+    assert!(!updater.download(1, &[0x55; 100]));
+    assert_eq!(
+        updater.get_status(),
+        (DfuStatus::ErrAddress, DfuState::Error)
+    );
+}
+
+#[test]
+fn manifest_needs_an_image() {
+    let flash = RefCell::new(MemFlash::new(4));
+    let mut updater = updater(&flash);
+
+    assert!(!updater.download(0, &[]));
+    assert_eq!(
+        updater.get_status(),
+        (DfuStatus::ErrStalledPkt, DfuState::Error)
+    );
+    assert_eq!(updater.image(), None);
+}
+
+#[test]
+fn corrupted_image_fails_verification() {
+    let flash = RefCell::new(MemFlash::new(4));
+    let mut updater = updater(&flash);
+    let image = image(1000);
+    download(&mut updater, &image);
+    //  A bit flipped after the read back
+    flash.borrow_mut().data[OFFSET as usize + 600] ^= 0x01;
+
+    assert!(updater.download(4, &[]));
+    assert_eq!(
+        updater.get_status(),
+        (DfuStatus::ErrVerify, DfuState::Error)
+    );
+    assert_eq!(updater.image(), None);
+}
+
+#[test]
+fn write_failure() {
+    let flash = RefCell::new(MemFlash::new(4));
+    let mut updater = updater(&flash);
+    let image = image(2 * TRANSFER_SIZE);
+
+    assert!(updater.download(0, &image[..TRANSFER_SIZE]));
+    assert_eq!(updater.get_status(), (DfuStatus::Ok, DfuState::DnloadIdle));
+    flash.borrow_mut().power_loss_after(16);
+    assert!(updater.download(1, &image[TRANSFER_SIZE..]));
+    assert_eq!(updater.get_status(), (DfuStatus::ErrWrite, DfuState::Error));
+}
+
+#[test]
+fn abort_restarts_the_download() {
+    let flash = RefCell::new(MemFlash::new(4));
+    let mut updater = updater(&flash);
+    let old = image(3 * TRANSFER_SIZE);
+    download(&mut updater, &old);
+    updater.abort();
+    assert_eq!(updater.state(), DfuState::Idle);
+
+    let image: Vec<u8> = image(TRANSFER_SIZE + 4).iter().map(|b| !b).collect();
+    download(&mut updater, &image);
+    assert!(updater.download(2, &[]));
+    assert_eq!(updater.get_status(), (DfuStatus::Ok, DfuState::Manifest));
+    assert_eq!(updater.image(), Some((image.len() as u32, crc32(&image))));
+}
+
+fn updater(flash: &RefCell<MemFlash>) -> Updater<Partition<'_, MemFlash>> {
+    Updater::new(Partition::new(flash, OFFSET, CAPACITY), 0, CAPACITY)
+}
+
+/// Send `image` block by block, each programmed by the following DFU_GETSTATUS.
+fn download(updater: &mut Updater<Partition<'_, MemFlash>>, image: &[u8]) {
+    for (block, data) in image.chunks(TRANSFER_SIZE).enumerate() {
+        assert!(updater.download(block as u16, data), "block {block}");
+        assert_eq!(
+            updater.get_status(),
+            (DfuStatus::Ok, DfuState::DnloadIdle),
+            "block {block}"
+        );
+    }
+}
+
+/// Image content without runs of erased bytes.
+fn image(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+}
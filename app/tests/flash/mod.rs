@@ -0,0 +1,111 @@
+//! In-memory NOR flash with the sector geometry of the RP2040 flash.
+#![allow(dead_code)]
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+pub const SECTOR_SIZE: usize = 4096;
+
+/// Flash of `sectors` erase sectors, erased to 0xFF.
+///
+/// Programming only clears bits like a NOR flash does. A power loss is simulated by a budget of
+/// bytes after which a write stops halfway and every further operation fails.
+pub struct MemFlash {
+    pub data: Vec<u8>,
+    pub erases: Vec<u32>,
+    budget: Option<usize>,
+}
+
+impl MemFlash {
+    pub fn new(sectors: usize) -> Self {
+        Self {
+            data: vec![0xFF; sectors * SECTOR_SIZE],
+            erases: vec![0; sectors],
+            budget: None,
+        }
+    }
+
+    /// Lose power after `bytes` more bytes have been programmed.
+    pub fn power_loss_after(&mut self, bytes: usize) {
+        self.budget = Some(bytes);
+    }
+
+    /// Power is back, operations succeed again.
+    pub fn power_on(&mut self) {
+        self.budget = None;
+    }
+
+    fn check(&self, offset: u32, len: usize, align: usize) -> Result<(), Error> {
+        if !(offset as usize).is_multiple_of(align) || !len.is_multiple_of(align) {
+            return Err(Error(NorFlashErrorKind::NotAligned));
+        }
+        if offset as usize + len > self.data.len() {
+            return Err(Error(NorFlashErrorKind::OutOfBounds));
+        }
+        if self.budget == Some(0) {
+            return Err(Error(NorFlashErrorKind::Other));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Error(pub NorFlashErrorKind);
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+impl ErrorType for MemFlash {
+    type Error = Error;
+}
+
+impl ReadNorFlash for MemFlash {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        self.check(offset, bytes.len(), Self::READ_SIZE)?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for MemFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR_SIZE;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        self.check(from, (to - from) as usize, Self::ERASE_SIZE)?;
+        self.data[from as usize..to as usize].fill(0xFF);
+        for sector in from as usize / SECTOR_SIZE..to as usize / SECTOR_SIZE {
+            self.erases[sector] += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        self.check(offset, bytes.len(), Self::WRITE_SIZE)?;
+        let len = self
+            .budget
+            .map_or(bytes.len(), |budget| budget.min(bytes.len()));
+        let offset = offset as usize;
+        for (cell, byte) in self.data[offset..offset + len].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        if let Some(budget) = &mut self.budget {
+            *budget -= len;
+            if len < bytes.len() {
+                return Err(Error(NorFlashErrorKind::Other));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,167 @@
+//! Settings log on an in-memory flash.
+
+mod flash;
+
+use app::settings::{
+    crc32, encode_record, parse_record, Settings, SettingsStore, MAX_PARAMETERS, RECORD_SIZE,
+    VERSION,
+};
+use flash::{MemFlash, SECTOR_SIZE};
+use uac2::MAX_CHANNELS;
+
+const SECTORS: usize = 2;
+const SLOTS: usize = SECTORS * SECTOR_SIZE / RECORD_SIZE;
+const CHANNELS: usize = MAX_CHANNELS as usize + 1;
+
+#[test]
+fn round_trip() {
+    let mut flash = MemFlash::new(SECTORS);
+    assert_eq!(load(&mut flash), None);
+
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    store.save(&settings(1)).unwrap();
+    assert_eq!(load(&mut flash), Some(settings(1)));
+
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    assert_eq!(store.load(), Ok(Some(settings(1))));
+    store.save(&settings(2)).unwrap();
+    store.save(&settings(3)).unwrap();
+    assert_eq!(load(&mut flash), Some(settings(3)));
+}
+
+#[test]
+fn store_at_an_offset() {
+    let mut flash = MemFlash::new(SECTORS + 1);
+    let mut store = SettingsStore::new(&mut flash, SECTOR_SIZE as u32, SECTORS as u32);
+    store.save(&settings(1)).unwrap();
+    assert!(flash.data[..SECTOR_SIZE].iter().all(|&byte| byte == 0xFF));
+    assert_eq!(flash.erases, [0, 1, 0]);
+
+    let mut store = SettingsStore::new(&mut flash, SECTOR_SIZE as u32, SECTORS as u32);
+    assert_eq!(store.load(), Ok(Some(settings(1))));
+}
+
+#[test]
+fn torn_last_record() {
+    let mut flash = MemFlash::new(SECTORS);
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    store.save(&settings(1)).unwrap();
+    flash.power_loss_after(RECORD_SIZE / 2);
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    assert!(store.save(&settings(2)).is_err());
+    flash.power_on();
+
+    //  The previous record survives, the next save skips the dirty slot
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    assert_eq!(store.load(), Ok(Some(settings(1))));
+    store.save(&settings(3)).unwrap();
+    assert_eq!(load(&mut flash), Some(settings(3)));
+    assert_eq!(sequence(&flash, 2), Some(1));
+}
+
+#[test]
+fn corrupt_last_record() {
+    let mut flash = MemFlash::new(SECTORS);
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    store.save(&settings(1)).unwrap();
+    store.save(&settings(2)).unwrap();
+    //  A bit that did not program
+    flash.data[RECORD_SIZE + 12] |= 0x01;
+
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    assert_eq!(store.load(), Ok(Some(settings(1))));
+    store.save(&settings(3)).unwrap();
+    assert_eq!(load(&mut flash), Some(settings(3)));
+}
+
+#[test]
+fn log_wraps_around_the_sectors() {
+    let mut flash = MemFlash::new(SECTORS);
+    for n in 0..SLOTS as i32 + SLOTS as i32 / 2 + 4 {
+        let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+        store.save(&settings(n)).unwrap();
+        assert_eq!(load(&mut flash), Some(settings(n)), "save {n}");
+    }
+    //  Each sector is erased once per lap, when the log wraps into it
+    assert_eq!(flash.erases, [2, 2]);
+}
+
+#[test]
+fn sequence_numbers_wrap() {
+    let mut flash = MemFlash::new(SECTORS);
+    for (slot, sequence) in [u32::MAX - 1, u32::MAX, 0, 1].into_iter().enumerate() {
+        write_record(&mut flash, slot, sequence, &settings(slot as i32));
+    }
+
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    assert_eq!(store.load(), Ok(Some(settings(3))));
+    store.save(&settings(4)).unwrap();
+    assert_eq!(sequence(&flash, 4), Some(2));
+    assert_eq!(load(&mut flash), Some(settings(4)));
+}
+
+#[test]
+fn older_records_in_later_slots() {
+    let mut flash = MemFlash::new(SECTORS);
+    write_record(&mut flash, 0, 70, &settings(70));
+    write_record(&mut flash, 1, 71, &settings(71));
+    write_record(&mut flash, SLOTS - 1, 69, &settings(69));
+
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    assert_eq!(store.load(), Ok(Some(settings(71))));
+    store.save(&settings(72)).unwrap();
+    assert_eq!(sequence(&flash, 2), Some(72));
+}
+
+#[test]
+fn unknown_version_is_not_restored() {
+    let mut flash = MemFlash::new(SECTORS);
+    write_record(&mut flash, 0, 4, &settings(1));
+    let mut record = [0xFF; RECORD_SIZE];
+    encode_record(&mut record, 5, &settings(2));
+    record[2] = VERSION + 1;
+    let end = 8 + record[3] as usize;
+    let crc = crc32(&record[..end]);
+    record[end..end + 4].copy_from_slice(&crc.to_le_bytes());
+    flash.data[RECORD_SIZE..2 * RECORD_SIZE].copy_from_slice(&record);
+
+    //  Written by a newer firmware, the settings of an older record would undo the user's changes
+    let mut store = SettingsStore::new(&mut flash, 0, SECTORS as u32);
+    assert_eq!(store.load(), Ok(None));
+    store.save(&settings(3)).unwrap();
+    assert_eq!(sequence(&flash, 2), Some(6));
+    assert_eq!(load(&mut flash), Some(settings(3)));
+}
+
+/// Distinct settings for every `n`.
+fn settings(n: i32) -> Settings {
+    let mut settings = Settings {
+        sample_rate: 44100 + n as u32,
+        mute: [false; CHANNELS],
+        volume: [0; CHANNELS],
+        parameters: [0; MAX_PARAMETERS],
+    };
+    settings.mute[n as usize % CHANNELS] = true;
+    settings.volume[n as usize % CHANNELS] = -256 * n as i16;
+    settings.parameters[n as usize % MAX_PARAMETERS] = -n;
+    settings
+}
+
+/// Settings a freshly booted store finds in `flash`.
+fn load(flash: &mut MemFlash) -> Option<Settings> {
+    SettingsStore::new(flash, 0, SECTORS as u32).load().unwrap()
+}
+
+fn write_record(flash: &mut MemFlash, slot: usize, sequence: u32, settings: &Settings) {
+    let mut record = [0xFF; RECORD_SIZE];
+    encode_record(&mut record, sequence, settings);
+    flash.data[slot * RECORD_SIZE..][..RECORD_SIZE].copy_from_slice(&record);
+}
+
+/// Sequence number of the valid record in `slot`.
+fn sequence(flash: &MemFlash, slot: usize) -> Option<u32> {
+    let record = flash.data[slot * RECORD_SIZE..][..RECORD_SIZE]
+        .try_into()
+        .unwrap();
+    parse_record(record).map(|(sequence, _, _)| sequence)
+}
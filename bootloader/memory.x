@@ -0,0 +1,7 @@
+/* The bootloader and its state partition come first, see `app::flash::layout` */
+MEMORY
+{
+BOOT2   : ORIGIN = 0x10000000, LENGTH = 0x100
+FLASH : ORIGIN = 0x10000100, LENGTH = 24K - 0x100
+  RAM : ORIGIN = 0x20000000, LENGTH = 264K
+}
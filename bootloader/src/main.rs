@@ -0,0 +1,41 @@
+//! Bootloader in front of the firmware.
+//!
+//! After the firmware marked a DFU image updated, the bootloader swaps it with the active
+//! firmware. If the new firmware does not confirm its boot, the next reset swaps the previous
+//! firmware back. The partitions are laid out in `app::flash::layout`.
+#![no_std]
+#![no_main]
+
+use core::cell::RefCell;
+
+use app::flash::{layout, Partition};
+use cortex_m_rt::entry;
+use embassy_boot_rp::{BootLoader, BootLoaderConfig, WatchdogFlash};
+use embassy_time::Duration;
+use {defmt_rtt as _, panic_probe as _};
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_rp::init(Default::default());
+
+    //  A swap that hangs resets into the bootloader, which resumes it from its state partition
+    let flash = RefCell::new(WatchdogFlash::<FLASH_SIZE>::start(
+        p.FLASH,
+        p.WATCHDOG,
+        Duration::from_secs(8),
+    ));
+    let config = BootLoaderConfig {
+        active: Partition::new(&flash, layout::ACTIVE_OFFSET, layout::ACTIVE_SIZE),
+        dfu: Partition::new(&flash, layout::DFU_OFFSET, layout::DFU_SIZE),
+        state: Partition::new(
+            &flash,
+            layout::BOOTLOADER_STATE_OFFSET,
+            layout::BOOTLOADER_STATE_SIZE,
+        ),
+    };
+    let bootloader: BootLoader = BootLoader::prepare(config);
+
+    unsafe { bootloader.load(embassy_rp::flash::FLASH_BASE as u32 + layout::ACTIVE_OFFSET) }
+}
+
+const FLASH_SIZE: usize = layout::FLASH_SIZE as usize;
@@ -7,11 +7,16 @@
 //! Cargo re-run the build script whenever `memory.x` is changed,
 //! updating `memory.x` ensures a rebuild of the application with the
 //! new memory settings.
+//!
+//! It also turns the device profile, `device.toml` or the file named by the
+//! `DEVICE_PROFILE` environment variable, into `profile.rs` in the output
+//! directory with `device-profile`, which checks it against the rules of the
+//! UAC2 class.
 
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
@@ -32,5 +37,19 @@ fn main() {
     println!("cargo:rustc-link-arg-bins=--nmagic");
     println!("cargo:rustc-link-arg-bins=-Tlink.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
-println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+    println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
+
+    generate_profile(out);
+}
+
+const DEFAULT_PROFILE: &str = "device.toml";
+
+fn generate_profile(out: &Path) {
+    println!("cargo:rerun-if-env-changed=DEVICE_PROFILE");
+    let path = env::var("DEVICE_PROFILE").unwrap_or_else(|_| DEFAULT_PROFILE.to_string());
+    println!("cargo:rerun-if-changed={}", path);
+
+    let code = device_profile::generate(&path)
+        .unwrap_or_else(|error| panic!("invalid device profile {}: {}", path, error));
+    fs::write(out.join("profile.rs"), code).unwrap();
 }
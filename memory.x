@@ -1,6 +1,9 @@
+/* The firmware runs from the active partition behind the bootloader, see `app::flash::layout`.
+ * The bootloader shares the first sector and is flashed after the firmware, a DFU image is the
+ * firmware without its second stage boot loader: objcopy -O binary --remove-section .boot2 */
 MEMORY
 {
 BOOT2   : ORIGIN = 0x10000000, LENGTH = 0x100
-FLASH : ORIGIN = 0x10000100, LENGTH = 2048K - 0x100
+FLASH : ORIGIN = 0x10007000, LENGTH = 1004K
   RAM : ORIGIN = 0x20000000, LENGTH = 264K
 }
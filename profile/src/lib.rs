@@ -0,0 +1,643 @@
+//! Device profile of the firmware, `device.toml`, turned into the `profile.rs` the firmware includes.
+//!
+//! The profile is checked with the same rules as the UAC2 class applies at runtime, so a profile exceeding
+//! the full-speed bandwidth or with colliding entity IDs fails the build instead of the device. Both the
+//! firmware and `uac2-sim`, whose descriptor snapshot of the firmware follows `device.toml`, generate their
+//! profile with it from their build scripts.
+
+use std::fmt::Write as _;
+use std::fs;
+
+use toml::{Table, Value};
+
+/// Read the profile at `path`, check it and generate the Rust source of its constants.
+pub fn generate(path: &str) -> Result<String, String> {
+    let text = fs::read_to_string(path).map_err(|error| error.to_string())?;
+    let table: Table = text.parse().map_err(|error| format!("{}", error))?;
+    let profile = Profile::parse(&table)?;
+    profile.validate()?;
+    Ok(profile.generate(path))
+}
+
+struct Profile {
+    vid: u16,
+    pid: u16,
+    manufacturer: String,
+    product: String,
+    serial_number: Option<String>,
+    max_power: u16,
+    sample_rates: Vec<u32>,
+    speaker: Stream,
+    microphone: Stream,
+    extension_units: Vec<ExtensionUnit>,
+    midi: Option<Midi>,
+    dfu: Dfu,
+}
+
+struct Stream {
+    channels: u8,
+    channel_config: u32,
+    channel_names: Vec<String>,
+    terminal_name: Option<String>,
+    terminal_type: Option<u16>,
+    formats: Vec<uac2::Format>,
+    jack_detect: bool,
+}
+
+struct ExtensionUnit {
+    name: String,
+    id: u8,
+    extension_code: u16,
+}
+
+struct Midi {
+    cables: u8,
+    name: Option<String>,
+}
+
+struct Dfu {
+    product: String,
+    device_interface_guid: String,
+}
+
+impl Profile {
+    fn parse(table: &Table) -> Result<Self, String> {
+        let root = Section::new("", table, &["device", "audio", "midi", "dfu"])?;
+
+        let device = root.section(
+            "device",
+            &[
+                "vid",
+                "pid",
+                "manufacturer",
+                "product",
+                "serial_number",
+                "max_power",
+            ],
+        )?;
+        let audio = root.section(
+            "audio",
+            &["sample_rates", "speaker", "microphone", "extension_units"],
+        )?;
+        let dfu = root.section("dfu", &["product", "device_interface_guid"])?;
+
+        let sample_rates: Vec<u32> = audio
+            .array("sample_rates")?
+            .iter()
+            .map(|value| integer("audio.sample_rates", value))
+            .collect::<Result<_, _>>()?;
+
+        let mut extension_units = Vec::new();
+        if audio.table.contains_key("extension_units") {
+            for (i, value) in audio.array("extension_units")?.iter().enumerate() {
+                let name = format!("audio.extension_units[{}]", i);
+                let table = value
+                    .as_table()
+                    .ok_or_else(|| format!("{} must be a table", name))?;
+                let unit = Section::new(&name, table, &["name", "id", "extension_code"])?;
+                extension_units.push(ExtensionUnit {
+                    name: unit.required(unit.string("name"), "name")?,
+                    id: unit.required(unit.integer("id"), "id")?,
+                    extension_code: unit.integer("extension_code")?.unwrap_or(0),
+                });
+            }
+        }
+
+        let midi = match root.optional_section("midi", &["cables", "name"])? {
+            Some(midi) => Some(Midi {
+                cables: midi.integer("cables")?.unwrap_or(1),
+                name: midi.string("name")?,
+            }),
+            None => None,
+        };
+
+        Ok(Self {
+            vid: device.required(device.integer("vid"), "vid")?,
+            pid: device.required(device.integer("pid"), "pid")?,
+            manufacturer: device.required(device.string("manufacturer"), "manufacturer")?,
+            product: device.required(device.string("product"), "product")?,
+            serial_number: device.string("serial_number")?,
+            max_power: device.integer("max_power")?.unwrap_or(100),
+            sample_rates,
+            speaker: Stream::parse(&audio.section("speaker", STREAM_KEYS)?)?,
+            microphone: Stream::parse(&audio.section("microphone", STREAM_KEYS)?)?,
+            extension_units,
+            midi,
+            dfu: Dfu {
+                product: dfu.required(dfu.string("product"), "product")?,
+                device_interface_guid: dfu
+                    .required(dfu.string("device_interface_guid"), "device_interface_guid")?,
+            },
+        })
+    }
+
+    /// Checks of the UAC2 class, followed by the ones of the rest of the device.
+    fn validate(&self) -> Result<(), String> {
+        let speaker_names = self.speaker.channel_names();
+        let microphone_names = self.microphone.channel_names();
+        let speaker = self.speaker.config(&speaker_names);
+        let microphone = self.microphone.config(&microphone_names);
+        let extension_unit_ids: Vec<u8> = self.extension_units.iter().map(|xu| xu.id).collect();
+        let midi = self.midi.as_ref().map(|midi| uac2::MidiConfig {
+            cables: midi.cables,
+            name: midi.name.as_deref(),
+        });
+
+        uac2::try_validate_config(
+            &speaker,
+            &microphone,
+            &self.sample_rates,
+            &extension_unit_ids,
+            midi.as_ref(),
+        )?;
+
+        for (i, xu) in self.extension_units.iter().enumerate() {
+            if !is_identifier(&xu.name) {
+                return Err(format!(
+                    "Extension Unit name {:?} is not a lowercase identifier",
+                    xu.name
+                ));
+            }
+            if self.extension_units[..i]
+                .iter()
+                .any(|other| other.name == xu.name)
+            {
+                return Err(format!("duplicate Extension Unit name {:?}", xu.name));
+            }
+        }
+        if self.max_power > 500 {
+            return Err(format!(
+                "device.max_power = {} exceeds 500 mA",
+                self.max_power
+            ));
+        }
+        if !is_guid(&self.dfu.device_interface_guid) {
+            return Err(format!(
+                "dfu.device_interface_guid {:?} is not a {{XXXXXXXX-XXXX-XXXX-XXXX-XXXXXXXXXXXX}} GUID",
+                self.dfu.device_interface_guid
+            ));
+        }
+        Ok(())
+    }
+
+    fn generate(&self, path: &str) -> String {
+        let mut code = String::new();
+        let _ = self.write(&mut code, path);
+        code
+    }
+
+    fn write(&self, code: &mut String, path: &str) -> std::fmt::Result {
+        writeln!(code, "// Generated by build.rs from {}, do not edit.", path)?;
+        writeln!(code)?;
+        writeln!(code, "pub const VID: u16 = {:#06x};", self.vid)?;
+        writeln!(code, "pub const PID: u16 = {:#06x};", self.pid)?;
+        writeln!(
+            code,
+            "pub const MANUFACTURER: &str = {:?};",
+            self.manufacturer
+        )?;
+        writeln!(code, "pub const PRODUCT: &str = {:?};", self.product)?;
+        writeln!(
+            code,
+            "pub const SERIAL_NUMBER: Option<&str> = {:?};",
+            self.serial_number
+        )?;
+        writeln!(code, "pub const MAX_POWER: u16 = {};", self.max_power)?;
+        writeln!(code)?;
+        writeln!(
+            code,
+            "pub const SAMPLE_RATES: &[u32] = &{:?};",
+            self.sample_rates
+        )?;
+
+        let speaker_names = self.speaker.channel_names();
+        let microphone_names = self.microphone.channel_names();
+        let speaker = self.speaker.config(&speaker_names);
+        let microphone = self.microphone.config(&microphone_names);
+        for (name, stream, config, other) in [
+            ("SPEAKER", &self.speaker, &speaker, &microphone),
+            ("MICROPHONE", &self.microphone, &microphone, &speaker),
+        ] {
+            writeln!(code)?;
+            stream.write(code, name)?;
+            let packet_sizes: Vec<u16> = config
+                .formats
+                .iter()
+                .map(|format| {
+                    uac2::endpoint_packet_size(&self.sample_rates, config, format, other).unwrap()
+                })
+                .collect();
+            writeln!(
+                code,
+                "pub const {}_PACKET_SIZES: [u16; {}] = {:?};",
+                name,
+                packet_sizes.len(),
+                packet_sizes
+            )?;
+            writeln!(
+                code,
+                "pub const {}_MAX_PACKET_SIZE: usize = {};",
+                name,
+                packet_sizes.iter().max().unwrap()
+            )?;
+        }
+
+        writeln!(code)?;
+        for xu in &self.extension_units {
+            let name = xu.name.to_uppercase();
+            writeln!(code, "pub const XU_{}: u8 = {:#04x};", name, xu.id)?;
+            writeln!(
+                code,
+                "pub const XU_{}_EXTENSION_CODE: u16 = {:#06x};",
+                name, xu.extension_code
+            )?;
+        }
+        writeln!(
+            code,
+            "pub const XU_COUNT: usize = {};",
+            self.extension_units.len()
+        )?;
+        writeln!(code)?;
+        //  One handler parameter per unit, so a profile and firmware that disagree on the units fail to build
+        writeln!(code, "/// The Extension Units of the profile, each with the handler of the parameter named like it.")?;
+        let handlers: Vec<String> = self
+            .extension_units
+            .iter()
+            .map(|xu| format!("{}: &'d mut dyn uac2::ExtensionUnitHandler", xu.name))
+            .collect();
+        writeln!(
+            code,
+            "pub fn extension_units<'d>({}) -> [uac2::ExtensionUnit<'d>; XU_COUNT] {{",
+            handlers.join(", ")
+        )?;
+        writeln!(code, "    [")?;
+        for xu in &self.extension_units {
+            let name = xu.name.to_uppercase();
+            writeln!(
+                code,
+                "        uac2::ExtensionUnit {{ id: XU_{}, extension_code: XU_{}_EXTENSION_CODE, handler: {} }},",
+                name, name, xu.name
+            )?;
+        }
+        writeln!(code, "    ]")?;
+        writeln!(code, "}}")?;
+
+        writeln!(code)?;
+        match &self.midi {
+            Some(midi) => writeln!(
+                code,
+                "pub const MIDI: Option<uac2::MidiConfig<'static>> = Some(uac2::MidiConfig {{ cables: {}, name: {:?} }});",
+                midi.cables, midi.name
+            )?,
+            None => writeln!(
+                code,
+                "pub const MIDI: Option<uac2::MidiConfig<'static>> = None;"
+            )?,
+        }
+
+        writeln!(code)?;
+        writeln!(
+            code,
+            "pub const DFU_PRODUCT: &str = {:?};",
+            self.dfu.product
+        )?;
+        writeln!(
+            code,
+            "pub const DFU_DEVICE_INTERFACE_GUID: &str = {:?};",
+            self.dfu.device_interface_guid
+        )
+    }
+}
+
+const STREAM_KEYS: &[&str] = &[
+    "channels",
+    "channel_config",
+    "channel_names",
+    "terminal_name",
+    "terminal_type",
+    "formats",
+    "jack_detect",
+];
+
+impl Stream {
+    fn parse(section: &Section) -> Result<Self, String> {
+        let channel_config = match section.table.get("channel_config") {
+            None => 0,
+            Some(Value::Array(locations)) => {
+                let mut config = 0;
+                for location in locations {
+                    let location = string(&section.key("channel_config"), location)?;
+                    config |= lookup(CHANNEL_LOCATIONS, &location).ok_or_else(|| {
+                        format!(
+                            "{} has an unknown spatial location {:?}",
+                            section.key("channel_config"),
+                            location
+                        )
+                    })?;
+                }
+                config
+            }
+            Some(value) => integer(&section.key("channel_config"), value)?,
+        };
+
+        let terminal_type = match section.table.get("terminal_type") {
+            None => None,
+            Some(Value::String(name)) => Some(lookup(TERMINAL_TYPES, name).ok_or_else(|| {
+                format!(
+                    "{} has an unknown terminal type {:?}",
+                    section.key("terminal_type"),
+                    name
+                )
+            })?),
+            Some(value) => Some(integer(&section.key("terminal_type"), value)?),
+        };
+
+        let formats = match section.table.get("formats") {
+            None => vec![uac2::FORMAT_16_BIT, uac2::FORMAT_24_BIT],
+            Some(_) => section
+                .array("formats")?
+                .iter()
+                .map(|value| format(&section.key("formats"), value))
+                .collect::<Result<_, _>>()?,
+        };
+
+        let channel_names = match section.table.get("channel_names") {
+            None => Vec::new(),
+            Some(_) => section
+                .array("channel_names")?
+                .iter()
+                .map(|value| string(&section.key("channel_names"), value))
+                .collect::<Result<_, _>>()?,
+        };
+
+        Ok(Self {
+            channels: section.integer("channels")?.unwrap_or(1),
+            channel_config,
+            channel_names,
+            terminal_name: section.string("terminal_name")?,
+            terminal_type,
+            formats,
+            jack_detect: section.boolean("jack_detect")?.unwrap_or(false),
+        })
+    }
+
+    fn channel_names(&self) -> Vec<&str> {
+        self.channel_names.iter().map(String::as_str).collect()
+    }
+
+    fn config<'a>(&'a self, channel_names: &'a [&'a str]) -> uac2::StreamConfig<'a> {
+        uac2::StreamConfig {
+            channels: self.channels,
+            channel_config: self.channel_config,
+            channel_names,
+            terminal_name: self.terminal_name.as_deref(),
+            terminal_type: self.terminal_type,
+            formats: &self.formats,
+            jack_detect: self.jack_detect,
+        }
+    }
+
+    fn write(&self, code: &mut String, name: &str) -> std::fmt::Result {
+        writeln!(
+            code,
+            "pub const {}: uac2::StreamConfig<'static> = uac2::StreamConfig {{",
+            name
+        )?;
+        writeln!(code, "    channels: {},", self.channels)?;
+        writeln!(code, "    channel_config: {:#010x},", self.channel_config)?;
+        writeln!(code, "    channel_names: &{:?},", self.channel_names)?;
+        writeln!(code, "    terminal_name: {:?},", self.terminal_name)?;
+        match self.terminal_type {
+            Some(terminal_type) => {
+                writeln!(code, "    terminal_type: Some({:#06x}),", terminal_type)?
+            }
+            None => writeln!(code, "    terminal_type: None,")?,
+        }
+        writeln!(code, "    formats: &[")?;
+        for format in &self.formats {
+            writeln!(
+                code,
+                "        uac2::Format {{ subslot_size: {}, bit_resolution: {} }},",
+                format.subslot_size, format.bit_resolution
+            )?;
+        }
+        writeln!(code, "    ],")?;
+        writeln!(code, "    jack_detect: {},", self.jack_detect)?;
+        writeln!(code, "}};")
+    }
+}
+
+/// A table of the profile, named by its dotted path for error messages.
+struct Section<'a> {
+    name: String,
+    table: &'a Table,
+}
+
+impl<'a> Section<'a> {
+    /// Rejects keys outside of `keys`, a misspelled key would otherwise silently fall back to its default.
+    fn new(name: &str, table: &'a Table, keys: &[&str]) -> Result<Self, String> {
+        let section = Self {
+            name: name.to_string(),
+            table,
+        };
+        if let Some(key) = table.keys().find(|key| !keys.contains(&key.as_str())) {
+            return Err(format!("unknown key {}", section.key(key)));
+        }
+        Ok(section)
+    }
+
+    fn key(&self, key: &str) -> String {
+        if self.name.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", self.name, key)
+        }
+    }
+
+    fn section(&self, key: &str, keys: &[&str]) -> Result<Section<'a>, String> {
+        self.optional_section(key, keys)?
+            .ok_or_else(|| format!("[{}] is missing", self.key(key)))
+    }
+
+    fn optional_section(&self, key: &str, keys: &[&str]) -> Result<Option<Section<'a>>, String> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Table(table)) => Section::new(&self.key(key), table, keys).map(Some),
+            Some(_) => Err(format!("{} must be a table", self.key(key))),
+        }
+    }
+
+    fn required<T>(&self, value: Result<Option<T>, String>, key: &str) -> Result<T, String> {
+        value?.ok_or_else(|| format!("{} is missing", self.key(key)))
+    }
+
+    fn integer<T: TryFrom<i64>>(&self, key: &str) -> Result<Option<T>, String> {
+        self.table
+            .get(key)
+            .map(|value| integer(&self.key(key), value))
+            .transpose()
+    }
+
+    fn string(&self, key: &str) -> Result<Option<String>, String> {
+        self.table
+            .get(key)
+            .map(|value| string(&self.key(key), value))
+            .transpose()
+    }
+
+    fn boolean(&self, key: &str) -> Result<Option<bool>, String> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Boolean(value)) => Ok(Some(*value)),
+            Some(_) => Err(format!("{} must be true or false", self.key(key))),
+        }
+    }
+
+    fn array(&self, key: &str) -> Result<&'a [Value], String> {
+        match self.table.get(key) {
+            None => Err(format!("{} is missing", self.key(key))),
+            Some(Value::Array(values)) => Ok(values),
+            Some(_) => Err(format!("{} must be an array", self.key(key))),
+        }
+    }
+}
+
+fn integer<T: TryFrom<i64>>(key: &str, value: &Value) -> Result<T, String> {
+    match value {
+        Value::Integer(value) => {
+            T::try_from(*value).map_err(|_| format!("{} = {} is out of range", key, value))
+        }
+        _ => Err(format!("{} must be an integer", key)),
+    }
+}
+
+fn string(key: &str, value: &Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value.clone()),
+        _ => Err(format!("{} must be a string", key)),
+    }
+}
+
+/// A bit resolution of 16 or 24, or a `{ subslot_size, bit_resolution }` table.
+fn format(key: &str, value: &Value) -> Result<uac2::Format, String> {
+    match value {
+        Value::Integer(16) => Ok(uac2::FORMAT_16_BIT),
+        Value::Integer(24) => Ok(uac2::FORMAT_24_BIT),
+        Value::Table(table) => {
+            let format = Section::new(key, table, &["subslot_size", "bit_resolution"])?;
+            let subslot_size: u8 =
+                format.required(format.integer("subslot_size"), "subslot_size")?;
+            let bit_resolution: u8 =
+                format.required(format.integer("bit_resolution"), "bit_resolution")?;
+            if !(1..=4).contains(&subslot_size) || bit_resolution > 8 * subslot_size {
+                return Err(format!(
+                    "{} has {} bits in {} byte subslots",
+                    key, bit_resolution, subslot_size
+                ));
+            }
+            Ok(uac2::Format {
+                subslot_size,
+                bit_resolution,
+            })
+        }
+        _ => Err(format!(
+            "{} must be 16, 24 or a {{ subslot_size, bit_resolution }} table",
+            key
+        )),
+    }
+}
+
+fn lookup<T: Copy>(names: &[(&str, T)], name: &str) -> Option<T> {
+    names
+        .iter()
+        .find(|(candidate, _)| *candidate == name)
+        .map(|&(_, value)| value)
+}
+
+fn is_identifier(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+fn is_guid(guid: &str) -> bool {
+    let Some(inner) = guid
+        .strip_prefix('{')
+        .and_then(|guid| guid.strip_suffix('}'))
+    else {
+        return false;
+    };
+    let groups: Vec<&str> = inner.split('-').collect();
+    groups.iter().map(|group| group.len()).eq([8, 4, 4, 4, 12])
+        && groups
+            .iter()
+            .all(|group| group.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+// Spatial locations of `channel_config` (4.1 Audio Channel Cluster Descriptor)
+const CHANNEL_LOCATIONS: &[(&str, u32)] = &[
+    ("front_left", uac2::CHANNEL_FRONT_LEFT),
+    ("front_right", uac2::CHANNEL_FRONT_RIGHT),
+    ("front_center", uac2::CHANNEL_FRONT_CENTER),
+    ("low_frequency_effects", uac2::CHANNEL_LOW_FREQUENCY_EFFECTS),
+    ("back_left", uac2::CHANNEL_BACK_LEFT),
+    ("back_right", uac2::CHANNEL_BACK_RIGHT),
+    ("front_left_of_center", uac2::CHANNEL_FRONT_LEFT_OF_CENTER),
+    ("front_right_of_center", uac2::CHANNEL_FRONT_RIGHT_OF_CENTER),
+    ("back_center", uac2::CHANNEL_BACK_CENTER),
+    ("side_left", uac2::CHANNEL_SIDE_LEFT),
+    ("side_right", uac2::CHANNEL_SIDE_RIGHT),
+    ("top_center", uac2::CHANNEL_TOP_CENTER),
+    ("top_front_left", uac2::CHANNEL_TOP_FRONT_LEFT),
+    ("top_front_center", uac2::CHANNEL_TOP_FRONT_CENTER),
+    ("top_front_right", uac2::CHANNEL_TOP_FRONT_RIGHT),
+    ("top_back_left", uac2::CHANNEL_TOP_BACK_LEFT),
+    ("top_back_center", uac2::CHANNEL_TOP_BACK_CENTER),
+    ("top_back_right", uac2::CHANNEL_TOP_BACK_RIGHT),
+    (
+        "top_front_left_of_center",
+        uac2::CHANNEL_TOP_FRONT_LEFT_OF_CENTER,
+    ),
+    (
+        "top_front_right_of_center",
+        uac2::CHANNEL_TOP_FRONT_RIGHT_OF_CENTER,
+    ),
+    (
+        "left_low_frequency_effects",
+        uac2::CHANNEL_LEFT_LOW_FREQUENCY_EFFECTS,
+    ),
+    (
+        "right_low_frequency_effects",
+        uac2::CHANNEL_RIGHT_LOW_FREQUENCY_EFFECTS,
+    ),
+    ("top_side_left", uac2::CHANNEL_TOP_SIDE_LEFT),
+    ("top_side_right", uac2::CHANNEL_TOP_SIDE_RIGHT),
+    ("bottom_center", uac2::CHANNEL_BOTTOM_CENTER),
+    ("back_left_of_center", uac2::CHANNEL_BACK_LEFT_OF_CENTER),
+    ("back_right_of_center", uac2::CHANNEL_BACK_RIGHT_OF_CENTER),
+    ("raw_data", uac2::CHANNEL_RAW_DATA),
+];
+
+// Physical terminal types of `terminal_type` (Universal Serial Bus Device Class Definition for Terminal Types 2.0)
+const TERMINAL_TYPES: &[(&str, u16)] = &[
+    ("microphone", 0x0201),
+    ("desktop_microphone", 0x0202),
+    ("personal_microphone", 0x0203),
+    ("omnidirectional_microphone", 0x0204),
+    ("microphone_array", 0x0205),
+    ("processing_microphone_array", 0x0206),
+    ("speaker", 0x0301),
+    ("headphones", 0x0302),
+    ("head_mounted_display_audio", 0x0303),
+    ("desktop_speaker", 0x0304),
+    ("room_speaker", 0x0305),
+    ("communication_speaker", 0x0306),
+    ("low_frequency_effects_speaker", 0x0307),
+    ("handset", 0x0401),
+    ("headset", 0x0402),
+    ("speakerphone", 0x0403),
+    ("analog_connector", 0x0601),
+    ("line_connector", 0x0603),
+    ("spdif_interface", 0x0605),
+];
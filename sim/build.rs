@@ -0,0 +1,16 @@
+//! Generates the profile of the firmware from `device.toml`, the descriptor snapshot of the firmware is
+//! built from it.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+const PROFILE: &str = "../device.toml";
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", PROFILE);
+    let code = device_profile::generate(PROFILE)
+        .unwrap_or_else(|error| panic!("invalid device profile {}: {}", PROFILE, error));
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    fs::write(out.join("profile.rs"), code).unwrap();
+}
@@ -0,0 +1,511 @@
+//! Reading the audio function back out of a configuration descriptor, the way a host driver does.
+
+use std::fmt::Write;
+
+/// The descriptors of a configuration descriptor, each one starting with its bLength and bDescriptorType.
+/// Stops at a truncated descriptor.
+pub fn descriptors(configuration: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = configuration;
+    std::iter::from_fn(move || {
+        let length = *rest.first()? as usize;
+        if length < 2 || length > rest.len() {
+            return None;
+        }
+        let (descriptor, tail) = rest.split_at(length);
+        rest = tail;
+        Some(descriptor)
+    })
+}
+
+/// Interfaces and entities of the audio function a host needs to start the streams.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AudioFunction {
+    pub ac_interface: u8,
+    pub clock_source: u8,
+    /// Streaming interfaces in descriptor order
+    pub streaming: Vec<StreamingInterface>,
+}
+
+impl AudioFunction {
+    /// Streaming interface with an OUT endpoint, the speaker.
+    pub fn playback(&self) -> Option<&StreamingInterface> {
+        self.streaming.iter().find(|interface| {
+            interface
+                .alt_settings
+                .first()
+                .is_some_and(|alt| alt.endpoint & 0x80 == 0)
+        })
+    }
+
+    /// Streaming interface with an IN endpoint, the microphone.
+    pub fn capture(&self) -> Option<&StreamingInterface> {
+        self.streaming.iter().find(|interface| {
+            interface
+                .alt_settings
+                .first()
+                .is_some_and(|alt| alt.endpoint & 0x80 != 0)
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct StreamingInterface {
+    pub interface: u8,
+    /// Terminal the interface is connected to
+    pub terminal_link: u8,
+    pub channels: u8,
+    /// Operational alternate settings, the zero bandwidth setting 0 is left out
+    pub alt_settings: Vec<AltSetting>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AltSetting {
+    pub alt_setting: u8,
+    pub subslot_size: u8,
+    pub bit_resolution: u8,
+    pub endpoint: u8,
+    pub max_packet_size: u16,
+}
+
+impl StreamingInterface {
+    /// Alternate setting with `bit_resolution`, or the one with the highest resolution.
+    pub fn alt_setting(&self, bit_resolution: u8) -> Option<&AltSetting> {
+        self.alt_settings
+            .iter()
+            .find(|alt| alt.bit_resolution == bit_resolution)
+            .or_else(|| {
+                self.alt_settings
+                    .iter()
+                    .max_by_key(|alt| alt.bit_resolution)
+            })
+    }
+}
+
+/// The first UAC2 audio function of a configuration descriptor.
+pub fn audio_function(configuration: &[u8]) -> Option<AudioFunction> {
+    let mut function: Option<AudioFunction> = None;
+    //  Interface and alternate setting of the descriptors that follow
+    let mut current: Option<(u8, u8, u8)> = None;
+    let mut alt: Option<AltSetting> = None;
+
+    for descriptor in descriptors(configuration) {
+        match (descriptor[1], descriptor.get(2).copied()) {
+            (DESCRIPTOR_INTERFACE, _) if descriptor.len() >= 9 => {
+                finish_alt_setting(function.as_mut(), current, alt.take());
+                let (number, alt_setting, class, subclass) =
+                    (descriptor[2], descriptor[3], descriptor[5], descriptor[6]);
+                current = (class == AUDIO).then_some((number, alt_setting, subclass));
+                if class == AUDIO && subclass == AUDIOCONTROL && function.is_none() {
+                    function = Some(AudioFunction {
+                        ac_interface: number,
+                        clock_source: 0,
+                        streaming: Vec::new(),
+                    });
+                }
+                if let (Some(function), Some((number, 0, AUDIOSTREAMING))) =
+                    (function.as_mut(), current)
+                {
+                    function.streaming.push(StreamingInterface {
+                        interface: number,
+                        terminal_link: 0,
+                        channels: 0,
+                        alt_settings: Vec::new(),
+                    });
+                }
+                if let Some((_, alt_setting, AUDIOSTREAMING)) = current {
+                    if alt_setting != 0 {
+                        alt = Some(AltSetting {
+                            alt_setting,
+                            subslot_size: 0,
+                            bit_resolution: 0,
+                            endpoint: 0,
+                            max_packet_size: 0,
+                        });
+                    }
+                }
+            }
+            (CS_INTERFACE, Some(CLOCK_SOURCE)) if descriptor.len() >= 4 => {
+                if let (Some(function), Some((_, _, AUDIOCONTROL))) = (function.as_mut(), current) {
+                    if function.clock_source == 0 {
+                        function.clock_source = descriptor[3];
+                    }
+                }
+            }
+            (CS_INTERFACE, Some(AS_GENERAL)) if descriptor.len() >= 11 => {
+                if let Some(interface) = function
+                    .as_mut()
+                    .and_then(|function| function.streaming.last_mut())
+                {
+                    interface.terminal_link = descriptor[3];
+                    interface.channels = descriptor[10];
+                }
+            }
+            (CS_INTERFACE, Some(FORMAT_TYPE)) if descriptor.len() >= 6 => {
+                if let Some(alt) = alt.as_mut() {
+                    alt.subslot_size = descriptor[4];
+                    alt.bit_resolution = descriptor[5];
+                }
+            }
+            (DESCRIPTOR_ENDPOINT, _) if descriptor.len() >= 7 => {
+                //  The explicit feedback endpoint of an asynchronous OUT stream is not the data endpoint
+                if let Some(alt) = alt.as_mut().filter(|alt| alt.endpoint == 0) {
+                    alt.endpoint = descriptor[2];
+                    alt.max_packet_size = u16::from_le_bytes([descriptor[4], descriptor[5]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    finish_alt_setting(function.as_mut(), current, alt);
+    function
+}
+
+fn finish_alt_setting(
+    function: Option<&mut AudioFunction>,
+    current: Option<(u8, u8, u8)>,
+    alt: Option<AltSetting>,
+) {
+    let (Some(function), Some((number, _, AUDIOSTREAMING)), Some(alt)) = (function, current, alt)
+    else {
+        return;
+    };
+    if let Some(interface) = function
+        .streaming
+        .iter_mut()
+        .find(|interface| interface.interface == number)
+    {
+        interface.alt_settings.push(alt);
+    }
+}
+
+/// The descriptors of a configuration descriptor as a tree with one line per descriptor, every field named
+/// the way the specifications do. Descriptors the decoder does not know are shown as hex bytes.
+pub fn describe(configuration: &[u8]) -> String {
+    let mut out = String::new();
+    //  Class and subclass of the interface the descriptors that follow belong to
+    let mut interface = (0, 0);
+    let mut consumed = 0;
+    for descriptor in descriptors(configuration) {
+        consumed += descriptor.len();
+        let (depth, decoded) = match descriptor[1] {
+            DESCRIPTOR_CONFIGURATION => (0, configuration_descriptor(descriptor)),
+            DESCRIPTOR_INTERFACE_ASSOCIATION => (1, interface_association(descriptor)),
+            DESCRIPTOR_INTERFACE => {
+                interface = (
+                    descriptor.get(5).copied().unwrap_or(0),
+                    descriptor.get(6).copied().unwrap_or(0),
+                );
+                (1, interface_descriptor(descriptor))
+            }
+            DESCRIPTOR_ENDPOINT => (2, endpoint_descriptor(descriptor)),
+            CS_INTERFACE => (2, class_interface(interface, descriptor)),
+            CS_ENDPOINT => (3, class_endpoint(interface, descriptor)),
+            _ => (2, None),
+        };
+        let line = match decoded {
+            Some((name, fields)) => format!("{name}: {fields}"),
+            None => format!("Descriptor {:#04x}: {}", descriptor[1], hex(descriptor)),
+        };
+        writeln!(out, "{}{line}", "  ".repeat(depth)).unwrap();
+    }
+    if consumed < configuration.len() {
+        writeln!(out, "Trailing bytes: {}", hex(&configuration[consumed..])).unwrap();
+    }
+    out
+}
+
+type Decoded = Option<(&'static str, String)>;
+
+fn configuration_descriptor(descriptor: &[u8]) -> Decoded {
+    let mut fields = Fields::new(descriptor);
+    fields.dec16("wTotalLength")?;
+    fields.dec("bNumInterfaces")?;
+    fields.dec("bConfigurationValue")?;
+    fields.dec("iConfiguration")?;
+    fields.hex("bmAttributes")?;
+    fields.dec("bMaxPower")?;
+    fields.finish("Configuration")
+}
+
+fn interface_association(descriptor: &[u8]) -> Decoded {
+    let mut fields = Fields::new(descriptor);
+    fields.dec("bFirstInterface")?;
+    fields.dec("bInterfaceCount")?;
+    fields.hex("bFunctionClass")?;
+    fields.hex("bFunctionSubClass")?;
+    fields.hex("bFunctionProtocol")?;
+    fields.dec("iFunction")?;
+    fields.finish("Interface Association")
+}
+
+fn interface_descriptor(descriptor: &[u8]) -> Decoded {
+    let mut fields = Fields::new(descriptor);
+    fields.dec("bInterfaceNumber")?;
+    fields.dec("bAlternateSetting")?;
+    fields.dec("bNumEndpoints")?;
+    fields.hex("bInterfaceClass")?;
+    fields.hex("bInterfaceSubClass")?;
+    fields.hex("bInterfaceProtocol")?;
+    fields.dec("iInterface")?;
+    fields.finish("Interface")
+}
+
+fn endpoint_descriptor(descriptor: &[u8]) -> Decoded {
+    let mut fields = Fields::new(descriptor);
+    fields.hex("bEndpointAddress")?;
+    fields.hex("bmAttributes")?;
+    fields.dec16("wMaxPacketSize")?;
+    fields.dec("bInterval")?;
+    //  Audio class 1.0 endpoints, used by MIDIStreaming
+    if descriptor.len() == 9 {
+        fields.dec("bRefresh")?;
+        fields.hex("bSynchAddress")?;
+    }
+    fields.finish("Endpoint")
+}
+
+/// Class-specific interface descriptors of the AudioControl, AudioStreaming and MIDIStreaming interfaces.
+fn class_interface((class, subclass): (u8, u8), descriptor: &[u8]) -> Decoded {
+    if class != AUDIO {
+        return None;
+    }
+    let mut fields = Fields::new(descriptor);
+    fields.subtype()?;
+    let name = match (subclass, descriptor[2]) {
+        (AUDIOCONTROL, AC_HEADER) => {
+            fields.bcd("bcdADC")?;
+            fields.hex("bCategory")?;
+            fields.dec16("wTotalLength")?;
+            fields.hex("bmControls")?;
+            "AC Header"
+        }
+        (AUDIOCONTROL, INPUT_TERMINAL) => {
+            fields.dec("bTerminalID")?;
+            fields.hex16("wTerminalType")?;
+            fields.dec("bAssocTerminal")?;
+            fields.dec("bCSourceID")?;
+            fields.dec("bNrChannels")?;
+            fields.hex32("bmChannelConfig")?;
+            fields.dec("iChannelNames")?;
+            fields.hex16("bmControls")?;
+            fields.dec("iTerminal")?;
+            "Input Terminal"
+        }
+        (AUDIOCONTROL, OUTPUT_TERMINAL) => {
+            fields.dec("bTerminalID")?;
+            fields.hex16("wTerminalType")?;
+            fields.dec("bAssocTerminal")?;
+            fields.dec("bSourceID")?;
+            fields.dec("bCSourceID")?;
+            fields.hex16("bmControls")?;
+            fields.dec("iTerminal")?;
+            "Output Terminal"
+        }
+        (AUDIOCONTROL, FEATURE_UNIT) => {
+            fields.dec("bUnitID")?;
+            fields.dec("bSourceID")?;
+            let channels = descriptor.len().checked_sub(6)? / 4;
+            for channel in 0..channels {
+                fields.hex32(&format!("bmaControls({channel})"))?;
+            }
+            fields.dec("iFeature")?;
+            "Feature Unit"
+        }
+        (AUDIOCONTROL, EXTENSION_UNIT) => {
+            fields.dec("bUnitID")?;
+            fields.hex16("wExtensionCode")?;
+            let pins = fields.dec("bNrInPins")?;
+            for pin in 1..=pins {
+                fields.dec(&format!("baSourceID({pin})"))?;
+            }
+            fields.dec("bNrChannels")?;
+            fields.hex32("bmChannelConfig")?;
+            fields.dec("iChannelNames")?;
+            fields.hex("bmControls")?;
+            fields.dec("iExtension")?;
+            "Extension Unit"
+        }
+        (AUDIOCONTROL, CLOCK_SOURCE) => {
+            fields.dec("bClockID")?;
+            fields.hex("bmAttributes")?;
+            fields.hex("bmControls")?;
+            fields.dec("bAssocTerminal")?;
+            fields.dec("iClockSource")?;
+            "Clock Source"
+        }
+        (AUDIOSTREAMING, AS_GENERAL) => {
+            fields.dec("bTerminalLink")?;
+            fields.hex("bmControls")?;
+            fields.dec("bFormatType")?;
+            fields.hex32("bmFormats")?;
+            fields.dec("bNrChannels")?;
+            fields.hex32("bmChannelConfig")?;
+            fields.dec("iChannelNames")?;
+            "AS General"
+        }
+        (AUDIOSTREAMING, FORMAT_TYPE) => {
+            fields.dec("bFormatType")?;
+            fields.dec("bSubslotSize")?;
+            fields.dec("bBitResolution")?;
+            "Format Type"
+        }
+        (MIDISTREAMING, MS_HEADER) => {
+            fields.bcd("bcdMSC")?;
+            fields.dec16("wTotalLength")?;
+            "MS Header"
+        }
+        (MIDISTREAMING, MIDI_IN_JACK) => {
+            fields.dec("bJackType")?;
+            fields.dec("bJackID")?;
+            fields.dec("iJack")?;
+            "MIDI IN Jack"
+        }
+        (MIDISTREAMING, MIDI_OUT_JACK) => {
+            fields.dec("bJackType")?;
+            fields.dec("bJackID")?;
+            let pins = fields.dec("bNrInputPins")?;
+            for pin in 1..=pins {
+                fields.dec(&format!("baSourceID({pin})"))?;
+                fields.dec(&format!("baSourcePin({pin})"))?;
+            }
+            fields.dec("iJack")?;
+            "MIDI OUT Jack"
+        }
+        _ => return None,
+    };
+    fields.finish(name)
+}
+
+/// Class-specific endpoint descriptors of the AudioStreaming and MIDIStreaming interfaces.
+fn class_endpoint((class, subclass): (u8, u8), descriptor: &[u8]) -> Decoded {
+    if class != AUDIO {
+        return None;
+    }
+    let mut fields = Fields::new(descriptor);
+    fields.subtype()?;
+    let name = match (subclass, descriptor[2]) {
+        (AUDIOSTREAMING, EP_GENERAL) => {
+            fields.hex("bmAttributes")?;
+            fields.hex("bmControls")?;
+            fields.dec("bLockDelayUnits")?;
+            fields.dec16("wLockDelay")?;
+            "AS Isochronous Endpoint"
+        }
+        (MIDISTREAMING, MS_GENERAL) => {
+            let jacks = fields.dec("bNumEmbMIDIJack")?;
+            for jack in 1..=jacks {
+                fields.dec(&format!("baAssocJackID({jack})"))?;
+            }
+            "MS Bulk Endpoint"
+        }
+        _ => return None,
+    };
+    fields.finish(name)
+}
+
+/// Named fields of a descriptor, read in order after bLength and bDescriptorType.
+/// Every read fails once the descriptor is exhausted, so a descriptor with a different layout falls back to hex.
+struct Fields<'a> {
+    rest: &'a [u8],
+    fields: Vec<String>,
+}
+
+impl<'a> Fields<'a> {
+    fn new(descriptor: &'a [u8]) -> Self {
+        Self {
+            rest: &descriptor[2..],
+            fields: Vec::new(),
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let bytes = self.rest.get(..N)?.try_into().ok()?;
+        self.rest = &self.rest[N..];
+        Some(bytes)
+    }
+
+    /// The descriptor subtype, part of the name of the descriptor.
+    fn subtype(&mut self) -> Option<u8> {
+        self.take::<1>().map(|[subtype]| subtype)
+    }
+
+    fn dec(&mut self, name: &str) -> Option<u8> {
+        let [value] = self.take()?;
+        self.fields.push(format!("{name} {value}"));
+        Some(value)
+    }
+
+    fn hex(&mut self, name: &str) -> Option<u8> {
+        let [value] = self.take()?;
+        self.fields.push(format!("{name} {value:#04x}"));
+        Some(value)
+    }
+
+    fn dec16(&mut self, name: &str) -> Option<u16> {
+        let value = u16::from_le_bytes(self.take()?);
+        self.fields.push(format!("{name} {value}"));
+        Some(value)
+    }
+
+    fn hex16(&mut self, name: &str) -> Option<u16> {
+        let value = u16::from_le_bytes(self.take()?);
+        self.fields.push(format!("{name} {value:#06x}"));
+        Some(value)
+    }
+
+    fn hex32(&mut self, name: &str) -> Option<u32> {
+        let value = u32::from_le_bytes(self.take()?);
+        self.fields.push(format!("{name} {value:#010x}"));
+        Some(value)
+    }
+
+    /// Binary-coded decimal release number, 0x0200 is 2.00.
+    fn bcd(&mut self, name: &str) -> Option<u16> {
+        let value = u16::from_le_bytes(self.take()?);
+        self.fields
+            .push(format!("{name} {:x}.{:02x}", value >> 8, value & 0xFF));
+        Some(value)
+    }
+
+    /// The decoded descriptor, `None` if bytes are left over.
+    fn finish(self, name: &'static str) -> Decoded {
+        self.rest.is_empty().then(|| (name, self.fields.join(", ")))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+const DESCRIPTOR_CONFIGURATION: u8 = 0x02;
+const DESCRIPTOR_INTERFACE: u8 = 0x04;
+const DESCRIPTOR_ENDPOINT: u8 = 0x05;
+const DESCRIPTOR_INTERFACE_ASSOCIATION: u8 = 0x0B;
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+
+const AUDIO: u8 = 0x01;
+const AUDIOCONTROL: u8 = 0x01;
+const AUDIOSTREAMING: u8 = 0x02;
+const MIDISTREAMING: u8 = 0x03;
+
+//  AudioControl descriptor subtypes
+const AC_HEADER: u8 = 0x01;
+const INPUT_TERMINAL: u8 = 0x02;
+const OUTPUT_TERMINAL: u8 = 0x03;
+const FEATURE_UNIT: u8 = 0x06;
+const EXTENSION_UNIT: u8 = 0x09;
+const CLOCK_SOURCE: u8 = 0x0A;
+//  AudioStreaming descriptor subtypes
+const AS_GENERAL: u8 = 0x01;
+const FORMAT_TYPE: u8 = 0x02;
+const EP_GENERAL: u8 = 0x01;
+//  MIDIStreaming descriptor subtypes
+const MS_HEADER: u8 = 0x01;
+const MIDI_IN_JACK: u8 = 0x02;
+const MIDI_OUT_JACK: u8 = 0x03;
+const MS_GENERAL: u8 = 0x01;
@@ -0,0 +1,401 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::rc::Rc;
+use std::task::{Poll, Waker};
+
+use embassy_usb::driver::{
+    Bus, ControlPipe, Direction, Driver, Endpoint, EndpointAddress, EndpointAllocError,
+    EndpointError, EndpointIn, EndpointInfo, EndpointOut, EndpointType, Event, Unsupported,
+};
+
+use crate::host::Host;
+
+/// Endpoints per direction, endpoint 0 included.
+const MAX_ENDPOINTS: usize = 16;
+
+/// Packets an IN endpoint holds until the host collects them, one per frame like a double-buffered peripheral.
+const IN_QUEUE_DEPTH: usize = 1;
+
+/// State of the bus shared between the device side and [`Host`].
+#[derive(Default)]
+pub(crate) struct Shared {
+    pub(crate) control: ControlState,
+    pub(crate) endpoints: Vec<EndpointState>,
+    pub(crate) events: VecDeque<Event>,
+    pub(crate) address: u8,
+    wakers: Vec<Waker>,
+}
+
+impl Shared {
+    /// Wake every future waiting on the bus, each one checks its own condition again.
+    pub(crate) fn wake(&mut self) {
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+    }
+
+    pub(crate) fn register(&mut self, waker: &Waker) {
+        if !self
+            .wakers
+            .iter()
+            .any(|registered| registered.will_wake(waker))
+        {
+            self.wakers.push(waker.clone());
+        }
+    }
+
+    pub(crate) fn endpoint(&mut self, addr: EndpointAddress) -> Option<&mut EndpointState> {
+        self.endpoints
+            .iter_mut()
+            .find(|endpoint| endpoint.info.addr == addr)
+    }
+}
+
+/// Control transfer in flight, set up by the host and completed by the device.
+#[derive(Default)]
+pub(crate) struct ControlState {
+    pub(crate) setup: Option<[u8; 8]>,
+    /// Data stage of an OUT transfer not yet read by the device
+    pub(crate) data_out: VecDeque<u8>,
+    /// Data stage of an IN transfer written by the device
+    pub(crate) data_in: Vec<u8>,
+    /// `Some(true)` once the device accepted the transfer, `Some(false)` if it stalled
+    pub(crate) status: Option<bool>,
+}
+
+pub(crate) struct EndpointState {
+    pub(crate) info: EndpointInfo,
+    pub(crate) enabled: bool,
+    pub(crate) stalled: bool,
+    /// Packets from the host for OUT endpoints, packets for the host for IN endpoints
+    pub(crate) packets: VecDeque<Vec<u8>>,
+}
+
+/// In-memory driver for the device side, see [`MockDriver::new`].
+pub struct MockDriver {
+    shared: Rc<RefCell<Shared>>,
+    next_out: usize,
+    next_in: usize,
+}
+
+impl MockDriver {
+    /// A driver and the host connected to it.
+    pub fn new() -> (Self, Host) {
+        let shared = Rc::new(RefCell::new(Shared::default()));
+        let driver = Self {
+            shared: shared.clone(),
+            next_out: 1,
+            next_in: 1,
+        };
+        (driver, Host::new(shared))
+    }
+
+    fn alloc(
+        &mut self,
+        direction: Direction,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<EndpointInfo, EndpointAllocError> {
+        let next = match direction {
+            Direction::Out => &mut self.next_out,
+            Direction::In => &mut self.next_in,
+        };
+        if *next >= MAX_ENDPOINTS {
+            return Err(EndpointAllocError);
+        }
+        let info = EndpointInfo {
+            addr: EndpointAddress::from_parts(*next, direction),
+            ep_type,
+            max_packet_size,
+            interval_ms,
+        };
+        *next += 1;
+        self.shared.borrow_mut().endpoints.push(EndpointState {
+            info,
+            enabled: false,
+            stalled: false,
+            packets: VecDeque::new(),
+        });
+        Ok(info)
+    }
+}
+
+impl<'a> Driver<'a> for MockDriver {
+    type EndpointOut = MockEndpointOut;
+    type EndpointIn = MockEndpointIn;
+    type ControlPipe = MockControlPipe;
+    type Bus = MockBus;
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<MockEndpointOut, EndpointAllocError> {
+        let info = self.alloc(Direction::Out, ep_type, max_packet_size, interval_ms)?;
+        Ok(MockEndpointOut {
+            shared: self.shared.clone(),
+            info,
+        })
+    }
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<MockEndpointIn, EndpointAllocError> {
+        let info = self.alloc(Direction::In, ep_type, max_packet_size, interval_ms)?;
+        Ok(MockEndpointIn {
+            shared: self.shared.clone(),
+            info,
+        })
+    }
+
+    fn start(self, control_max_packet_size: u16) -> (MockBus, MockControlPipe) {
+        (
+            MockBus {
+                shared: self.shared.clone(),
+            },
+            MockControlPipe {
+                shared: self.shared,
+                max_packet_size: control_max_packet_size as usize,
+            },
+        )
+    }
+}
+
+pub struct MockBus {
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl Bus for MockBus {
+    async fn enable(&mut self) {}
+
+    async fn disable(&mut self) {}
+
+    async fn poll(&mut self) -> Event {
+        poll_fn(|cx| {
+            let mut shared = self.shared.borrow_mut();
+            match shared.events.pop_front() {
+                Some(event) => Poll::Ready(event),
+                None => {
+                    shared.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
+        let mut shared = self.shared.borrow_mut();
+        if let Some(endpoint) = shared.endpoint(ep_addr) {
+            endpoint.enabled = enabled;
+            if !enabled {
+                endpoint.packets.clear();
+            }
+        }
+        shared.wake();
+    }
+
+    fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
+        if let Some(endpoint) = self.shared.borrow_mut().endpoint(ep_addr) {
+            endpoint.stalled = stalled;
+        }
+    }
+
+    fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
+        self.shared
+            .borrow_mut()
+            .endpoint(ep_addr)
+            .is_some_and(|endpoint| endpoint.stalled)
+    }
+
+    async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+        Err(Unsupported)
+    }
+}
+
+pub struct MockControlPipe {
+    shared: Rc<RefCell<Shared>>,
+    max_packet_size: usize,
+}
+
+impl MockControlPipe {
+    fn complete(&mut self, accepted: bool) {
+        let mut shared = self.shared.borrow_mut();
+        shared.control.status = Some(accepted);
+        shared.wake();
+    }
+}
+
+impl ControlPipe for MockControlPipe {
+    fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        poll_fn(|cx| {
+            let mut shared = self.shared.borrow_mut();
+            match shared.control.setup.take() {
+                Some(setup) => Poll::Ready(setup),
+                None => {
+                    shared.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    async fn data_out(
+        &mut self,
+        buf: &mut [u8],
+        _first: bool,
+        _last: bool,
+    ) -> Result<usize, EndpointError> {
+        let mut shared = self.shared.borrow_mut();
+        let data = &mut shared.control.data_out;
+        let n = buf.len().min(self.max_packet_size).min(data.len());
+        for (byte, data) in buf.iter_mut().zip(data.drain(..n)) {
+            *byte = data;
+        }
+        Ok(n)
+    }
+
+    async fn data_in(
+        &mut self,
+        data: &[u8],
+        _first: bool,
+        last: bool,
+    ) -> Result<(), EndpointError> {
+        if data.len() > self.max_packet_size {
+            return Err(EndpointError::BufferOverflow);
+        }
+        self.shared
+            .borrow_mut()
+            .control
+            .data_in
+            .extend_from_slice(data);
+        if last {
+            self.complete(true);
+        }
+        Ok(())
+    }
+
+    async fn accept(&mut self) {
+        self.complete(true);
+    }
+
+    async fn reject(&mut self) {
+        self.complete(false);
+    }
+
+    async fn accept_set_address(&mut self, addr: u8) {
+        self.shared.borrow_mut().address = addr;
+        self.complete(true);
+    }
+}
+
+pub struct MockEndpointOut {
+    shared: Rc<RefCell<Shared>>,
+    info: EndpointInfo,
+}
+
+impl Endpoint for MockEndpointOut {
+    fn info(&self) -> &EndpointInfo {
+        &self.info
+    }
+
+    async fn wait_enabled(&mut self) {
+        wait_enabled(&self.shared, self.info.addr).await
+    }
+}
+
+impl EndpointOut for MockEndpointOut {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        poll_fn(|cx| {
+            let mut shared = self.shared.borrow_mut();
+            let Some(endpoint) = shared.endpoint(self.info.addr) else {
+                return Poll::Ready(Err(EndpointError::Disabled));
+            };
+            if !endpoint.enabled {
+                return Poll::Ready(Err(EndpointError::Disabled));
+            }
+            match endpoint.packets.pop_front() {
+                Some(packet) if packet.len() > buf.len() => {
+                    Poll::Ready(Err(EndpointError::BufferOverflow))
+                }
+                Some(packet) => {
+                    buf[..packet.len()].copy_from_slice(&packet);
+                    Poll::Ready(Ok(packet.len()))
+                }
+                None => {
+                    shared.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+pub struct MockEndpointIn {
+    shared: Rc<RefCell<Shared>>,
+    info: EndpointInfo,
+}
+
+impl Endpoint for MockEndpointIn {
+    fn info(&self) -> &EndpointInfo {
+        &self.info
+    }
+
+    async fn wait_enabled(&mut self) {
+        wait_enabled(&self.shared, self.info.addr).await
+    }
+}
+
+impl EndpointIn for MockEndpointIn {
+    async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+        if buf.len() > self.info.max_packet_size as usize {
+            return Err(EndpointError::BufferOverflow);
+        }
+        poll_fn(|cx| {
+            let mut shared = self.shared.borrow_mut();
+            let Some(endpoint) = shared.endpoint(self.info.addr) else {
+                return Poll::Ready(Err(EndpointError::Disabled));
+            };
+            if !endpoint.enabled {
+                return Poll::Ready(Err(EndpointError::Disabled));
+            }
+            if endpoint.packets.len() < IN_QUEUE_DEPTH {
+                endpoint.packets.push_back(buf.to_vec());
+                shared.wake();
+                Poll::Ready(Ok(()))
+            } else {
+                shared.register(cx.waker());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+async fn wait_enabled(shared: &RefCell<Shared>, addr: EndpointAddress) {
+    poll_fn(|cx| {
+        let mut shared = shared.borrow_mut();
+        if shared
+            .endpoint(addr)
+            .is_some_and(|endpoint| endpoint.enabled)
+        {
+            Poll::Ready(())
+        } else {
+            shared.register(cx.waker());
+            Poll::Pending
+        }
+    })
+    .await
+}
@@ -0,0 +1,198 @@
+use std::cell::RefCell;
+use std::future::poll_fn;
+use std::rc::Rc;
+use std::task::Poll;
+
+use embassy_usb::driver::{Direction, EndpointAddress, Event};
+
+use crate::driver::{ControlState, Shared};
+
+/// The device stalled a control request.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Stall;
+
+/// USB host connected to a [`MockDriver`](crate::MockDriver).
+///
+/// Control transfers and IN packets wait for the device, so the device future has to run concurrently.
+pub struct Host {
+    shared: Rc<RefCell<Shared>>,
+}
+
+impl Host {
+    pub(crate) fn new(shared: Rc<RefCell<Shared>>) -> Self {
+        Self { shared }
+    }
+
+    /// Power the bus and reset the device, the device starts in the Default state.
+    pub fn connect(&self) {
+        let mut shared = self.shared.borrow_mut();
+        shared.events.push_back(Event::PowerDetected);
+        shared.events.push_back(Event::Reset);
+        shared.wake();
+    }
+
+    /// Enumerate the device at `address` and select configuration 1. Returns the configuration descriptor.
+    pub async fn enumerate(&self, address: u8) -> Result<Vec<u8>, Stall> {
+        self.get_descriptor(DESCRIPTOR_DEVICE, 0, 18).await?;
+        self.control_out(
+            REQUEST_TYPE_STANDARD_DEVICE,
+            SET_ADDRESS,
+            address as u16,
+            0,
+            &[],
+        )
+        .await?;
+        let header = self.get_descriptor(DESCRIPTOR_CONFIGURATION, 0, 9).await?;
+        let total_length = u16::from_le_bytes([header[2], header[3]]);
+        let configuration = self
+            .get_descriptor(DESCRIPTOR_CONFIGURATION, 0, total_length)
+            .await?;
+        self.control_out(REQUEST_TYPE_STANDARD_DEVICE, SET_CONFIGURATION, 1, 0, &[])
+            .await?;
+        Ok(configuration)
+    }
+
+    /// Address assigned with SET_ADDRESS.
+    pub fn address(&self) -> u8 {
+        self.shared.borrow().address
+    }
+
+    pub async fn get_descriptor(
+        &self,
+        descriptor_type: u8,
+        index: u8,
+        length: u16,
+    ) -> Result<Vec<u8>, Stall> {
+        self.control_in(
+            REQUEST_TYPE_STANDARD_DEVICE | REQUEST_TYPE_IN,
+            GET_DESCRIPTOR,
+            (descriptor_type as u16) << 8 | index as u16,
+            0,
+            length,
+        )
+        .await
+    }
+
+    pub async fn set_interface(&self, interface: u8, alt_setting: u8) -> Result<(), Stall> {
+        self.control_out(
+            REQUEST_TYPE_STANDARD_INTERFACE,
+            SET_INTERFACE,
+            alt_setting as u16,
+            interface as u16,
+            &[],
+        )
+        .await
+    }
+
+    /// Control transfer with a device-to-host data stage of `length` bytes at most.
+    /// The data is returned as the device sent it, a device exceeding `length` is not cut off.
+    pub async fn control_in(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> Result<Vec<u8>, Stall> {
+        let setup = setup_packet(request_type, request, value, index, length);
+        self.control(setup, &[]).await
+    }
+
+    /// Control transfer with a host-to-device data stage of `data`, none if it is empty.
+    pub async fn control_out(
+        &self,
+        request_type: u8,
+        request: u8,
+        value: u16,
+        index: u16,
+        data: &[u8],
+    ) -> Result<(), Stall> {
+        let setup = setup_packet(request_type, request, value, index, data.len() as u16);
+        self.control(setup, data).await.map(|_| ())
+    }
+
+    async fn control(&self, setup: [u8; 8], data: &[u8]) -> Result<Vec<u8>, Stall> {
+        {
+            let mut shared = self.shared.borrow_mut();
+            shared.control = ControlState {
+                setup: Some(setup),
+                data_out: data.iter().copied().collect(),
+                ..ControlState::default()
+            };
+            shared.wake();
+        }
+        poll_fn(|cx| {
+            let mut shared = self.shared.borrow_mut();
+            match shared.control.status {
+                Some(true) => Poll::Ready(Ok(std::mem::take(&mut shared.control.data_in))),
+                Some(false) => Poll::Ready(Err(Stall)),
+                None => {
+                    shared.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Queue a packet for the OUT endpoint `endpoint`, dropped like on the bus if the endpoint is disabled.
+    pub fn send(&self, endpoint: u8, packet: &[u8]) {
+        let mut shared = self.shared.borrow_mut();
+        let addr = EndpointAddress::from_parts(endpoint as usize & 0x0F, Direction::Out);
+        if let Some(endpoint) = shared.endpoint(addr).filter(|endpoint| endpoint.enabled) {
+            endpoint.packets.push_back(packet.to_vec());
+        }
+        shared.wake();
+    }
+
+    /// Next packet of the IN endpoint `endpoint`, `None` if the endpoint is disabled.
+    pub async fn receive(&self, endpoint: u8) -> Option<Vec<u8>> {
+        let addr = EndpointAddress::from_parts(endpoint as usize & 0x0F, Direction::In);
+        poll_fn(|cx| {
+            let mut shared = self.shared.borrow_mut();
+            let endpoint = match shared.endpoint(addr) {
+                Some(endpoint) if endpoint.enabled => endpoint,
+                _ => return Poll::Ready(None),
+            };
+            match endpoint.packets.pop_front() {
+                Some(packet) => {
+                    shared.wake();
+                    Poll::Ready(Some(packet))
+                }
+                None => {
+                    shared.register(cx.waker());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+}
+
+fn setup_packet(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> [u8; 8] {
+    let [value_low, value_high] = value.to_le_bytes();
+    let [index_low, index_high] = index.to_le_bytes();
+    let [length_low, length_high] = length.to_le_bytes();
+    [
+        request_type,
+        request,
+        value_low,
+        value_high,
+        index_low,
+        index_high,
+        length_low,
+        length_high,
+    ]
+}
+
+const REQUEST_TYPE_IN: u8 = 0x80;
+const REQUEST_TYPE_STANDARD_DEVICE: u8 = 0x00;
+const REQUEST_TYPE_STANDARD_INTERFACE: u8 = 0x01;
+
+const GET_DESCRIPTOR: u8 = 0x06;
+const SET_ADDRESS: u8 = 0x05;
+const SET_CONFIGURATION: u8 = 0x09;
+const SET_INTERFACE: u8 = 0x0B;
+
+const DESCRIPTOR_DEVICE: u8 = 0x01;
+const DESCRIPTOR_CONFIGURATION: u8 = 0x02;
@@ -0,0 +1,17 @@
+//! Host side of the UAC2 class for regression testing without hardware.
+//!
+//! [`MockDriver`] is an in-memory embassy-usb driver, [`Host`] plays the USB host on the other end:
+//! it enumerates the device, sends control requests and moves the packets of the streaming endpoints.
+//! Device and host run as two futures on one thread, so a run is deterministic.
+//!
+//! The crate targets the host, `cargo run -p uac2-sim --target host-tuple` runs the simulator.
+
+mod descriptor;
+mod driver;
+mod host;
+
+pub use descriptor::{
+    audio_function, describe, descriptors, AltSetting, AudioFunction, StreamingInterface,
+};
+pub use driver::{MockBus, MockControlPipe, MockDriver, MockEndpointIn, MockEndpointOut};
+pub use host::{Host, Stall};
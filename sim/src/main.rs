@@ -0,0 +1,375 @@
+//! Stream a WAV file through the speaker of the UAC2 class and record the microphone into another WAV.
+//!
+//! Usage: `uac2-sim [--tail MS] [--generator SIGNAL] [--level DB] INPUT.wav OUTPUT.wav`
+//!
+//! The host enumerates the device, sets the sample rate of the input file and selects the alternate
+//! settings closest to its bit depth, then sends one speaker packet and collects one microphone packet
+//! per 1 ms frame. The device runs the speaker into a [`uac2::Loopback`] that plays it back mixed down
+//! to mono on every microphone channel, `--tail` keeps recording after the input ended (100 ms by default).
+//!
+//! With `--generator` the microphone records a [`uac2::Generator`] instead, at `--level` dBFS (-20 by
+//! default). SIGNAL is `silence`, `sine:HZ`, `sweep:HZ:HZ:MS`, `white`, `pink` or `identify`.
+
+use std::convert::Infallible;
+use std::process::ExitCode;
+
+use embassy_futures::block_on;
+use embassy_futures::join::join4;
+use embassy_futures::select::{select, Either};
+use uac2::{
+    AudioSource, Downmix, Format, Generator, GeneratorSource, Loopback, LoopbackSource, Signal,
+    State, StreamFormat, UAC2,
+};
+use uac2_sim::{audio_function, AltSetting, Host, MockDriver};
+
+/// Address assigned to the device during enumeration.
+const ADDRESS: u8 = 7;
+
+/// Loopback buffer, 8 ms of the stereo speaker at 48 kHz, the highest rate of the default configuration.
+const LOOPBACK_SAMPLES: usize = 8 * 49 * 2;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("uac2-sim: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+struct Args {
+    input: String,
+    output: String,
+    tail_ms: u32,
+    /// The microphone records the generator instead of the loopback
+    generator: Option<Signal>,
+    level: i32,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = std::env::args().skip(1);
+    let mut files = Vec::new();
+    let mut tail_ms = 100;
+    let mut generator = None;
+    let mut level = -20;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tail" => {
+                let value = args.next().ok_or("--tail needs a value in ms")?;
+                tail_ms = value
+                    .parse()
+                    .map_err(|_| format!("invalid --tail {value}"))?;
+            }
+            "--generator" => {
+                let value = args.next().ok_or("--generator needs a signal")?;
+                let signal =
+                    parse_signal(&value).ok_or_else(|| format!("invalid --generator {value}"))?;
+                generator = Some(signal);
+            }
+            "--level" => {
+                let value = args.next().ok_or("--level needs a value in dBFS")?;
+                level = value
+                    .parse()
+                    .ok()
+                    .filter(|level| (uac2::GENERATOR_MIN_LEVEL..=0).contains(level))
+                    .ok_or_else(|| format!("invalid --level {value}"))?;
+            }
+            _ => files.push(arg),
+        }
+    }
+    match <[String; 2]>::try_from(files) {
+        Ok([input, output]) => Ok(Args {
+            input,
+            output,
+            tail_ms,
+            generator,
+            level,
+        }),
+        Err(_) => Err(
+            "usage: uac2-sim [--tail MS] [--generator SIGNAL] [--level DB] INPUT.wav OUTPUT.wav"
+                .into(),
+        ),
+    }
+}
+
+/// A generator signal, its name with the arguments separated by colons.
+fn parse_signal(value: &str) -> Option<Signal> {
+    let mut parts = value.split(':');
+    let name = parts.next()?;
+    let mut argument = || parts.next()?.parse().ok();
+    let signal = match name {
+        "silence" => Signal::Silence,
+        "sine" => Signal::Sine(argument()?),
+        "sweep" => Signal::Sweep {
+            start: argument()?,
+            end: argument()?,
+            duration_ms: argument()?,
+        },
+        "white" => Signal::WhiteNoise,
+        "pink" => Signal::PinkNoise,
+        "identify" => Signal::Identify,
+        _ => return None,
+    };
+    match parts.next() {
+        None => Some(signal),
+        Some(_) => None,
+    }
+}
+
+/// Samples of the input file, interleaved and full scale over the `i32` range.
+struct Input {
+    spec: hound::WavSpec,
+    samples: Vec<i32>,
+}
+
+fn read_input(path: &str) -> Result<Input, String> {
+    let mut reader =
+        hound::WavReader::open(path).map_err(|error| format!("cannot read {path}: {error}"))?;
+    let spec = reader.spec();
+    let samples: Result<Vec<i32>, hound::Error> = match spec.sample_format {
+        hound::SampleFormat::Int => {
+            let shift = 32 - spec.bits_per_sample as u32;
+            reader
+                .samples::<i32>()
+                .map(|sample| sample.map(|sample| sample << shift))
+                .collect()
+        }
+        hound::SampleFormat::Float => reader
+            .samples::<f32>()
+            .map(|sample| sample.map(|sample| (sample as f64 * i32::MAX as f64) as i32))
+            .collect(),
+    };
+    let samples = samples.map_err(|error| format!("cannot read {path}: {error}"))?;
+    Ok(Input { spec, samples })
+}
+
+fn write_output(path: &str, spec: hound::WavSpec, samples: &[i32]) -> Result<(), String> {
+    let error = |error: hound::Error| format!("cannot write {path}: {error}");
+    let mut writer = hound::WavWriter::create(path, spec).map_err(error)?;
+    let shift = 32 - spec.bits_per_sample as u32;
+    for &sample in samples {
+        writer.write_sample(sample >> shift).map_err(error)?;
+    }
+    writer.finalize().map_err(error)
+}
+
+fn run() -> Result<(), String> {
+    let args = parse_args()?;
+    let input = read_input(&args.input)?;
+
+    let (driver, host) = MockDriver::new();
+    let config = {
+        let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+        config.manufacturer = Some("Leon Loeser");
+        config.product = Some("UAC2 simulator");
+        config.max_packet_size_0 = 64;
+        config.device_class = 0xEF;
+        config.device_sub_class = 0x02;
+        config.device_protocol = 0x01;
+        config.composite_with_iads = true;
+        config
+    };
+    let mut config_descriptor = [0; 1024];
+    let mut bos_descriptor = [0; 256];
+    let mut msos_descriptor = [0; 256];
+    let mut control_buf = [0; 64];
+    let mut state = State::new();
+    let mut builder = embassy_usb::Builder::new(
+        driver,
+        config,
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut msos_descriptor,
+        &mut control_buf,
+    );
+    let uac2::Parts {
+        control,
+        mut reader,
+        mut writer,
+        mut notifier,
+        ..
+    } = UAC2::new(
+        &mut builder,
+        &mut state,
+        uac2::Config {
+            function_name: Some("UAC2 simulator"),
+            ..uac2::Config::default()
+        },
+    )
+    .into_parts();
+    let mut usb = builder.build();
+
+    let max_packet_size = |alt_settings: &[(Format, u16)]| {
+        alt_settings
+            .iter()
+            .map(|&(_, size)| size as usize)
+            .max()
+            .unwrap_or(0)
+    };
+    let mut speaker_buf = vec![0; max_packet_size(&control.topology().speaker.alt_settings)];
+    let mut microphone_buf = vec![0; max_packet_size(&control.topology().microphone.alt_settings)];
+    let loopback = Loopback::<LOOPBACK_SAMPLES>::new(Downmix::Mono);
+    let generator = Generator::new(args.generator.unwrap_or(Signal::Silence), args.level);
+    let mut sink = loopback.sink();
+    let mut source = match args.generator {
+        Some(_) => CaptureSource::Generator(generator.source()),
+        None => CaptureSource::Loopback(loopback.source()),
+    };
+
+    let device = join4(
+        usb.run(),
+        notifier.run(),
+        uac2::pump_playback(&mut reader, &control, &mut sink, &mut speaker_buf),
+        uac2::pump_capture(&mut writer, &control, &mut source, &mut microphone_buf),
+    );
+    match block_on(select(device, stream(&host, &input, args.tail_ms))) {
+        Either::First(_) => unreachable!(),
+        Either::Second(result) => {
+            let (spec, samples) = result?;
+            write_output(&args.output, spec, &samples)
+        }
+    }
+}
+
+/// Play the role of the USB host: start both streams at the rate of `input`, send it to the speaker
+/// and record the microphone. Returns the format and samples of the recording.
+async fn stream(
+    host: &Host,
+    input: &Input,
+    tail_ms: u32,
+) -> Result<(hound::WavSpec, Vec<i32>), String> {
+    host.connect();
+    let configuration = host
+        .enumerate(ADDRESS)
+        .await
+        .map_err(|_| "enumeration stalled")?;
+    let function = audio_function(&configuration).ok_or("no UAC2 audio function")?;
+    let speaker = function.playback().ok_or("no speaker stream")?;
+    let microphone = function.capture().ok_or("no microphone stream")?;
+
+    let sample_rate = input.spec.sample_rate;
+    host.control_out(
+        REQUEST_TYPE_CLASS_INTERFACE,
+        CUR,
+        (CS_SAM_FREQ_CONTROL as u16) << 8,
+        (function.clock_source as u16) << 8 | function.ac_interface as u16,
+        &sample_rate.to_le_bytes(),
+    )
+    .await
+    .map_err(|_| format!("sample rate {sample_rate} Hz not supported"))?;
+
+    let bits = input.spec.bits_per_sample as u8;
+    let speaker_alt = *speaker
+        .alt_setting(bits)
+        .ok_or("no speaker alternate setting")?;
+    let microphone_alt = *microphone
+        .alt_setting(bits)
+        .ok_or("no microphone alternate setting")?;
+    for (interface, alt) in [
+        (speaker.interface, speaker_alt),
+        (microphone.interface, microphone_alt),
+    ] {
+        host.set_interface(interface, alt.alt_setting)
+            .await
+            .map_err(|_| format!("alternate setting {} stalled", alt.alt_setting))?;
+    }
+
+    let input_channels = input.spec.channels as usize;
+    let input_frames = input.samples.len() / input_channels;
+    let frames = input_frames + (sample_rate as usize * tail_ms as usize) / 1000;
+    let mut recording = Vec::with_capacity(frames * microphone.channels as usize);
+    let mut position = 0;
+    let mut remainder = 0;
+    let mut packet = Vec::new();
+    while position < frames {
+        let (n, next) = uac2::frames_per_packet(sample_rate, remainder);
+        remainder = next;
+
+        //  Input channels repeat over the speaker channels, a mono file plays on both sides
+        packet.clear();
+        let format = alt_format(&speaker_alt);
+        for frame in position..position + n {
+            for channel in 0..speaker.channels as usize {
+                let sample = if frame < input_frames {
+                    input.samples[frame * input_channels + channel % input_channels]
+                } else {
+                    0
+                };
+                let start = packet.len();
+                packet.resize(start + format.subslot_size as usize, 0);
+                format.encode(sample, &mut packet[start..]);
+            }
+        }
+        host.send(speaker_alt.endpoint, &packet);
+        position += n;
+
+        let received = host
+            .receive(microphone_alt.endpoint)
+            .await
+            .ok_or("microphone stream stopped")?;
+        let format = alt_format(&microphone_alt);
+        recording.extend(
+            received
+                .chunks_exact(format.subslot_size as usize)
+                .map(|subslot| format.decode(subslot)),
+        );
+    }
+
+    let spec = hound::WavSpec {
+        channels: microphone.channels as u16,
+        sample_rate,
+        bits_per_sample: microphone_alt.bit_resolution as u16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    Ok((spec, recording))
+}
+
+fn alt_format(alt: &AltSetting) -> Format {
+    Format {
+        subslot_size: alt.subslot_size,
+        bit_resolution: alt.bit_resolution,
+    }
+}
+
+/// Source of the microphone stream selected on the command line.
+enum CaptureSource<'a> {
+    Loopback(LoopbackSource<'a, LOOPBACK_SAMPLES>),
+    Generator(GeneratorSource<'a>),
+}
+
+impl AudioSource for CaptureSource<'_> {
+    type Error = Infallible;
+
+    async fn start(&mut self, format: StreamFormat) -> Result<(), Infallible> {
+        match self {
+            CaptureSource::Loopback(source) => source.start(format).await,
+            CaptureSource::Generator(source) => source.start(format).await,
+        }
+    }
+
+    async fn read(&mut self, frames: &mut [u8]) -> Result<usize, Infallible> {
+        match self {
+            CaptureSource::Loopback(source) => source.read(frames).await,
+            CaptureSource::Generator(source) => source.read(frames).await,
+        }
+    }
+
+    async fn stop(&mut self) {
+        match self {
+            CaptureSource::Loopback(source) => source.stop().await,
+            CaptureSource::Generator(source) => source.stop().await,
+        }
+    }
+
+    fn latency(&self) -> u32 {
+        match self {
+            CaptureSource::Loopback(source) => source.latency(),
+            CaptureSource::Generator(source) => source.latency(),
+        }
+    }
+}
+
+const REQUEST_TYPE_CLASS_INTERFACE: u8 = 0x21;
+const CUR: u8 = 0x01;
+const CS_SAM_FREQ_CONTROL: u8 = 0x01;
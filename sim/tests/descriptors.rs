@@ -0,0 +1,171 @@
+//! Configuration descriptors of the supported profiles against reviewed snapshots in `tests/golden/`.
+//!
+//! A snapshot is the tree printed by [`uac2_sim::describe`], so a changed byte shows up as a changed field
+//! in the diff. After reviewing an intended change, `UPDATE_GOLDEN=1 cargo test -p uac2-sim --target host-tuple`
+//! rewrites the snapshots.
+
+use std::fs;
+use std::path::Path;
+
+use embassy_futures::block_on;
+use embassy_futures::select::{select, Either};
+use uac2::{
+    ExtensionUnitHandler, Layout, State, StreamConfig, CHANNEL_FRONT_LEFT, CHANNEL_FRONT_RIGHT,
+    FORMAT_16_BIT, UAC2,
+};
+use uac2_sim::{descriptors, MockDriver};
+
+/// device.toml, generated by the build script the same way as for the firmware.
+#[allow(dead_code)]
+mod profile {
+    include!(concat!(env!("OUT_DIR"), "/profile.rs"));
+}
+
+/// The firmware as configured by device.toml.
+#[test]
+fn firmware() {
+    let mut dsp = NoControls;
+    let mut extension_units = profile::extension_units(&mut dsp);
+    check_golden(
+        "firmware",
+        uac2::Config {
+            function_name: Some(profile::PRODUCT),
+            speaker: profile::SPEAKER,
+            microphone: profile::MICROPHONE,
+            sample_rates: profile::SAMPLE_RATES,
+            extension_units: &mut extension_units,
+            midi: profile::MIDI,
+        },
+    );
+}
+
+/// Stereo headphones and a mono headset microphone, both with jack detect, 16 bit at 48 kHz.
+#[test]
+fn headset() {
+    check_golden(
+        "headset",
+        uac2::Config {
+            function_name: Some("Headset"),
+            speaker: StreamConfig {
+                channels: 2,
+                channel_config: CHANNEL_FRONT_LEFT | CHANNEL_FRONT_RIGHT,
+                terminal_type: Some(0x0302),
+                formats: &[FORMAT_16_BIT],
+                jack_detect: true,
+                ..StreamConfig::default()
+            },
+            microphone: StreamConfig {
+                terminal_type: Some(0x0203),
+                formats: &[FORMAT_16_BIT],
+                jack_detect: true,
+                ..StreamConfig::default()
+            },
+            sample_rates: &[48000],
+            ..uac2::Config::default()
+        },
+    );
+}
+
+/// The defaults of [`uac2::Config`].
+#[test]
+fn minimal() {
+    check_golden("minimal", uac2::Config::default());
+}
+
+struct NoControls;
+
+impl ExtensionUnitHandler for NoControls {
+    fn layout(&self, _selector: u8) -> Option<Layout> {
+        None
+    }
+
+    fn get_cur(&mut self, _selector: u8, _channel: u8) -> Option<i32> {
+        None
+    }
+
+    fn set_cur(&mut self, _selector: u8, _channel: u8, _value: i32) -> bool {
+        false
+    }
+}
+
+/// The function name labels the IAD as well as the AudioControl interface.
+#[test]
+fn function_name_is_the_iad_string() {
+    let configuration = configuration_descriptor(uac2::Config {
+        function_name: Some("Headset"),
+        ..uac2::Config::default()
+    });
+    let iad = descriptors(&configuration)
+        .find(|descriptor| descriptor[1] == INTERFACE_ASSOCIATION)
+        .expect("no IAD");
+    let audio_control = descriptors(&configuration)
+        .find(|descriptor| descriptor[1] == INTERFACE && descriptor[5..7] == [AUDIO, AUDIOCONTROL])
+        .expect("no AudioControl interface");
+    assert_ne!(iad[7], 0, "iFunction");
+    assert_eq!(iad[7], audio_control[8]);
+}
+
+fn check_golden(profile: &str, config: uac2::Config) {
+    let actual = uac2_sim::describe(&configuration_descriptor(config));
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{profile}.txt"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let golden = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "no snapshot {}, write it with UPDATE_GOLDEN=1 and review it",
+            path.display()
+        )
+    });
+    pretty_assertions::assert_eq!(
+        golden,
+        actual,
+        "descriptors of the {profile} profile differ from {}",
+        path.display()
+    );
+}
+
+/// Build the function over the mock driver and read the configuration descriptor the way a host does.
+fn configuration_descriptor<'d>(config: uac2::Config<'d>) -> Vec<u8> {
+    let (driver, host) = MockDriver::new();
+    let usb_config = {
+        let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+        config.max_power = 100;
+        config.max_packet_size_0 = 64;
+        config.device_class = 0xEF;
+        config.device_sub_class = 0x02;
+        config.device_protocol = 0x01;
+        config.composite_with_iads = true;
+        config
+    };
+    //  The class borrows its state and the buffers for as long as the config, a test can afford to leak them
+    let mut builder = embassy_usb::Builder::new(
+        driver,
+        usb_config,
+        Box::leak(Box::new([0; 1024])),
+        Box::leak(Box::new([0; 256])),
+        Box::leak(Box::new([0; 256])),
+        Box::leak(Box::new([0; 64])),
+    );
+    let state = Box::leak(Box::new(State::new()));
+    let _parts = UAC2::new(&mut builder, state, config).into_parts();
+    let mut usb = builder.build();
+
+    let enumerate = async {
+        host.connect();
+        host.enumerate(7).await.expect("enumeration stalled")
+    };
+    match block_on(select(usb.run(), enumerate)) {
+        Either::First(_) => unreachable!(),
+        Either::Second(configuration) => configuration,
+    }
+}
+
+const INTERFACE: u8 = 0x04;
+const INTERFACE_ASSOCIATION: u8 = 0x0B;
+const AUDIO: u8 = 0x01;
+const AUDIOCONTROL: u8 = 0x01;
@@ -0,0 +1,125 @@
+use core::fmt::Write;
+
+use defmt::info;
+use embassy_usb::class::cdc_acm::CdcAcmClass;
+use embassy_usb::driver::{Driver, EndpointError};
+use embassy_usb::Builder;
+use heapless::String;
+
+use app::console::{execute, parse, ConsoleBackend, ParseError};
+use uac2::ControlChanged;
+
+pub use embassy_usb::class::cdc_acm::State;
+
+/// Line-based console on a CDC-ACM interface, input is echoed back.
+pub struct Console<'d, D: Driver<'d>> {
+    class: CdcAcmClass<'d, D>,
+    control: ControlChanged<'d>,
+    line: String<MAX_LINE>,
+}
+
+impl<'d, D: Driver<'d>> Console<'d, D> {
+    pub fn new(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut State<'d>,
+        control: ControlChanged<'d>,
+    ) -> Self {
+        Self {
+            class: CdcAcmClass::new(builder, state, MAX_PACKET_SIZE),
+            control,
+            line: String::new(),
+        }
+    }
+
+    pub async fn run(&mut self, backend: &mut impl ConsoleBackend) -> ! {
+        loop {
+            self.class.wait_connection().await;
+            info!("Console connected");
+            if let Err(error) = self.session(backend).await {
+                info!("Console error {:#?}", error);
+            }
+            info!("Console disconnected");
+        }
+    }
+
+    async fn session(&mut self, backend: &mut impl ConsoleBackend) -> Result<(), EndpointError> {
+        self.line.clear();
+        self.write(PROMPT).await?;
+
+        let mut buf = [0; MAX_PACKET_SIZE as usize];
+        loop {
+            let n = self.class.read_packet(&mut buf).await?;
+            //  A backspace echoes 3 bytes, so a packet of them needs 3 times its size
+            let mut echo: String<{ 3 * MAX_PACKET_SIZE as usize }> = String::new();
+            for &byte in &buf[..n] {
+                match byte {
+                    b'\r' | b'\n' => {
+                        let _ = echo.push_str("\r\n");
+                        self.write(&echo).await?;
+                        echo.clear();
+                        self.execute_line(backend).await?;
+                        self.write(PROMPT).await?;
+                    }
+                    //  Backspace and delete erase the last character
+                    0x08 | 0x7F => {
+                        if self.line.pop().is_some() {
+                            let _ = echo.push_str("\x08 \x08");
+                        }
+                    }
+                    0x20..=0x7E => {
+                        if self.line.push(byte as char).is_ok() {
+                            let _ = echo.push(byte as char);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            if !echo.is_empty() {
+                self.write(&echo).await?;
+            }
+        }
+    }
+
+    async fn execute_line(
+        &mut self,
+        backend: &mut impl ConsoleBackend,
+    ) -> Result<(), EndpointError> {
+        let mut out: String<MAX_OUTPUT> = String::new();
+        let result = match parse(&self.line) {
+            Ok(command) => {
+                info!("Console command {}", command);
+                execute(command, &self.control, backend, &mut out)
+            }
+            Err(ParseError::Empty) => Ok(()),
+            Err(ParseError::UnknownCommand) => out.write_str("unknown command, try help\r\n"),
+            Err(ParseError::InvalidArgument) => out.write_str("invalid argument, try help\r\n"),
+        };
+        if result.is_err() {
+            out.truncate(MAX_OUTPUT - TRUNCATED.len());
+            let _ = out.push_str(TRUNCATED);
+        }
+        self.line.clear();
+        self.write(&out).await
+    }
+
+    async fn write(&mut self, text: &str) -> Result<(), EndpointError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        for chunk in text.as_bytes().chunks(MAX_PACKET_SIZE as usize) {
+            self.class.write_packet(chunk).await?;
+        }
+        //  A zero length packet ends a transfer of full packets
+        if text.len() % MAX_PACKET_SIZE as usize == 0 {
+            self.class.write_packet(&[]).await?;
+        }
+        Ok(())
+    }
+}
+
+const MAX_PACKET_SIZE: u16 = 64;
+const MAX_LINE: usize = 64;
+const MAX_OUTPUT: usize = 1024;
+
+const PROMPT: &str = "> ";
+const TRUNCATED: &str = "...\r\n";
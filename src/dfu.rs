@@ -0,0 +1,334 @@
+use core::mem::MaybeUninit;
+
+use defmt::info;
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::signal::Signal;
+use embassy_usb::builder::FunctionBuilder;
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::Driver;
+use embassy_usb::msos::{self, windows_version};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use embedded_storage::nor_flash::NorFlash;
+
+pub use app::dfu::{DfuState, DfuStatus, Updater, TRANSFER_SIZE};
+
+/// Configuration of the DFU interface.
+#[derive(Clone, Copy, Default)]
+pub struct Config<'d> {
+    /// Microsoft OS 2.0 descriptors, so Windows binds WinUSB to the interface without an INF file.
+    /// They are the only MS OS descriptors of the device.
+    pub msos: Option<MsOsConfig<'d>>,
+}
+
+/// Registry properties of the WinUSB device.
+#[derive(Clone, Copy)]
+pub struct MsOsConfig<'d> {
+    /// Device interface GUID in `{xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx}` form, update tools open the interface by it
+    pub device_interface_guid: &'d str,
+    /// Name shown in the Device Manager
+    pub friendly_name: Option<&'d str>,
+}
+
+/// Update mode requested by the host.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum Detach {
+    /// DFU_DETACH, reboot into the DFU mode of this firmware
+    DfuMode,
+    /// Vendor request, reboot into the USB bootloader in the RP2040 boot ROM
+    UsbBoot,
+}
+
+pub struct RuntimeState<'d> {
+    control: MaybeUninit<RuntimeControl<'d>>,
+    detach: Signal<CriticalSectionRawMutex, Detach>,
+}
+
+impl<'d> RuntimeState<'d> {
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            detach: Signal::new(),
+        }
+    }
+}
+
+/// DFU runtime interface of the application, the host requests an update mode through it.
+pub struct DfuRuntime<'d> {
+    detach: &'d Signal<CriticalSectionRawMutex, Detach>,
+}
+
+impl<'d> DfuRuntime<'d> {
+    pub fn new<D: Driver<'d>>(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut RuntimeState<'d>,
+        config: Config,
+    ) -> Self {
+        if config.msos.is_some() {
+            builder.msos_descriptor(windows_version::WIN8_1, MSOS_VENDOR_CODE);
+        }
+        let mut fun = builder.function(APPLICATION_SPECIFIC, DFU, PROTOCOL_RUNTIME);
+        if let Some(msos) = config.msos {
+            winusb_features(&mut fun, msos);
+        }
+        let mut int = fun.interface();
+        let interface = int.interface_number();
+        let mut alt = int.alt_setting(APPLICATION_SPECIFIC, DFU, PROTOCOL_RUNTIME, None);
+        alt.descriptor(DFU_FUNCTIONAL, &functional_descriptor());
+        drop(fun);
+
+        let control = state.control.write(RuntimeControl {
+            interface,
+            state: DfuState::AppIdle,
+            detach: &state.detach,
+        });
+        builder.handler(control);
+
+        Self {
+            detach: &state.detach,
+        }
+    }
+
+    /// Wait for the host to request an update mode. The application reboots into it once the
+    /// request has been acknowledged.
+    pub async fn wait_detach(&self) -> Detach {
+        self.detach.wait().await
+    }
+}
+
+struct RuntimeControl<'d> {
+    interface: InterfaceNumber,
+    state: DfuState,
+    detach: &'d Signal<CriticalSectionRawMutex, Detach>,
+}
+
+impl<'d> RuntimeControl<'d> {
+    fn accepts(&self, req: &Request) -> bool {
+        req.recipient == Recipient::Interface && req.index == u8::from(self.interface) as u16
+    }
+}
+
+impl<'d> Handler for RuntimeControl<'d> {
+    fn reset(&mut self) {
+        self.state = DfuState::AppIdle;
+    }
+
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        let detach = match (req.request_type, req.request) {
+            (RequestType::Class, DFU_DETACH) => Detach::DfuMode,
+            (RequestType::Vendor, VENDOR_USB_BOOT) => Detach::UsbBoot,
+            _ => return Some(OutResponse::Rejected),
+        };
+        info!("DFU detach to {}", detach);
+        self.state = DfuState::AppDetach;
+        self.detach.signal(detach);
+        Some(OutResponse::Accepted)
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        match (req.request_type, req.request) {
+            (RequestType::Class, DFU_GETSTATUS) => {
+                let len = encode_status(buf, DfuStatus::Ok, self.state);
+                Some(InResponse::Accepted(&buf[..len]))
+            }
+            (RequestType::Class, DFU_GETSTATE) => {
+                buf[0] = self.state as u8;
+                Some(InResponse::Accepted(&buf[..1]))
+            }
+            _ => Some(InResponse::Rejected),
+        }
+    }
+}
+
+pub struct ModeState<'d, F: NorFlash> {
+    control: MaybeUninit<ModeControl<'d, F>>,
+    reset: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d, F: NorFlash> ModeState<'d, F> {
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            reset: Signal::new(),
+        }
+    }
+}
+
+/// DFU mode interface, the only interface of the device while an image is downloaded.
+pub struct DfuMode<'d> {
+    reset: &'d Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d> DfuMode<'d> {
+    pub fn new<D: Driver<'d>, F: NorFlash>(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut ModeState<'d, F>,
+        updater: Updater<F>,
+        config: Config,
+    ) -> Self {
+        if config.msos.is_some() {
+            builder.msos_descriptor(windows_version::WIN8_1, MSOS_VENDOR_CODE);
+        }
+        let mut fun = builder.function(APPLICATION_SPECIFIC, DFU, PROTOCOL_DFU_MODE);
+        if let Some(msos) = config.msos {
+            winusb_features(&mut fun, msos);
+        }
+        let mut int = fun.interface();
+        let interface = int.interface_number();
+        let mut alt = int.alt_setting(APPLICATION_SPECIFIC, DFU, PROTOCOL_DFU_MODE, None);
+        alt.descriptor(DFU_FUNCTIONAL, &functional_descriptor());
+        drop(fun);
+
+        let control = state.control.write(ModeControl {
+            interface,
+            updater,
+            reset: &state.reset,
+        });
+        builder.handler(control);
+
+        Self {
+            reset: &state.reset,
+        }
+    }
+
+    /// Wait until the image has been manifested or the host reset the device, the application
+    /// then resets into the new firmware.
+    pub async fn wait_reset(&self) {
+        self.reset.wait().await
+    }
+}
+
+struct ModeControl<'d, F: NorFlash> {
+    interface: InterfaceNumber,
+    updater: Updater<F>,
+    reset: &'d Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl<'d, F: NorFlash> Handler for ModeControl<'d, F> {
+    fn reset(&mut self) {
+        if self.updater.state() == DfuState::ManifestWaitReset {
+            self.reset.signal(());
+        }
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.interface) as u16
+        {
+            return None;
+        }
+        let accepted = match req.request {
+            DFU_DNLOAD => self.updater.download(req.value, data),
+            DFU_CLRSTATUS => {
+                self.updater.clear_status();
+                true
+            }
+            DFU_ABORT => {
+                self.updater.abort();
+                true
+            }
+            _ => false,
+        };
+        if accepted {
+            Some(OutResponse::Accepted)
+        } else {
+            Some(OutResponse::Rejected)
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index != u8::from(self.interface) as u16
+        {
+            return None;
+        }
+        match req.request {
+            DFU_GETSTATUS => {
+                let (status, state) = self.updater.get_status();
+                //  Not manifestation tolerant, the device resets itself after the manifestation phase
+                if state == DfuState::Manifest {
+                    self.reset.signal(());
+                }
+                let len = encode_status(buf, status, state);
+                Some(InResponse::Accepted(&buf[..len]))
+            }
+            DFU_GETSTATE => {
+                buf[0] = self.updater.state() as u8;
+                Some(InResponse::Accepted(&buf[..1]))
+            }
+            _ => Some(InResponse::Rejected),
+        }
+    }
+}
+
+/// Compatible ID and registry properties of the function subset (Microsoft OS 2.0 Descriptors Specification)
+fn winusb_features<'d, D: Driver<'d>>(fun: &mut FunctionBuilder<'_, 'd, D>, config: MsOsConfig) {
+    fun.msos_feature(msos::CompatibleIdFeatureDescriptor::new("WINUSB", ""));
+    fun.msos_feature(msos::RegistryPropertyFeatureDescriptor::new(
+        "DeviceInterfaceGUIDs",
+        msos::PropertyData::RegMultiSz(&[config.device_interface_guid]),
+    ));
+    if let Some(name) = config.friendly_name {
+        fun.msos_feature(msos::RegistryPropertyFeatureDescriptor::new(
+            "FriendlyName",
+            msos::PropertyData::Sz(name),
+        ));
+    }
+}
+
+//  DFU Functional Descriptor (DFU 1.1 4.1.3)
+fn functional_descriptor() -> [u8; 7] {
+    let [timeout_lo, timeout_hi] = DETACH_TIMEOUT_MS.to_le_bytes();
+    let [size_lo, size_hi] = (TRANSFER_SIZE as u16).to_le_bytes();
+    [
+        WILL_DETACH | CAN_DNLOAD, //bmAttributes
+        timeout_lo,               //wDetachTimeOut
+        timeout_hi,
+        size_lo, //wTransferSize
+        size_hi,
+        0x10, //bcdDFUVersion 1.1
+        0x01,
+    ]
+}
+
+//  DFU_GETSTATUS response (DFU 1.1 6.1.2), programming is done before the response so no poll timeout is needed
+fn encode_status(buf: &mut [u8], status: DfuStatus, state: DfuState) -> usize {
+    buf[..6].copy_from_slice(&[status as u8, 0, 0, 0, state as u8, 0]);
+    6
+}
+
+//Interface class, subclass and protocols (DFU 1.1 4.2.1)
+const APPLICATION_SPECIFIC: u8 = 0xFE;
+const DFU: u8 = 0x01;
+const PROTOCOL_RUNTIME: u8 = 0x01;
+const PROTOCOL_DFU_MODE: u8 = 0x02;
+
+const DFU_FUNCTIONAL: u8 = 0x21;
+
+//Functional descriptor attributes
+const WILL_DETACH: u8 = 0b1000;
+const CAN_DNLOAD: u8 = 0b0001;
+
+const DETACH_TIMEOUT_MS: u16 = 1000;
+
+//Class requests (DFU 1.1 3)
+const DFU_DETACH: u8 = 0x00;
+const DFU_DNLOAD: u8 = 0x01;
+const DFU_GETSTATUS: u8 = 0x03;
+const DFU_CLRSTATUS: u8 = 0x04;
+const DFU_GETSTATE: u8 = 0x05;
+const DFU_ABORT: u8 = 0x06;
+
+/// Vendor request to the DFU runtime interface, reboots into the RP2040 USB bootloader
+pub const VENDOR_USB_BOOT: u8 = 0x01;
+
+//bRequest of the MS OS 2.0 descriptor set request
+const MSOS_VENDOR_CODE: u8 = 0x20;
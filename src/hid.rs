@@ -0,0 +1,127 @@
+use defmt::info;
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::driver::{Driver, EndpointError};
+use embassy_usb::Builder;
+
+use uac2::ControlChanged;
+
+pub use embassy_usb::class::hid::State;
+
+/// Consumer page usages of the headset buttons (HID Usage Tables 15 Consumer Page)
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum ConsumerKey {
+    PlayPause,
+    ScanNext,
+    ScanPrevious,
+    Stop,
+    Mute,
+    VolumeUp,
+    VolumeDown,
+}
+
+impl ConsumerKey {
+    fn usage(self) -> u16 {
+        match self {
+            ConsumerKey::PlayPause => 0xCD,
+            ConsumerKey::ScanNext => 0xB5,
+            ConsumerKey::ScanPrevious => 0xB6,
+            ConsumerKey::Stop => 0xB7,
+            ConsumerKey::Mute => 0xE2,
+            ConsumerKey::VolumeUp => 0xE9,
+            ConsumerKey::VolumeDown => 0xEA,
+        }
+    }
+}
+
+/// What the local mute button does.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, defmt::Format)]
+pub enum MuteButton {
+    /// Send the HID Mute key, the host mutes its output
+    Hid,
+    /// Toggle the master mute control of the speaker Feature Unit and notify the host
+    FeatureUnit,
+}
+
+/// Configuration of the consumer control interface.
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub mute_button: MuteButton,
+    /// Interval of the interrupt endpoint in milliseconds.
+    pub poll_ms: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mute_button: MuteButton::Hid,
+            poll_ms: 10,
+        }
+    }
+}
+
+/// HID consumer control interface for the hardware buttons, one 16 bit usage per report.
+pub struct ConsumerControl<'d, D: Driver<'d>> {
+    writer: HidWriter<'d, D, REPORT_SIZE>,
+    mute_button: MuteButton,
+}
+
+impl<'d, D: Driver<'d>> ConsumerControl<'d, D> {
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: Config) -> Self {
+        let writer = HidWriter::new(
+            builder,
+            state,
+            hid::Config {
+                report_descriptor: REPORT_DESCRIPTOR,
+                request_handler: None,
+                poll_ms: config.poll_ms,
+                max_packet_size: REPORT_SIZE as u16,
+            },
+        );
+        Self {
+            writer,
+            mute_button: config.mute_button,
+        }
+    }
+
+    /// Press and release `key`.
+    pub async fn send_consumer_key(&mut self, key: ConsumerKey) -> Result<(), EndpointError> {
+        info!("Consumer key {}", key);
+        self.writer.write(&key.usage().to_le_bytes()).await?;
+        self.writer.write(&[0; REPORT_SIZE]).await
+    }
+
+    /// Handle a press of the local mute button according to [`Config::mute_button`].
+    pub async fn mute_pressed(
+        &mut self,
+        control: &ControlChanged<'_>,
+    ) -> Result<(), EndpointError> {
+        match self.mute_button {
+            MuteButton::Hid => self.send_consumer_key(ConsumerKey::Mute).await,
+            MuteButton::FeatureUnit => {
+                control.set_muted(0, !control.muted(0));
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn wait_ready(&mut self) {
+        self.writer.ready().await
+    }
+}
+
+const REPORT_SIZE: usize = 2;
+
+//Consumer control report, a single 16 bit usage, 0 when released
+const REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x0C, //Usage Page (Consumer)
+    0x09, 0x01, //Usage (Consumer Control)
+    0xA1, 0x01, //Collection (Application)
+    0x15, 0x00, //  Logical Minimum (0)
+    0x26, 0xFF, 0x03, //  Logical Maximum (0x3FF)
+    0x19, 0x00, //  Usage Minimum (0)
+    0x2A, 0xFF, 0x03, //  Usage Maximum (0x3FF)
+    0x75, 0x10, //  Report Size (16)
+    0x95, 0x01, //  Report Count (1)
+    0x81, 0x00, //  Input (Data, Array, Absolute)
+    0xC0, //End Collection
+];
@@ -1,59 +1,82 @@
 #![no_std]
 #![no_main]
 
-mod uac2;
+mod console;
+mod dfu;
+mod hid;
+mod settings;
 
-use core::borrow::BorrowMut;
-use core::cell::RefCell;
+/// Device configuration generated by build.rs from the device profile.
+#[allow(dead_code)]
+mod profile {
+    include!(concat!(env!("OUT_DIR"), "/profile.rs"));
+}
 
-use cortex_m::interrupt::Mutex;
-use cortex_m::prelude::_embedded_hal_blocking_delay_DelayMs;
-use cortex_m::register::control::read;
-use embassy_futures::join::{join, join3};
+use core::cell::RefCell;
+use core::convert::Infallible;
 
-use defmt::info;
-use embassy_executor::Spawner;
-use embassy_futures::poll_once;
+use app::console::ConsoleBackend;
+use app::flash::{layout, Partition};
+use console::Console;
+use defmt::{info, unwrap};
+use dfu::{Detach, DfuMode, DfuRuntime, MsOsConfig, Updater};
+use embassy_boot_rp::BlockingFirmwareState;
+use embassy_executor::{InterruptExecutor, Spawner};
+use embassy_futures::select::{select, select4, Either4};
 use embassy_rp::bind_interrupts;
-use embassy_rp::peripherals::USB;
+use embassy_rp::flash::{self, Blocking, Flash};
+use embassy_rp::gpio::{Input, Pull};
+use embassy_rp::interrupt;
+use embassy_rp::interrupt::{InterruptExt, Priority};
+use embassy_rp::peripherals::{FLASH, USB};
+use embassy_rp::rom_data;
 use embassy_rp::usb::{Driver, Instance, InterruptHandler};
-use embassy_sync::blocking_mutex::NoopMutex;
+use embassy_rp::watchdog::Watchdog;
 use embassy_time::{Duration, Timer};
-use embassy_usb::driver::{Endpoint, EndpointOut};
-use embedded_alloc::LlffHeap as Heap;
-use embedded_hal::delay;
-use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
+use embassy_usb::UsbDevice;
+use hid::{ConsumerControl, ConsumerKey, MuteButton};
+use portable_atomic::{AtomicBool, AtomicI32, Ordering};
+use settings::{SettingsStore, MAX_PARAMETERS};
 use static_cell::StaticCell;
-use uac2::{AudioReader, AudioReaderWriter, AudioWriter, ControlChanged, State, UAC2};
+use uac2::{
+    AudioReader, AudioSink, AudioSource, AudioWriter, ControlChanged, Downmix, ExtensionUnit,
+    ExtensionUnitHandler, Generator, GeneratorSource, Latency, Layout, Loopback, LoopbackSink,
+    LoopbackSource, MidiReader, MidiReaderWriter, MidiWriter, Notifier, Overrun, Range, Signal,
+    State, StreamFormat, Terminal, UAC2,
+};
 use {defmt_rtt as _, panic_probe as _};
 
 bind_interrupts!(struct Irqs {
     USBCTRL_IRQ => InterruptHandler<USB>;
 });
 
-#[global_allocator]
-static HEAP: Heap = Heap::empty();
-
 #[embassy_executor::main]
 async fn main(spawner: Spawner) {
-    use core::mem::MaybeUninit;
-    const HEAP_SIZE: usize = 2048;
-    static mut HEAP_MEM: [MaybeUninit<u8>; HEAP_SIZE] = [MaybeUninit::uninit(); HEAP_SIZE];
-    unsafe { HEAP.init(HEAP_MEM.as_ptr() as usize, HEAP_SIZE) }
-
     let p = embassy_rp::init(Default::default());
 
+    let flash = {
+        static STORAGE: StaticCell<RefCell<FirmwareFlash>> = StaticCell::new();
+        &*STORAGE.init(RefCell::new(FirmwareFlash::new_blocking(p.FLASH)))
+    };
+    mark_booted(flash);
+
+    let mut watchdog = Watchdog::new(p.WATCHDOG);
+    //  The bootloader leaves its watchdog running, it only guards the swap of a new image
+    watchdog.stop();
+    if dfu_mode_requested(&mut watchdog) {
+        dfu_mode(p.USB, flash, watchdog).await;
+    }
+
     // Create the driver, from the HAL.
     let driver = Driver::new(p.USB, Irqs);
 
     // Create embassy-usb Config
     let config = {
-        let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
-        config.manufacturer = Some("Embassy");
-        config.product = Some("UAC2.0 Example");
-        config.serial_number = Some("12345678");
-        config.max_power = 100;
+        let mut config = embassy_usb::Config::new(profile::VID, profile::PID);
+        config.manufacturer = Some(profile::MANUFACTURER);
+        config.product = Some(profile::PRODUCT);
+        config.serial_number = profile::SERIAL_NUMBER;
+        config.max_power = profile::MAX_POWER;
         config.max_packet_size_0 = 64;
 
         // Required for windows compatibility.
@@ -82,100 +105,696 @@ async fn main(spawner: Spawner) {
         builder
     };
 
-    let mut uac2_class: UAC2<'_, Driver<'_, USB>> = {
+    let uac2::Parts {
+        control,
+        reader,
+        writer,
+        notifier,
+        midi,
+    } = {
         static STATE: StaticCell<State> = StaticCell::new();
-        let state = STATE.init(State::new());
-        UAC2::new(&mut builder, state)
+        static DSP_EXTENSION_UNIT: StaticCell<DspExtensionUnit> = StaticCell::new();
+        static EXTENSION_UNITS: StaticCell<[ExtensionUnit; profile::XU_COUNT]> = StaticCell::new();
+        let extension_units = EXTENSION_UNITS.init(profile::extension_units(
+            DSP_EXTENSION_UNIT.init(DspExtensionUnit(&DSP)),
+        ));
+        let config = uac2::Config {
+            function_name: Some(profile::PRODUCT),
+            speaker: profile::SPEAKER,
+            microphone: profile::MICROPHONE,
+            sample_rates: profile::SAMPLE_RATES,
+            extension_units,
+            midi: profile::MIDI,
+        };
+        UAC2::new_static(&mut builder, &STATE, config)
     };
-    let mut usb = builder.build();
 
-    let usb_fut = usb.run();
+    let consumer_control = {
+        static STATE: StaticCell<hid::State> = StaticCell::new();
+        let config = hid::Config {
+            mute_button: MuteButton::FeatureUnit,
+            ..hid::Config::default()
+        };
+        ConsumerControl::new(&mut builder, STATE.init(hid::State::new()), config)
+    };
+    //  Buttons to ground: mute, play/pause, volume up and volume down
+    let mute_button = Input::new(p.PIN_14, Pull::Up);
+    let play_pause_button = Input::new(p.PIN_15, Pull::Up);
+    let volume_up_button = Input::new(p.PIN_16, Pull::Up);
+    let volume_down_button = Input::new(p.PIN_17, Pull::Up);
+
+    let dfu_runtime = {
+        static STATE: StaticCell<dfu::RuntimeState> = StaticCell::new();
+        let config = dfu::Config {
+            msos: Some(DFU_MSOS),
+        };
+        DfuRuntime::new(&mut builder, STATE.init(dfu::RuntimeState::new()), config)
+    };
+
+    let console = {
+        static STATE: StaticCell<console::State> = StaticCell::new();
+        Console::new(&mut builder, STATE.init(console::State::new()), control)
+    };
+
+    let mut settings_store = SettingsStore::new(
+        Partition::new(flash, layout::SETTINGS_OFFSET, layout::SETTINGS_SIZE),
+        0,
+        layout::SETTINGS_SIZE / flash::ERASE_SIZE as u32,
+    );
+    match settings_store.load() {
+        Ok(Some(settings)) => {
+            info!("Restoring {}", settings);
+            settings.apply(&control);
+            DSP.restore(&settings.parameters);
+        }
+        Ok(None) => info!("No saved settings"),
+        Err(error) => info!("Settings load failed {:#?}", error),
+    }
+
+    let usb = builder.build();
+
+    //  The streams run above the USB device and the control tasks, so a busy console does not delay a packet
+    interrupt::SWI_IRQ_1.set_priority(Priority::P2);
+    let stream_spawner = EXECUTOR_STREAMS.start(interrupt::SWI_IRQ_1);
+    unwrap!(stream_spawner.spawn(playback_task(reader, control)));
+    unwrap!(stream_spawner.spawn(capture_task(writer, control)));
+
+    unwrap!(spawner.spawn(usb_task(usb)));
+    unwrap!(spawner.spawn(notifier_task(notifier)));
+    if let Some(midi) = midi {
+        unwrap!(spawner.spawn(midi_thru_task(midi)));
+    }
+    unwrap!(spawner.spawn(button_task(
+        consumer_control,
+        control,
+        mute_button,
+        play_pause_button,
+        volume_up_button,
+        volume_down_button
+    )));
+    unwrap!(spawner.spawn(console_task(console, DspConsole { dsp: &DSP, control })));
+    unwrap!(spawner.spawn(settings_task(settings_store, control)));
+
+    let detach = dfu_runtime.wait_detach().await;
+    //  Let the status stage of the request complete before the device drops off the bus
+    Timer::after(Duration::from_millis(50)).await;
+    reboot(detach, &mut watchdog);
+}
+
+type UsbDriver = Driver<'static, USB>;
+type FirmwareFlash = Flash<'static, FLASH, Blocking, FLASH_SIZE>;
+type SettingsFlash = Partition<'static, FirmwareFlash>;
+
+static EXECUTOR_STREAMS: InterruptExecutor = InterruptExecutor::new();
+
+#[interrupt]
+unsafe fn SWI_IRQ_1() {
+    EXECUTOR_STREAMS.on_interrupt()
+}
+
+#[embassy_executor::task]
+async fn usb_task(mut usb: UsbDevice<'static, UsbDriver>) -> ! {
+    usb.run().await
+}
+
+#[embassy_executor::task]
+async fn notifier_task(mut notifier: Notifier<'static, UsbDriver>) -> ! {
+    notifier.run().await
+}
+
+#[embassy_executor::task]
+async fn playback_task(
+    mut reader: AudioReader<'static, UsbDriver>,
+    control: ControlChanged<'static>,
+) -> ! {
+    let mut buf = [0; profile::SPEAKER_MAX_PACKET_SIZE];
+    let mut sink = PlaybackSink {
+        log: LogSink,
+        loopback: LOOPBACK.sink(),
+    };
+    uac2::pump_playback(&mut reader, &control, &mut sink, &mut buf).await
+}
+
+#[embassy_executor::task]
+async fn capture_task(
+    mut writer: AudioWriter<'static, UsbDriver>,
+    control: ControlChanged<'static>,
+) -> ! {
+    let mut buf = [0; profile::MICROPHONE_MAX_PACKET_SIZE];
+    let mut source = CaptureSource {
+        generator: GENERATOR.source(),
+        loopback: LOOPBACK.source(),
+        format: None,
+        looping: false,
+    };
+    uac2::pump_capture(&mut writer, &control, &mut source, &mut buf).await
+}
+
+#[embassy_executor::task]
+async fn midi_thru_task(midi: MidiReaderWriter<'static, UsbDriver>) {
+    let (mut reader, mut writer) = midi.split();
+    midi_task(&mut reader, &mut writer).await
+}
+
+#[embassy_executor::task]
+async fn button_task(
+    mut consumer_control: ConsumerControl<'static, UsbDriver>,
+    control: ControlChanged<'static>,
+    mut mute: Input<'static>,
+    mut play_pause: Input<'static>,
+    mut volume_up: Input<'static>,
+    mut volume_down: Input<'static>,
+) {
+    buttons(
+        &mut consumer_control,
+        &control,
+        &mut mute,
+        &mut play_pause,
+        &mut volume_up,
+        &mut volume_down,
+    )
+    .await
+}
+
+#[embassy_executor::task]
+async fn console_task(
+    mut console: Console<'static, UsbDriver>,
+    mut backend: DspConsole<'static>,
+) -> ! {
+    console.run(&mut backend).await
+}
+
+#[embassy_executor::task]
+async fn settings_task(
+    mut store: SettingsStore<SettingsFlash>,
+    control: ControlChanged<'static>,
+) -> ! {
+    settings::persist(&mut store, &control, || DSP.parameters()).await
+}
+
+const DFU_MSOS: MsOsConfig = MsOsConfig {
+    device_interface_guid: profile::DFU_DEVICE_INTERFACE_GUID,
+    friendly_name: Some(profile::DFU_PRODUCT),
+};
+
+// Partitions of the flash are laid out in `app::flash::layout`, FLASH in memory.x is the active partition
+const FLASH_SIZE: usize = layout::FLASH_SIZE as usize;
+const _: () = assert!(layout::SECTOR_SIZE as usize == flash::ERASE_SIZE);
+
+/// Confirm the running firmware to the bootloader, which otherwise swaps the previous firmware
+/// back at the next reset after an update.
+fn mark_booted(flash: &'static RefCell<FirmwareFlash>) {
+    let mut aligned = [0; flash::WRITE_SIZE];
+    let mut state = BlockingFirmwareState::new(bootloader_state(flash), &mut aligned);
+    if let Err(error) = state.mark_booted() {
+        info!("Bootloader state update failed {}", error);
+    }
+}
+
+/// Let the bootloader swap in the manifested DFU image at the next reset.
+fn mark_updated(flash: &'static RefCell<FirmwareFlash>) {
+    let mut aligned = [0; flash::WRITE_SIZE];
+    let mut state = BlockingFirmwareState::new(bootloader_state(flash), &mut aligned);
+    match state.mark_updated() {
+        Ok(()) => info!("DFU image marked for the bootloader"),
+        Err(error) => info!("Bootloader state update failed {}", error),
+    }
+}
 
-    //let uac2_fut = async { uac2_class.stuff().await };
+fn bootloader_state(flash: &'static RefCell<FirmwareFlash>) -> Partition<'static, FirmwareFlash> {
+    Partition::new(
+        flash,
+        layout::BOOTLOADER_STATE_OFFSET,
+        layout::BOOTLOADER_STATE_SIZE,
+    )
+}
 
-    let (mut _control, mut reader_writer): (
-        ControlChanged<'_>,
-        AudioReaderWriter<'_, Driver<'_, USB>>,
-    ) = uac2_class.split();
+// Watchdog scratch register surviving the reset into DFU mode
+const DFU_SCRATCH: usize = 0;
+const DFU_MODE_MAGIC: u32 = 0xDF0D_F00D;
 
-    let (mut reader, mut writer) = reader_writer.split();
+/// Reboot into the update mode requested by the host.
+fn reboot(detach: Detach, watchdog: &mut Watchdog) -> ! {
+    match detach {
+        Detach::DfuMode => {
+            watchdog.set_scratch(DFU_SCRATCH, DFU_MODE_MAGIC);
+            watchdog.trigger_reset();
+        }
+        Detach::UsbBoot => rom_data::reset_to_usb_boot(0, 0),
+    }
+    loop {
+        cortex_m::asm::nop();
+    }
+}
 
-    // Run everything concurrently.
-    // If we had made everything `'static` above instead, we could do this using separate tasks instead.
-    join3(usb_fut, receive_task(&mut reader), send_task(&mut writer)).await;
+fn dfu_mode_requested(watchdog: &mut Watchdog) -> bool {
+    let requested = watchdog.get_scratch(DFU_SCRATCH) == DFU_MODE_MAGIC;
+    watchdog.set_scratch(DFU_SCRATCH, 0);
+    requested
 }
 
-pub async fn send_task<'d, T: Instance + 'd>(writer: &mut AudioWriter<'d, Driver<'d, T>>) {
-    let mut data: [u8; 98] = [0; 98];
-    let mut small_rng = SmallRng::seed_from_u64(0x3675978356739456);
-    data.iter_mut()
-        .enumerate()
-        .for_each(|a| *a.1 = small_rng.gen());
+type DfuFlash = Partition<'static, FirmwareFlash>;
+
+/// DFU mode, the device only exposes the DFU interface until the new image is manifested.
+async fn dfu_mode(usb: USB, flash: &'static RefCell<FirmwareFlash>, mut watchdog: Watchdog) -> ! {
+    info!("DFU mode");
+    let driver = Driver::new(usb, Irqs);
+
+    let mut config = embassy_usb::Config::new(profile::VID, profile::PID);
+    config.manufacturer = Some(profile::MANUFACTURER);
+    config.product = Some(profile::DFU_PRODUCT);
+    config.serial_number = profile::SERIAL_NUMBER;
+    config.max_power = profile::MAX_POWER;
+    config.max_packet_size_0 = 64;
+
+    let mut builder = {
+        static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+        static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+        static CONTROL_BUF: StaticCell<[u8; dfu::TRANSFER_SIZE]> = StaticCell::new();
+        static MSOS_BUF: StaticCell<[u8; 256]> = StaticCell::new();
+
+        embassy_usb::Builder::new(
+            driver,
+            config,
+            CONFIG_DESCRIPTOR.init([0; 256]),
+            BOS_DESCRIPTOR.init([0; 256]),
+            MSOS_BUF.init([0; 256]),
+            CONTROL_BUF.init([0; dfu::TRANSFER_SIZE]),
+        )
+    };
+
+    let dfu = {
+        static STATE: StaticCell<dfu::ModeState<DfuFlash>> = StaticCell::new();
+        //  The spare sector at the end of the partition is left to the bootloader's swap
+        let updater = Updater::new(
+            DfuFlash::new(flash, layout::DFU_OFFSET, layout::DFU_SIZE),
+            0,
+            layout::ACTIVE_SIZE,
+        );
+        let config = dfu::Config {
+            msos: Some(DFU_MSOS),
+        };
+        DfuMode::new(
+            &mut builder,
+            STATE.init(dfu::ModeState::new()),
+            updater,
+            config,
+        )
+    };
 
+    let mut usb = builder.build();
+    select(usb.run(), async {
+        dfu.wait_reset().await;
+        //  Only a verified image gets here, a reset without one boots the current firmware again
+        mark_updated(flash);
+        Timer::after(Duration::from_millis(50)).await;
+    })
+    .await;
+    watchdog.trigger_reset();
     loop {
-        writer.wait_enabled().await;
-        info!("Connected");
-        loop {
-            match writer.write(&data).await {
-                Ok(_) => {
-                    info!("Sent stuff");
+        cortex_m::asm::nop();
+    }
+}
 
-                    Timer::after(Duration::from_micros(1000)).await;
-                }
-                Err(error) => {
-                    info!("Write error {:#?}", error);
-                    break;
-                }
+// Vendor control selectors of the DSP Extension Unit
+const XU_DSP_CROSSOVER_FREQUENCY: u8 = 0x01;
+const XU_DSP_LIMITER_THRESHOLD: u8 = 0x02;
+
+/// Proprietary DSP parameters, adjustable by host software through the DSP Extension Unit
+/// and through the console.
+struct DspParameters {
+    /// Crossover frequency in Hz
+    crossover_frequency: AtomicI32,
+    /// Limiter threshold in 1/256 dB
+    limiter_threshold: AtomicI32,
+}
+
+static DSP: DspParameters = DspParameters {
+    crossover_frequency: AtomicI32::new(2000),
+    limiter_threshold: AtomicI32::new(0),
+};
+
+// Console names of the DSP parameters
+const DSP_PARAMETER_NAMES: [(&str, u8); 2] = [
+    ("crossover", XU_DSP_CROSSOVER_FREQUENCY),
+    ("limiter", XU_DSP_LIMITER_THRESHOLD),
+];
+
+impl DspParameters {
+    fn parameter(&self, selector: u8) -> Option<&AtomicI32> {
+        match selector {
+            XU_DSP_CROSSOVER_FREQUENCY => Some(&self.crossover_frequency),
+            XU_DSP_LIMITER_THRESHOLD => Some(&self.limiter_threshold),
+            _ => None,
+        }
+    }
+
+    fn range(selector: u8) -> Option<Range> {
+        match selector {
+            XU_DSP_CROSSOVER_FREQUENCY => Some(Range {
+                min: 40,
+                max: 20000,
+                res: 1,
+            }),
+            XU_DSP_LIMITER_THRESHOLD => Some(Range {
+                min: -60 * 256,
+                max: 0,
+                res: 256 / 2,
+            }),
+            _ => None,
+        }
+    }
+
+    fn get(&self, selector: u8) -> Option<i32> {
+        Some(self.parameter(selector)?.load(Ordering::Relaxed))
+    }
+
+    fn set(&self, selector: u8, value: i32) -> bool {
+        let (Some(parameter), Some(range)) = (self.parameter(selector), Self::range(selector))
+        else {
+            return false;
+        };
+        if value < range.min || value > range.max {
+            return false;
+        }
+        parameter.store(value, Ordering::Relaxed);
+        info!("DSP control {} set to {}", selector, value);
+        true
+    }
+
+    /// Parameter values in the order of [`DSP_PARAMETER_NAMES`], for the settings store.
+    fn parameters(&self) -> [i32; MAX_PARAMETERS] {
+        let mut parameters = [0; MAX_PARAMETERS];
+        for (parameter, (_, selector)) in parameters.iter_mut().zip(DSP_PARAMETER_NAMES) {
+            *parameter = unwrap!(self.get(selector));
+        }
+        parameters
+    }
+
+    fn restore(&self, parameters: &[i32; MAX_PARAMETERS]) {
+        for (&value, (_, selector)) in parameters.iter().zip(DSP_PARAMETER_NAMES) {
+            self.set(selector, value);
+        }
+    }
+}
+
+/// The DSP Extension Unit, sharing its parameters with the console.
+struct DspExtensionUnit(&'static DspParameters);
+
+impl ExtensionUnitHandler for DspExtensionUnit {
+    fn layout(&self, selector: u8) -> Option<Layout> {
+        match selector {
+            XU_DSP_CROSSOVER_FREQUENCY => Some(Layout::Three),
+            XU_DSP_LIMITER_THRESHOLD => Some(Layout::Two),
+            _ => None,
+        }
+    }
+
+    fn get_cur(&mut self, selector: u8, _channel: u8) -> Option<i32> {
+        self.0.get(selector)
+    }
+
+    fn set_cur(&mut self, selector: u8, _channel: u8, value: i32) -> bool {
+        self.0.set(selector, value)
+    }
+
+    fn get_range(&mut self, selector: u8, _channel: u8) -> Option<Range> {
+        DspParameters::range(selector)
+    }
+}
+
+/// Console access to the DSP parameters, the loopback and the generator, DSP changes are notified
+/// to the host.
+struct DspConsole<'d> {
+    dsp: &'static DspParameters,
+    control: ControlChanged<'d>,
+}
+
+impl ConsoleBackend for DspConsole<'_> {
+    fn write_dsp_parameters(&self, out: &mut dyn core::fmt::Write) -> core::fmt::Result {
+        for (name, selector) in DSP_PARAMETER_NAMES {
+            write!(out, "{} {}\r\n", name, unwrap!(self.dsp.get(selector)))?;
+        }
+        Ok(())
+    }
+
+    fn set_dsp_parameter(&mut self, name: &str, value: i32) -> bool {
+        let Some(&(_, selector)) = DSP_PARAMETER_NAMES.iter().find(|(n, _)| *n == name) else {
+            return false;
+        };
+        if !self.dsp.set(selector, value) {
+            return false;
+        }
+        self.control
+            .extension_unit_changed(profile::XU_DSP, selector, 0);
+        true
+    }
+
+    fn buffer_fill(&self, terminal: Terminal) -> Option<(usize, usize)> {
+        match terminal {
+            Terminal::Speaker => {
+                let (fill, capacity) = LOOPBACK.fill();
+                Some((fill * size_of::<i32>(), capacity * size_of::<i32>()))
+            }
+            Terminal::Microphone => None,
+        }
+    }
+
+    fn loopback(&self) -> Option<Downmix> {
+        LOOPBACK_ENABLED
+            .load(Ordering::Relaxed)
+            .then(|| LOOPBACK.downmix())
+    }
+
+    fn set_loopback(&mut self, enabled: bool) {
+        LOOPBACK_ENABLED.store(enabled, Ordering::Relaxed);
+    }
+
+    fn set_downmix(&mut self, downmix: Downmix) {
+        LOOPBACK.set_downmix(downmix);
+    }
+
+    fn measure_latency(&mut self) -> bool {
+        if !LOOPBACK_ENABLED.load(Ordering::Relaxed) {
+            return false;
+        }
+        LOOPBACK.measure_latency();
+        true
+    }
+
+    fn latency(&self) -> Latency {
+        LOOPBACK.latency()
+    }
+
+    fn generator(&self) -> (Signal, i32) {
+        (GENERATOR.signal(), GENERATOR.level())
+    }
+
+    fn set_signal(&mut self, signal: Signal) -> bool {
+        GENERATOR.set_signal(signal)
+    }
+
+    fn set_level(&mut self, level: i32) -> bool {
+        GENERATOR.set_level(level)
+    }
+}
+
+/// Test signal on the microphone while the loopback is off, until the board has an audio input.
+static GENERATOR: Generator = Generator::new(Signal::Sine(1000), -20);
+
+/// Speaker time the loopback holds, several times the prefill so it absorbs the jitter between the streams.
+const LOOPBACK_MS: usize = 8;
+
+/// Loopback buffer in samples, one per speaker channel and frame over [`LOOPBACK_MS`] at the highest
+/// sample rate. A packet carries one frame more than the nominal frames per ms at most.
+const LOOPBACK_SAMPLES: usize = (max_sample_rate(profile::SAMPLE_RATES) as usize / 1000 + 1)
+    * profile::SPEAKER.channels as usize
+    * LOOPBACK_MS;
+
+const fn max_sample_rate(sample_rates: &[u32]) -> u32 {
+    let mut max = 0;
+    let mut i = 0;
+    while i < sample_rates.len() {
+        if sample_rates[i] > max {
+            max = sample_rates[i];
+        }
+        i += 1;
+    }
+    max
+}
+
+/// Plays the speaker back on the microphone, switched through the console.
+static LOOPBACK: Loopback<LOOPBACK_SAMPLES> = Loopback::new(Downmix::Mono);
+static LOOPBACK_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Playback backend, the packets are logged and feed the loopback.
+struct PlaybackSink {
+    log: LogSink,
+    loopback: LoopbackSink<'static, LOOPBACK_SAMPLES>,
+}
+
+impl AudioSink for PlaybackSink {
+    type Error = Overrun;
+
+    async fn start(&mut self, format: StreamFormat) -> Result<(), Overrun> {
+        let _ = self.log.start(format).await;
+        self.loopback.start(format).await
+    }
+
+    async fn write(&mut self, frames: &[u8]) -> Result<(), Overrun> {
+        let _ = self.log.write(frames).await;
+        self.loopback.write(frames).await
+    }
+
+    async fn stop(&mut self) {
+        self.log.stop().await;
+        self.loopback.stop().await;
+    }
+
+    fn latency(&self) -> u32 {
+        self.loopback.latency()
+    }
+}
+
+/// Capture backend, the loopback while it is enabled and the generator otherwise.
+struct CaptureSource {
+    generator: GeneratorSource<'static>,
+    loopback: LoopbackSource<'static, LOOPBACK_SAMPLES>,
+    format: Option<StreamFormat>,
+    /// The loopback source is started, it follows [`LOOPBACK_ENABLED`] with the next packet
+    looping: bool,
+}
+
+impl AudioSource for CaptureSource {
+    type Error = Infallible;
+
+    async fn start(&mut self, format: StreamFormat) -> Result<(), Infallible> {
+        info!("Capture started {}", format);
+        self.format = Some(format);
+        self.generator.start(format).await
+    }
+
+    async fn read(&mut self, frames: &mut [u8]) -> Result<usize, Infallible> {
+        let enabled = LOOPBACK_ENABLED.load(Ordering::Relaxed);
+        if enabled != self.looping {
+            match self.format {
+                Some(format) if enabled => self.loopback.start(format).await?,
+                _ => self.loopback.stop().await,
             }
+            self.looping = enabled;
+        }
+        if self.looping {
+            self.loopback.read(frames).await
+        } else {
+            self.generator.read(frames).await
+        }
+    }
+
+    async fn stop(&mut self) {
+        if self.looping {
+            self.loopback.stop().await;
+            self.looping = false;
         }
-        info!("Disconnected");
+        self.generator.stop().await;
+        self.format = None;
+        info!("Capture stopped");
+    }
+
+    fn latency(&self) -> u32 {
+        0
     }
 }
 
-pub async fn receive_task<'d, T: Instance + 'd>(reader: &mut AudioReader<'d, Driver<'d, T>>) {
+/// Playback backend until the board has an audio output, the packets are dropped and only the
+/// start and stop of the stream are logged.
+struct LogSink;
+
+impl AudioSink for LogSink {
+    type Error = Infallible;
+
+    async fn start(&mut self, format: StreamFormat) -> Result<(), Infallible> {
+        info!("Playback started {}", format);
+        Ok(())
+    }
+
+    async fn write(&mut self, _frames: &[u8]) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    async fn stop(&mut self) {
+        info!("Playback stopped");
+    }
+
+    fn latency(&self) -> u32 {
+        0
+    }
+}
+
+/// MIDI thru, every event received from the host is sent back on the same cable.
+pub async fn midi_task<'d, T: Instance + 'd>(
+    reader: &mut MidiReader<'d, Driver<'d, T>>,
+    writer: &mut MidiWriter<'d, Driver<'d, T>>,
+) {
     loop {
-        let mut data = [0; 400];
         reader.wait_enabled().await;
-        info!("Connected");
-
+        info!("MIDI connected");
         loop {
-            match reader.read(&mut data).await {
-                Ok(n) => {
-                    info!("Read stuff {:a}", data[..n]);
-                    //info!("Got bulk: {:a}", data[..n]);
-                    // Echo back to the host:
-                    // write_ep.write(&data[..n]).await.ok();
-
-                    let mut mic_data: [u8; 200] = [0; 200];
-                    data.chunks(4)
-                        .zip(mic_data.chunks_mut(2))
-                        .for_each(|(chunk, output)| {
-                            let left = u16::from_le_bytes(chunk[0..2].try_into().unwrap());
-                            let right = u16::from_le_bytes(chunk[2..4].try_into().unwrap());
-                            output.copy_from_slice(
-                                &((((left as i16) >> 1) + ((right as i16) >> 1)) as i16)
-                                    .to_le_bytes(),
-                            );
-                        });
-                    let data_len: usize = n / 2;
-                }
+            let event = match reader.read().await {
+                Ok(event) => event,
                 Err(error) => {
-                    info!("Read error {:#?}", error);
+                    info!("MIDI read error {:#?}", error);
                     break;
                 }
+            };
+            info!("MIDI event {}", event);
+            if let Err(error) = writer.write_event(&event).await {
+                info!("MIDI write error {:#?}", error);
+                break;
             }
         }
-        info!("Disconnected");
-        /*             let write_fn = async {
-            loop {
-                if mutex.borrow().borrow().new_data {
-                    let sharable = mutex.borrow().borrow_mut();
-                    let _ = reader_writer
-                        .write_ep_mic
-                        .write(&sharable.mic_data[..sharable.data_len / 2])
-                        .await;
-                }
+        info!("MIDI disconnected");
+    }
+}
+
+/// Headset buttons, active low with 50 ms debounce.
+pub async fn buttons<'d, T: Instance + 'd>(
+    consumer_control: &mut ConsumerControl<'d, Driver<'d, T>>,
+    control: &ControlChanged<'_>,
+    mute: &mut Input<'_>,
+    play_pause: &mut Input<'_>,
+    volume_up: &mut Input<'_>,
+    volume_down: &mut Input<'_>,
+) {
+    loop {
+        consumer_control.wait_ready().await;
+        let result = match select4(
+            mute.wait_for_falling_edge(),
+            play_pause.wait_for_falling_edge(),
+            volume_up.wait_for_falling_edge(),
+            volume_down.wait_for_falling_edge(),
+        )
+        .await
+        {
+            Either4::First(()) => consumer_control.mute_pressed(control).await,
+            Either4::Second(()) => {
+                consumer_control
+                    .send_consumer_key(ConsumerKey::PlayPause)
+                    .await
             }
-        }; */
+            Either4::Third(()) => {
+                consumer_control
+                    .send_consumer_key(ConsumerKey::VolumeUp)
+                    .await
+            }
+            Either4::Fourth(()) => {
+                consumer_control
+                    .send_consumer_key(ConsumerKey::VolumeDown)
+                    .await
+            }
+        };
+        if let Err(error) = result {
+            info!("Button error {:#?}", error);
+        }
+        Timer::after(Duration::from_millis(50)).await;
     }
 }
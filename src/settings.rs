@@ -0,0 +1,40 @@
+use defmt::info;
+use embassy_time::{Duration, Timer};
+use embedded_storage::nor_flash::NorFlash;
+
+use uac2::ControlChanged;
+
+pub use app::settings::{Settings, SettingsStore, MAX_PARAMETERS};
+
+/// Save the settings once they stop changing, so a volume knob being turned costs a single write.
+///
+/// `parameters` returns the application part of the settings.
+pub async fn persist<F: NorFlash>(
+    store: &mut SettingsStore<F>,
+    control: &ControlChanged<'_>,
+    mut parameters: impl FnMut() -> [i32; MAX_PARAMETERS],
+) -> ! {
+    let mut saved = Settings::capture(control, parameters());
+    loop {
+        Timer::after(POLL_INTERVAL).await;
+        let mut settings = Settings::capture(control, parameters());
+        if settings == saved {
+            continue;
+        }
+        loop {
+            Timer::after(SETTLE_TIME).await;
+            let current = Settings::capture(control, parameters());
+            if current == settings {
+                break;
+            }
+            settings = current;
+        }
+        match store.save(&settings) {
+            Ok(()) => saved = settings,
+            Err(error) => info!("Settings save failed: {}", defmt::Debug2Format(&error)),
+        }
+    }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const SETTLE_TIME: Duration = Duration::from_secs(2);
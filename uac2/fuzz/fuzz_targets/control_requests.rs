@@ -0,0 +1,261 @@
+//! Class-specific control requests with arbitrary fields and payloads, sent through embassy-usb to the
+//! control handler of the class. The function has several channels and formats per stream, MIDI and an
+//! Extension Unit with a control of each parameter block layout, so every kind of control is reachable.
+//!
+//! Run from `uac2/` with `cargo fuzz run control_requests`. A panic, a reply longer than wLength or a
+//! control the host changed that ends up outside of the RANGE it reports fails the run.
+#![no_main]
+
+use std::collections::BTreeSet;
+
+use arbitrary::Arbitrary;
+use embassy_futures::block_on;
+use embassy_futures::join::join;
+use embassy_futures::select::{select, Either};
+use libfuzzer_sys::fuzz_target;
+use uac2::{
+    ExtensionUnit, ExtensionUnitHandler, Format, Layout, MidiConfig, Range, State, StreamConfig,
+    CHANNEL_BACK_LEFT, CHANNEL_BACK_RIGHT, CHANNEL_FRONT_LEFT, CHANNEL_FRONT_RIGHT, FORMAT_16_BIT,
+    FORMAT_24_BIT, UAC2,
+};
+use uac2_sim::{audio_function, Host, MockDriver};
+
+/// Address assigned to the device during enumeration.
+const ADDRESS: u8 = 7;
+
+#[derive(Arbitrary, Debug)]
+struct Transfer {
+    /// Direction and recipient, the type is always class
+    request_type: u8,
+    request: u8,
+    value: u16,
+    index: u16,
+    /// wLength of IN transfers, OUT transfers send `data`
+    length: u16,
+    data: Vec<u8>,
+}
+
+fuzz_target!(|transfers: Vec<Transfer>| {
+    let (driver, host) = MockDriver::new();
+    let mut config_descriptor = [0; 1024];
+    let mut bos_descriptor = [0; 256];
+    let mut msos_descriptor = [0; 256];
+    let mut control_buf = [0; 256];
+    let mut state = State::new();
+    let mut vendor = VendorControls {
+        values: [0, 0, 48000],
+    };
+    let mut extension_units = [ExtensionUnit {
+        id: 0x40,
+        extension_code: 0x1234,
+        handler: &mut vendor,
+    }];
+    let mut builder = embassy_usb::Builder::new(
+        driver,
+        embassy_usb::Config::new(0xc0de, 0xcafe),
+        &mut config_descriptor,
+        &mut bos_descriptor,
+        &mut msos_descriptor,
+        &mut control_buf,
+    );
+    let uac2::Parts { mut notifier, .. } = UAC2::new(
+        &mut builder,
+        &mut state,
+        uac2::Config {
+            function_name: Some("Fuzz"),
+            speaker: StreamConfig {
+                channels: 4,
+                channel_config: CHANNEL_FRONT_LEFT
+                    | CHANNEL_FRONT_RIGHT
+                    | CHANNEL_BACK_LEFT
+                    | CHANNEL_BACK_RIGHT,
+                formats: &[FORMAT_16_BIT, FORMAT_24_BIT, FORMAT_24_BIT_PACKED],
+                ..StreamConfig::default()
+            },
+            microphone: StreamConfig {
+                channels: 2,
+                channel_config: CHANNEL_FRONT_LEFT | CHANNEL_FRONT_RIGHT,
+                jack_detect: true,
+                ..StreamConfig::default()
+            },
+            sample_rates: &[44100, 48000, 96000],
+            extension_units: &mut extension_units,
+            midi: Some(MidiConfig {
+                cables: 2,
+                name: None,
+            }),
+        },
+    )
+    .into_parts();
+    let mut usb = builder.build();
+
+    let device = join(usb.run(), notifier.run());
+    match block_on(select(device, exercise(&host, &transfers))) {
+        Either::First(_) => unreachable!(),
+        Either::Second(()) => {}
+    }
+});
+
+async fn exercise(host: &Host, transfers: &[Transfer]) {
+    host.connect();
+    let configuration = host.enumerate(ADDRESS).await.expect("enumeration stalled");
+    let function = audio_function(&configuration).expect("no UAC2 audio function");
+
+    //  Controls set by the host, the sample rate can also be set through the endpoint by UAC1 hosts
+    let mut changed = BTreeSet::from([(
+        REQUEST_TYPE_CLASS | RECIPIENT_INTERFACE,
+        (CS_SAM_FREQ_CONTROL as u16) << 8,
+        (function.clock_source as u16) << 8 | function.ac_interface as u16,
+    )]);
+    for transfer in transfers {
+        let request_type = transfer.request_type & !REQUEST_TYPE_MASK | REQUEST_TYPE_CLASS;
+        if request_type & REQUEST_TYPE_IN != 0 {
+            let reply = host
+                .control_in(
+                    request_type,
+                    transfer.request,
+                    transfer.value,
+                    transfer.index,
+                    transfer.length,
+                )
+                .await;
+            if let Ok(reply) = reply {
+                assert!(
+                    reply.len() <= transfer.length as usize,
+                    "{transfer:?} answered with {} bytes",
+                    reply.len()
+                );
+            }
+        } else {
+            let accepted = host
+                .control_out(
+                    request_type,
+                    transfer.request,
+                    transfer.value,
+                    transfer.index,
+                    &transfer.data,
+                )
+                .await
+                .is_ok();
+            if accepted && transfer.request == CUR {
+                changed.insert((request_type, transfer.value, transfer.index));
+            }
+        }
+    }
+
+    for (request_type, value, index) in changed {
+        check_range(host, request_type | REQUEST_TYPE_IN, value, index).await;
+    }
+}
+
+/// The current value of a control with a RANGE lies within one of its subranges.
+async fn check_range(host: &Host, request_type: u8, value: u16, index: u16) {
+    let Ok(range) = host
+        .control_in(request_type, RANGE, value, index, 256)
+        .await
+    else {
+        return;
+    };
+    assert!(
+        range.len() >= 2,
+        "RANGE of {value:#06x} {index:#06x}: {range:?}"
+    );
+    let subranges = u16::from_le_bytes([range[0], range[1]]) as usize;
+    assert!(subranges > 0, "RANGE of {value:#06x} {index:#06x} is empty");
+    let size = (range.len() - 2) / (3 * subranges);
+    assert!(
+        matches!(size, 2 | 4) && range.len() == 2 + 3 * subranges * size,
+        "RANGE of {value:#06x} {index:#06x}: {range:?}"
+    );
+    let decode = |bytes: &[u8]| match size {
+        2 => i16::from_le_bytes([bytes[0], bytes[1]]) as i32,
+        _ => i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+    };
+
+    let cur = host
+        .control_in(request_type, CUR, value, index, size as u16)
+        .await
+        .expect("CUR of a control with a RANGE stalled");
+    assert_eq!(cur.len(), size, "CUR of {value:#06x} {index:#06x}");
+    let cur = decode(&cur) as i64;
+    let contained = range[2..].chunks_exact(3 * size).any(|subrange| {
+        let [min, max, res] = [0, 1, 2].map(|i| decode(&subrange[i * size..]) as i64);
+        (min..=max).contains(&cur) && (res <= 0 || (cur - min) % res == 0)
+    });
+    assert!(
+        contained,
+        "CUR {cur} of {value:#06x} {index:#06x} outside of RANGE {range:?}"
+    );
+}
+
+/// 24 bit samples in 3 byte subslots.
+const FORMAT_24_BIT_PACKED: Format = Format {
+    subslot_size: 3,
+    bit_resolution: 24,
+};
+
+/// Vendor controls of the Extension Unit, one per parameter block layout.
+/// Values outside of the RANGE are rejected like a real handler would.
+struct VendorControls {
+    values: [i32; 3],
+}
+
+impl VendorControls {
+    fn range(selector: u8) -> Option<Range> {
+        match selector {
+            2 => Some(Range {
+                min: -6000,
+                max: 600,
+                res: 100,
+            }),
+            3 => Some(Range {
+                min: 8000,
+                max: 96000,
+                res: 4000,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl ExtensionUnitHandler for VendorControls {
+    fn layout(&self, selector: u8) -> Option<Layout> {
+        match selector {
+            1 => Some(Layout::One),
+            2 => Some(Layout::Two),
+            3 => Some(Layout::Three),
+            _ => None,
+        }
+    }
+
+    fn get_cur(&mut self, selector: u8, _channel: u8) -> Option<i32> {
+        self.layout(selector)?;
+        Some(self.values[selector as usize - 1])
+    }
+
+    fn set_cur(&mut self, selector: u8, _channel: u8, value: i32) -> bool {
+        if self.layout(selector).is_none() {
+            return false;
+        }
+        if let Some(range) = Self::range(selector) {
+            if !(range.min..=range.max).contains(&value) || (value - range.min) % range.res != 0 {
+                return false;
+            }
+        }
+        self.values[selector as usize - 1] = value;
+        true
+    }
+
+    fn get_range(&mut self, selector: u8, _channel: u8) -> Option<Range> {
+        Self::range(selector)
+    }
+}
+
+const REQUEST_TYPE_IN: u8 = 0x80;
+const REQUEST_TYPE_MASK: u8 = 0x60;
+const REQUEST_TYPE_CLASS: u8 = 0x20;
+const RECIPIENT_INTERFACE: u8 = 0x01;
+
+const CUR: u8 = 0x01;
+const RANGE: u8 = 0x02;
+const CS_SAM_FREQ_CONTROL: u8 = 0x01;
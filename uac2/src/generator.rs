@@ -0,0 +1,397 @@
+//! Test signal generator for the microphone stream.
+//!
+//! A [`Generator`] holds the signal and level, its [`GeneratorSource`] renders them at the rate and format
+//! of the running stream and follows changes with the next packet. The signals are computed in fixed point
+//! from tables built at compile time, the target has no FPU.
+
+use core::cell::Cell;
+use core::convert::Infallible;
+use core::f64::consts::{FRAC_PI_2, LN_2};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+
+use crate::{AudioSource, StreamFormat};
+
+/// Signal of a [`Generator`], the same on every channel except for [`Signal::Identify`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Signal {
+    /// Digital silence, every sample is zero regardless of the level
+    Silence,
+    /// Sine at a frequency in Hz
+    Sine(u32),
+    /// Logarithmic sweep from `start` to `end` Hz over `duration_ms`, repeating
+    Sweep {
+        start: u32,
+        end: u32,
+        duration_ms: u32,
+    },
+    /// Uniform white noise
+    WhiteNoise,
+    /// Noise falling by 3 dB per octave
+    PinkNoise,
+    /// Each channel in turn beeps for [`IDENTIFY_SLOT_MS`], channel n (counted from 0) at n + 1 times
+    /// [`IDENTIFY_FREQUENCY`]
+    Identify,
+}
+
+/// Lowest level of a [`Generator`] in dBFS.
+pub const GENERATOR_MIN_LEVEL: i32 = -120;
+
+/// Time slot of a channel in [`Signal::Identify`], the last quarter is silent.
+pub const IDENTIFY_SLOT_MS: u32 = 1000;
+/// Beep frequency of the first channel in [`Signal::Identify`].
+pub const IDENTIFY_FREQUENCY: u32 = 500;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct Settings {
+    signal: Signal,
+    /// dBFS, peak of the sine and the noise
+    level: i32,
+}
+
+/// Signal and level shared with a [`GeneratorSource`], adjustable while the stream runs.
+pub struct Generator {
+    settings: Mutex<CriticalSectionRawMutex, Cell<Settings>>,
+}
+
+impl Generator {
+    /// Panics on a level outside of [`GENERATOR_MIN_LEVEL`] to 0 dBFS.
+    pub const fn new(signal: Signal, level: i32) -> Self {
+        assert!(level >= GENERATOR_MIN_LEVEL && level <= 0);
+        Self {
+            settings: Mutex::new(Cell::new(Settings { signal, level })),
+        }
+    }
+
+    /// The source for the microphone stream, one per generator.
+    pub fn source(&self) -> GeneratorSource<'_> {
+        GeneratorSource {
+            generator: self,
+            format: None,
+            settings: self.settings(),
+            gain: 0,
+            phase: 0,
+            increment: 0,
+            position: 0,
+            sweep_start: 0,
+            sweep_frames: 0,
+            sweep_step: 0,
+            rng: RNG_SEED,
+            pink: [0; PINK_ROWS],
+        }
+    }
+
+    pub fn signal(&self) -> Signal {
+        self.settings().signal
+    }
+
+    /// Returning `false` rejects a zero frequency or duration. Frequencies above half the sample rate alias.
+    pub fn set_signal(&self, signal: Signal) -> bool {
+        let valid = match signal {
+            Signal::Sine(frequency) => frequency > 0,
+            Signal::Sweep {
+                start,
+                end,
+                duration_ms,
+            } => start > 0 && end > 0 && duration_ms > 0,
+            Signal::Silence | Signal::WhiteNoise | Signal::PinkNoise | Signal::Identify => true,
+        };
+        if valid {
+            self.update(|settings| settings.signal = signal);
+        }
+        valid
+    }
+
+    /// Level in dBFS.
+    pub fn level(&self) -> i32 {
+        self.settings().level
+    }
+
+    /// Returning `false` rejects a level outside of [`GENERATOR_MIN_LEVEL`] to 0 dBFS.
+    pub fn set_level(&self, level: i32) -> bool {
+        let valid = (GENERATOR_MIN_LEVEL..=0).contains(&level);
+        if valid {
+            self.update(|settings| settings.level = level);
+        }
+        valid
+    }
+
+    fn settings(&self) -> Settings {
+        self.settings.lock(|settings| settings.get())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Settings)) {
+        self.settings.lock(|settings| {
+            let mut value = settings.get();
+            f(&mut value);
+            settings.set(value);
+        })
+    }
+}
+
+/// Microphone side of a [`Generator`].
+pub struct GeneratorSource<'a> {
+    generator: &'a Generator,
+    format: Option<StreamFormat>,
+    /// Settings the signal state below was set up for
+    settings: Settings,
+    /// Q31
+    gain: u32,
+    /// Of the sine, a full turn is 2^32
+    phase: u32,
+    increment: u32,
+    /// Frames since the signal started
+    position: u32,
+    /// Phase increment at the start of the sweep
+    sweep_start: u32,
+    sweep_frames: u32,
+    /// Octaves per frame in Q32
+    sweep_step: i64,
+    rng: u32,
+    /// Voss-McCartney rows, row n changes every 2^n frames
+    pink: [i32; PINK_ROWS],
+}
+
+impl GeneratorSource<'_> {
+    /// Set up the signal for `settings` at `sample_rate`.
+    fn restart(&mut self, settings: Settings, sample_rate: u32) {
+        self.settings = settings;
+        self.gain = exp2_scale(1 << 31, settings.level * DB_TO_OCTAVES_Q16);
+        self.phase = 0;
+        self.position = 0;
+        self.pink = [0; PINK_ROWS];
+        match settings.signal {
+            Signal::Sine(frequency) => self.increment = phase_increment(frequency, sample_rate),
+            Signal::Sweep {
+                start,
+                end,
+                duration_ms,
+            } => {
+                self.sweep_start = phase_increment(start, sample_rate);
+                self.sweep_frames = (duration_ms as u64 * sample_rate as u64 / 1000).max(1) as u32;
+                let octaves = (log2_q16(end) - log2_q16(start)) as i64;
+                self.sweep_step = (octaves << 16) / self.sweep_frames as i64;
+            }
+            Signal::Identify => self.increment = phase_increment(IDENTIFY_FREQUENCY, sample_rate),
+            Signal::Silence | Signal::WhiteNoise | Signal::PinkNoise => {}
+        }
+        info!("Generator {} at {} dBFS", settings.signal, settings.level);
+    }
+
+    /// The next sample at full scale, and the only channel playing it for [`Signal::Identify`].
+    fn next(&mut self, format: &StreamFormat) -> (i32, Option<usize>) {
+        let position = self.position;
+        self.position = self.position.wrapping_add(1);
+        match self.settings.signal {
+            Signal::Silence => (0, None),
+            Signal::Sine(_) => {
+                let sample = sine(self.phase);
+                self.phase = self.phase.wrapping_add(self.increment);
+                (sample, None)
+            }
+            Signal::Sweep { .. } => {
+                if self.position >= self.sweep_frames {
+                    self.position = 0;
+                }
+                let octaves = (self.sweep_step * position as i64) >> 16;
+                let sample = sine(self.phase);
+                self.phase = self
+                    .phase
+                    .wrapping_add(exp2_scale(self.sweep_start, octaves as i32));
+                (sample, None)
+            }
+            Signal::WhiteNoise => (self.random(), None),
+            Signal::PinkNoise => {
+                //  Row n is redrawn on every frame with n trailing zeros, the white part changes every frame
+                let row = self.position.trailing_zeros() as usize;
+                if row < PINK_ROWS {
+                    self.pink[row] = self.random() >> PINK_SHIFT;
+                }
+                let white = self.random() >> PINK_SHIFT;
+                (self.pink.iter().sum::<i32>() + white, None)
+            }
+            Signal::Identify => {
+                let slot_frames = format.sample_rate * IDENTIFY_SLOT_MS / 1000;
+                let slot = position / slot_frames.max(1);
+                let channel = slot as usize % format.channels as usize;
+                let elapsed = position % slot_frames.max(1);
+                if elapsed == 0 {
+                    self.phase = 0;
+                }
+                if elapsed >= slot_frames / 4 * 3 {
+                    return (0, Some(channel));
+                }
+                let sample = sine(self.phase);
+                self.phase = self
+                    .phase
+                    .wrapping_add(self.increment.wrapping_mul(channel as u32 + 1));
+                (sample, Some(channel))
+            }
+        }
+    }
+
+    /// xorshift32, uniform over the `i32` range.
+    fn random(&mut self) -> i32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        self.rng as i32
+    }
+}
+
+impl AudioSource for GeneratorSource<'_> {
+    type Error = Infallible;
+
+    async fn start(&mut self, format: StreamFormat) -> Result<(), Infallible> {
+        self.format = Some(format);
+        self.restart(self.generator.settings(), format.sample_rate);
+        Ok(())
+    }
+
+    async fn read(&mut self, frames: &mut [u8]) -> Result<usize, Infallible> {
+        let Some(format) = self.format else {
+            return Ok(0);
+        };
+        let settings = self.generator.settings();
+        if settings != self.settings {
+            self.restart(settings, format.sample_rate);
+        }
+
+        let subslot_size = format.format.subslot_size as usize;
+        for frame in frames.chunks_exact_mut(format.frame_size()) {
+            let (sample, only) = self.next(&format);
+            let sample = ((sample as i64 * self.gain as i64) >> 31) as i32;
+            for (channel, subslot) in frame.chunks_exact_mut(subslot_size).enumerate() {
+                let sample = match only {
+                    Some(only) if only != channel => 0,
+                    _ => sample,
+                };
+                format.format.encode(sample, subslot);
+            }
+        }
+        Ok(frames.len())
+    }
+
+    async fn stop(&mut self) {
+        self.format = None;
+    }
+
+    /// The samples are computed on demand.
+    fn latency(&self) -> u32 {
+        0
+    }
+}
+
+const RNG_SEED: u32 = 0x3675_9783;
+
+const PINK_ROWS: usize = 12;
+/// Scales the rows and the white part so their sum stays within the `i32` range.
+const PINK_SHIFT: u32 = 4;
+
+/// Octaves per dB in Q16, log2(10) / 20.
+const DB_TO_OCTAVES_Q16: i32 = 10885;
+
+/// Steps of the quarter wave in [`SINE`] and of an octave in [`EXP2`].
+const TABLE_STEPS: usize = 256;
+
+/// Quarter wave of a full scale sine, sin(i / [`TABLE_STEPS`] * pi / 2) in Q31.
+static SINE: [i32; TABLE_STEPS + 1] = {
+    let mut table = [0; TABLE_STEPS + 1];
+    let mut i = 0;
+    while i <= TABLE_STEPS {
+        let x = i as f64 * FRAC_PI_2 / TABLE_STEPS as f64;
+        //  Taylor series, 12 terms reach f64 precision up to pi / 2
+        let mut term = x;
+        let mut sum = x;
+        let mut n = 1;
+        while n < 12 {
+            term *= -x * x / ((2 * n) * (2 * n + 1)) as f64;
+            sum += term;
+            n += 1;
+        }
+        table[i] = (sum * i32::MAX as f64) as i32;
+        i += 1;
+    }
+    table
+};
+
+/// One octave of 2^(i / [`TABLE_STEPS`]) in Q30.
+static EXP2: [u32; TABLE_STEPS + 1] = {
+    let mut table = [0; TABLE_STEPS + 1];
+    let mut i = 0;
+    while i <= TABLE_STEPS {
+        let x = i as f64 * LN_2 / TABLE_STEPS as f64;
+        //  Taylor series of e^x, 16 terms reach f64 precision up to ln 2
+        let mut term = 1.0;
+        let mut sum = 1.0;
+        let mut n = 1;
+        while n < 16 {
+            term *= x / n as f64;
+            sum += term;
+            n += 1;
+        }
+        table[i] = (sum * (1u32 << 30) as f64) as u32;
+        i += 1;
+    }
+    table
+};
+
+/// Sine at `phase`, a full turn is 2^32.
+fn sine(phase: u32) -> i32 {
+    //  Position within the quarter wave, mirrored in the second and fourth quarter
+    let mut x = phase & 0x3FFF_FFFF;
+    if phase & 0x4000_0000 != 0 {
+        x = 0x4000_0000 - x;
+    }
+    let i = (x >> 22) as usize;
+    let fraction = ((x >> 6) & 0xFFFF) as i64;
+    let next = SINE[(i + 1).min(TABLE_STEPS)] as i64;
+    let value = SINE[i] as i64 + (((next - SINE[i] as i64) * fraction) >> 16);
+    if phase & 0x8000_0000 != 0 {
+        -value as i32
+    } else {
+        value as i32
+    }
+}
+
+/// `x` times 2^`octaves` with `octaves` in Q16, saturating.
+fn exp2_scale(x: u32, octaves: i32) -> u32 {
+    let whole = octaves >> 16;
+    let fraction = (octaves & 0xFFFF) as u32;
+    let i = (fraction >> 8) as usize;
+    let t = (fraction & 0xFF) as u64;
+    let mantissa = EXP2[i] as u64 + (((EXP2[i + 1] - EXP2[i]) as u64 * t) >> 8);
+    let scaled = x as u64 * mantissa;
+    let shift = 30 - whole;
+    let result = if shift >= 64 {
+        0
+    } else if shift >= 0 {
+        scaled >> shift
+    } else {
+        u64::MAX
+    };
+    result.min(u32::MAX as u64) as u32
+}
+
+/// log2 of `x` in Q16, `x` must not be zero.
+fn log2_q16(x: u32) -> i32 {
+    let whole = 31 - x.leading_zeros();
+    //  Mantissa in Q31 within [1, 2), each squaring yields the next bit of the fraction
+    let mut mantissa = (x as u64) << (31 - whole);
+    let mut fraction = 0;
+    for bit in (0..16).rev() {
+        mantissa = (mantissa * mantissa) >> 31;
+        if mantissa >= 2 << 31 {
+            mantissa >>= 1;
+            fraction |= 1 << bit;
+        }
+    }
+    ((whole << 16) | fraction) as i32
+}
+
+/// Phase increment per frame of `frequency` at `sample_rate`.
+fn phase_increment(frequency: u32, sample_rate: u32) -> u32 {
+    (((frequency as u64) << 32) / sample_rate.max(1) as u64) as u32
+}
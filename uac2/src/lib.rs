@@ -0,0 +1,2829 @@
+//! USB Audio Class 2.0 function for embassy-usb.
+//!
+//! A speaker and a microphone path sharing one clock source, with optional Extension Units and a
+//! USB MIDI 1.0 interface. The class only depends on the `embassy_usb::driver::Driver` of the target, so
+//! it builds for the host too: `cargo test -p uac2 --target host-tuple` runs its tests over the mock
+//! driver of `uac2-sim`.
+//! The control request handler is fuzzed from `fuzz/` with `cargo fuzz run control_requests`.
+#![no_std]
+
+#[macro_use]
+mod fmt;
+mod generator;
+mod loopback;
+mod stream;
+
+pub use generator::{
+    Generator, GeneratorSource, Signal, GENERATOR_MIN_LEVEL, IDENTIFY_FREQUENCY, IDENTIFY_SLOT_MS,
+};
+pub use loopback::{
+    Downmix, Latency, Loopback, LoopbackSink, LoopbackSource, Overrun, LOOPBACK_PREFILL_MS,
+};
+pub use stream::{
+    frames_per_packet, pump_capture, pump_playback, AudioSink, AudioSource, StreamFormat,
+};
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+
+use heapless::{Deque, Vec};
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::{
+    Driver, Endpoint, EndpointError, EndpointIn, EndpointOut, SynchronizationType, UsageType,
+};
+use embassy_usb::types::{InterfaceNumber, StringIndex};
+use embassy_usb::{Builder, Handler};
+use portable_atomic::{AtomicI32, AtomicU32};
+use static_cell::StaticCell;
+
+pub struct State<'a> {
+    control: MaybeUninit<Control<'a>>,
+    topology: MaybeUninit<Topology>,
+    shared: ControlShared,
+}
+
+/// Configuration of the audio function.
+pub struct Config<'d> {
+    /// Name of the audio function, used as the function string of the IAD and the AudioControl interface string.
+    pub function_name: Option<&'d str>,
+    /// Speaker path, the Output Terminal is the physical terminal.
+    pub speaker: StreamConfig<'d>,
+    /// Microphone path, the Input Terminal is the physical terminal.
+    pub microphone: StreamConfig<'d>,
+    /// Sample rates of the clock source in Hz, at most [`MAX_SAMPLE_RATES`].
+    pub sample_rates: &'d [u32],
+    /// Extension Units, chained into the speaker path between the Feature Unit and the Output Terminal in the given order.
+    pub extension_units: &'d mut [ExtensionUnit<'d>],
+    /// MIDIStreaming interface in the same function, split off with [`UAC2::split`].
+    pub midi: Option<MidiConfig<'d>>,
+}
+
+impl<'d> Default for Config<'d> {
+    fn default() -> Self {
+        Self {
+            function_name: None,
+            speaker: StreamConfig {
+                channels: 2,
+                channel_config: CHANNEL_FRONT_LEFT | CHANNEL_FRONT_RIGHT,
+                ..StreamConfig::default()
+            },
+            microphone: StreamConfig::default(),
+            sample_rates: &[44100, 48000],
+            extension_units: &mut [],
+            midi: None,
+        }
+    }
+}
+
+/// Channel cluster, formats and naming of one direction of the audio function.
+#[derive(Clone, Copy)]
+pub struct StreamConfig<'d> {
+    /// Number of logical channels, 1 to [`MAX_CHANNELS`].
+    pub channels: u8,
+    /// Spatial locations of the logical channels (4.1 Audio Channel Cluster Descriptor), a combination of the `CHANNEL_*` constants.
+    /// Channels without a spatial location follow the predefined ones.
+    pub channel_config: u32,
+    /// Names of the logical channels, either empty or one name per logical channel.
+    pub channel_names: &'d [&'d str],
+    /// Name of the physical terminal.
+    pub terminal_name: Option<&'d str>,
+    /// Terminal Type of the physical terminal (Terminal Types 2.2), `None` for Speaker or Microphone.
+    pub terminal_type: Option<u16>,
+    /// Formats of the streaming interface, one alternate setting per format, at most [`MAX_FORMATS`].
+    /// Formats exceeding the full-speed budget at a sample rate are reported as invalid alternate settings at that rate.
+    pub formats: &'d [Format],
+    /// The physical terminal has a jack detect, enables its Connectors control.
+    /// Report plug changes with [`ControlChanged::set_connected`].
+    pub jack_detect: bool,
+}
+
+impl<'d> Default for StreamConfig<'d> {
+    fn default() -> Self {
+        Self {
+            channels: 1,
+            channel_config: 0,
+            channel_names: &[],
+            terminal_name: None,
+            terminal_type: None,
+            formats: &[FORMAT_16_BIT, FORMAT_24_BIT],
+            jack_detect: false,
+        }
+    }
+}
+
+/// USB MIDI 1.0 MIDIStreaming interface.
+#[derive(Clone, Copy)]
+pub struct MidiConfig<'d> {
+    /// Number of virtual cables, 1 to [`MAX_MIDI_CABLES`]. Each cable has an embedded and an external jack per direction.
+    pub cables: u8,
+    /// Name of the MIDIStreaming interface.
+    pub name: Option<&'d str>,
+}
+
+/// Type I PCM format of an alternate setting (2.3.1.6 Type I Format Type Descriptor)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Format {
+    /// Bytes per audio subslot, 1 to 4
+    pub subslot_size: u8,
+    /// Bits used of the subslot
+    pub bit_resolution: u8,
+}
+
+pub const FORMAT_16_BIT: Format = Format {
+    subslot_size: 2,
+    bit_resolution: 16,
+};
+
+pub const FORMAT_24_BIT: Format = Format {
+    subslot_size: 4,
+    bit_resolution: 24,
+};
+
+/// Parameter block layout of a control (5.2.3 Control Request Parameter Block Layout)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Layout {
+    /// 1 byte, CUR only
+    One,
+    /// 2 bytes, CUR and RANGE
+    Two,
+    /// 4 bytes, CUR and RANGE
+    Three,
+}
+
+impl Layout {
+    fn size(self) -> usize {
+        match self {
+            Layout::One => 1,
+            Layout::Two => 2,
+            Layout::Three => 4,
+        }
+    }
+}
+
+/// A single RANGE subrange of a layout 2 or layout 3 control.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Range {
+    pub min: i32,
+    pub max: i32,
+    pub res: i32,
+}
+
+/// Application handler for the vendor-defined controls of an Extension Unit.
+///
+/// Values are passed as `i32` and converted to and from the wire format according to [`ExtensionUnitHandler::layout`].
+pub trait ExtensionUnitHandler {
+    /// Layout of the control with the given selector, or `None` if the selector is not supported.
+    fn layout(&self, selector: u8) -> Option<Layout>;
+
+    /// Current value of a control. Returning `None` stalls the request.
+    fn get_cur(&mut self, selector: u8, channel: u8) -> Option<i32>;
+
+    /// Set the current value of a control. Returning `false` stalls the request.
+    fn set_cur(&mut self, selector: u8, channel: u8, value: i32) -> bool;
+
+    /// Range of a layout 2 or layout 3 control. Returning `None` stalls the request.
+    fn get_range(&mut self, selector: u8, channel: u8) -> Option<Range> {
+        let _ = (selector, channel);
+        None
+    }
+}
+
+/// An Extension Unit (4.7.2.12) with its vendor control handler.
+pub struct ExtensionUnit<'d> {
+    /// Unit ID, must not collide with the IDs used by the class.
+    pub id: u8,
+    /// Vendor-specific extension code.
+    pub extension_code: u16,
+    pub handler: &'d mut dyn ExtensionUnitHandler,
+}
+
+impl<'a> Default for State<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> State<'a> {
+    /// Create a new `State`.
+    pub fn new() -> Self {
+        Self {
+            control: MaybeUninit::uninit(),
+            topology: MaybeUninit::uninit(),
+            shared: ControlShared::default(),
+        }
+    }
+}
+
+struct Control<'a> {
+    shared: &'a ControlShared,
+    extension_units: &'a mut [ExtensionUnit<'a>],
+    strings: Vec<(StringIndex, &'a str), MAX_STRINGS>,
+    controls: Controls<'a>,
+    spk_ep: u8,
+    mic_ep: u8,
+    ac_interface: u8,
+    spk_interface: u8,
+    mic_interface: u8,
+}
+
+/// Shared data between Control and UAC2
+///
+/// Sync, so the handles of the class can run on other executors than the USB device, including interrupt executors.
+struct ControlShared {
+    spk_connected: AtomicBool,
+    mic_connected: AtomicBool,
+    notifications:
+        Mutex<CriticalSectionRawMutex, RefCell<Deque<Notification, MAX_PENDING_NOTIFICATIONS>>>,
+    notification_waker: AtomicWaker,
+    spk_status: StreamStatus,
+    mic_status: StreamStatus,
+    /// Sampling frequency control of the clock source in Hz
+    sample_rate: AtomicI32,
+    /// Active Alternate Setting controls of the streaming interfaces
+    spk_alt_setting: AtomicI32,
+    mic_alt_setting: AtomicI32,
+    /// Mute controls of the speaker Feature Unit, master channel first
+    spk_mute: [AtomicI32; MAX_CHANNELS as usize + 1],
+    /// Volume controls of the speaker Feature Unit in 1/256 dB, master channel first
+    spk_volume: [AtomicI32; MAX_CHANNELS as usize + 1],
+}
+
+/// Event counters and status flags of one stream, the flags are cleared when the host reads them.
+#[derive(Default)]
+struct StreamStatus {
+    underruns: AtomicU32,
+    overruns: AtomicU32,
+    overloads: AtomicU32,
+    /// Underflow control of the Feature Unit or Terminal
+    underflow: AtomicBool,
+    /// Overflow control of the Feature Unit or Terminal
+    overflow: AtomicBool,
+    /// Overload control of the Input Terminal
+    overload: AtomicBool,
+    /// Data Underrun control of the endpoint
+    data_underrun: AtomicBool,
+    /// Data Overrun control of the endpoint
+    data_overrun: AtomicBool,
+}
+
+/// Event counters of one stream.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StreamStats {
+    /// Data was missing, the host sent an empty packet or the application ran out of samples
+    pub underruns: u32,
+    /// Data was lost, a packet did not fit the buffer or the application dropped samples
+    pub overruns: u32,
+    /// The ADC clipped, only counted for the microphone
+    pub overloads: u32,
+}
+
+/// Interfaces, entities and streaming formats of the function as described to the host.
+#[derive(Clone, Debug)]
+pub struct Topology {
+    pub ac_interface: u8,
+    pub clock_source: u8,
+    pub sample_rates: Vec<u32, MAX_SAMPLE_RATES>,
+    pub speaker: StreamTopology,
+    pub microphone: StreamTopology,
+    /// Extension Unit IDs in the order of the speaker path
+    pub extension_units: Vec<u8, MAX_EXTENSION_UNITS>,
+    pub midi_interface: Option<u8>,
+}
+
+/// Terminals and streaming interface of one direction.
+#[derive(Clone, Debug)]
+pub struct StreamTopology {
+    pub interface: u8,
+    pub endpoint: u8,
+    pub channels: u8,
+    pub channel_config: u32,
+    pub input_terminal: u8,
+    pub feature_unit: Option<u8>,
+    pub output_terminal: u8,
+    /// Format and endpoint size of alternate settings 1 to n
+    pub alt_settings: Vec<(Format, u16), MAX_FORMATS>,
+}
+
+#[derive(Clone, Copy)]
+pub struct ControlChanged<'d> {
+    control: &'d ControlShared,
+    topology: &'d Topology,
+}
+
+/// Physical terminal of the audio function.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Terminal {
+    /// Output Terminal of the speaker path
+    Speaker,
+    /// Input Terminal of the microphone path
+    Microphone,
+}
+
+impl<'d> ControlChanged<'d> {
+    /// Report the plug state of the jack of `terminal`, the host is notified on changes.
+    pub fn set_connected(&self, terminal: Terminal, connected: bool) {
+        let (flag, entity_id) = match terminal {
+            Terminal::Speaker => (&self.control.spk_connected, UAC2_ENTITY_SPK_OUTPUT_TERMINAL),
+            Terminal::Microphone => (&self.control.mic_connected, UAC2_ENTITY_MIC_INPUT_TERMINAL),
+        };
+        if flag.swap(connected, Ordering::Relaxed) != connected {
+            info!("{} connected: {}", terminal, connected);
+            self.control.notify(Notification {
+                origin: Origin::Entity(entity_id),
+                selector: TE_CONNECTOR_CONTROL,
+                channel: 0,
+            });
+        }
+    }
+
+    /// Report that the ADC of the microphone clipped.
+    pub fn report_overload(&self) {
+        self.control.overload();
+    }
+
+    /// Event counters of the stream of `terminal`.
+    pub fn stats(&self, terminal: Terminal) -> StreamStats {
+        let status = self.control.status(terminal);
+        StreamStats {
+            underruns: status.underruns.load(Ordering::Relaxed),
+            overruns: status.overruns.load(Ordering::Relaxed),
+            overloads: status.overloads.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Plug state of the jack of `terminal`.
+    pub fn connected(&self, terminal: Terminal) -> bool {
+        match terminal {
+            Terminal::Speaker => self.control.spk_connected.load(Ordering::Relaxed),
+            Terminal::Microphone => self.control.mic_connected.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn topology(&self) -> &'d Topology {
+        self.topology
+    }
+
+    /// Current sampling frequency of the clock source in Hz.
+    pub fn sample_rate(&self) -> u32 {
+        self.control.sample_rate.load(Ordering::Relaxed) as u32
+    }
+
+    /// Format of the active alternate setting of the streaming interface of `terminal`, `None` while idle.
+    pub fn format(&self, terminal: Terminal) -> Option<Format> {
+        let alt_setting = self.control.alt_setting(terminal).load(Ordering::Relaxed);
+        let stream = match terminal {
+            Terminal::Speaker => &self.topology.speaker,
+            Terminal::Microphone => &self.topology.microphone,
+        };
+        let (format, _) = stream
+            .alt_settings
+            .get((alt_setting as usize).checked_sub(1)?)?;
+        Some(*format)
+    }
+
+    /// Report that a control of an Extension Unit changed locally, the host is notified.
+    pub fn extension_unit_changed(&self, id: u8, selector: u8, channel: u8) {
+        self.control.notify(Notification {
+            origin: Origin::Entity(id),
+            selector,
+            channel,
+        });
+    }
+
+    /// Mute control of a channel of the speaker Feature Unit, channel 0 is the master channel.
+    pub fn muted(&self, channel: u8) -> bool {
+        self.control
+            .spk_mute
+            .get(channel as usize)
+            .is_some_and(|mute| mute.load(Ordering::Relaxed) != 0)
+    }
+
+    /// Volume control of a channel of the speaker Feature Unit in 1/256 dB, channel 0 is the master channel.
+    pub fn volume(&self, channel: u8) -> i16 {
+        self.control
+            .spk_volume
+            .get(channel as usize)
+            .map_or(0, |volume| volume.load(Ordering::Relaxed) as i16)
+    }
+
+    /// Change the mute control of a channel of the speaker Feature Unit locally, the host is notified.
+    pub fn set_muted(&self, channel: u8, muted: bool) {
+        let Some(mute) = self.control.spk_mute.get(channel as usize) else {
+            return;
+        };
+        if mute.swap(muted as i32, Ordering::Relaxed) != muted as i32 {
+            info!("Channel {} muted: {}", channel, muted);
+            self.control.notify(Notification {
+                origin: Origin::Entity(UAC2_ENTITY_SPK_FEATURE_UNIT),
+                selector: FU_MUTE_CONTROL,
+                channel,
+            });
+        }
+    }
+
+    /// Change the volume control of a channel of the speaker Feature Unit locally, the host is notified.
+    pub fn set_volume(&self, channel: u8, volume: i16) {
+        let Some(current) = self.control.spk_volume.get(channel as usize) else {
+            return;
+        };
+        if !VOLUME_RANGE.contains(volume as i32) {
+            return;
+        }
+        if current.swap(volume as i32, Ordering::Relaxed) != volume as i32 {
+            info!("Channel {} volume: {}", channel, volume);
+            self.control.notify(Notification {
+                origin: Origin::Entity(UAC2_ENTITY_SPK_FEATURE_UNIT),
+                selector: FU_VOLUME_CONTROL,
+                channel,
+            });
+        }
+    }
+
+    /// Change the sampling frequency of the clock source locally, the host is notified.
+    ///
+    /// Returns `false` if `sample_rate` is not one of [`Config::sample_rates`].
+    pub fn set_sample_rate(&self, sample_rate: u32) -> bool {
+        if !self.topology.sample_rates.contains(&sample_rate) {
+            return false;
+        }
+        if self
+            .control
+            .sample_rate
+            .swap(sample_rate as i32, Ordering::Relaxed)
+            != sample_rate as i32
+        {
+            info!("Sample rate: {}", sample_rate);
+            self.control.notify(Notification {
+                origin: Origin::Entity(UAC2_ENTITY_CLOCK),
+                selector: CS_SAM_FREQ_CONTROL,
+                channel: 0,
+            });
+            self.control.sample_rate_changed();
+        }
+        true
+    }
+}
+
+/// Source of an Interrupt Data Message (6.1 Interrupt Data Message)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+struct Notification {
+    origin: Origin,
+    selector: u8,
+    channel: u8,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum Origin {
+    /// Entity of the AudioControl interface
+    Entity(u8),
+    /// AudioStreaming interface of a terminal
+    Interface(Terminal),
+    /// Streaming endpoint of a terminal
+    Endpoint(Terminal),
+}
+
+impl Default for ControlShared {
+    fn default() -> Self {
+        ControlShared {
+            spk_connected: AtomicBool::new(true),
+            mic_connected: AtomicBool::new(true),
+            notifications: Mutex::new(RefCell::new(Deque::new())),
+            notification_waker: AtomicWaker::new(),
+            spk_status: StreamStatus::default(),
+            mic_status: StreamStatus::default(),
+            sample_rate: AtomicI32::new(0),
+            spk_alt_setting: AtomicI32::new(0),
+            mic_alt_setting: AtomicI32::new(0),
+            spk_mute: Default::default(),
+            spk_volume: Default::default(),
+        }
+    }
+}
+
+impl ControlShared {
+    fn alt_setting(&self, terminal: Terminal) -> &AtomicI32 {
+        match terminal {
+            Terminal::Speaker => &self.spk_alt_setting,
+            Terminal::Microphone => &self.mic_alt_setting,
+        }
+    }
+
+    fn status(&self, terminal: Terminal) -> &StreamStatus {
+        match terminal {
+            Terminal::Speaker => &self.spk_status,
+            Terminal::Microphone => &self.mic_status,
+        }
+    }
+
+    /// Count an underrun of the stream and raise its underflow controls.
+    fn underrun(&self, terminal: Terminal) {
+        let status = self.status(terminal);
+        status.underruns.fetch_add(1, Ordering::Relaxed);
+        let selector = match terminal {
+            Terminal::Speaker => FU_UNDERFLOW_CONTROL,
+            Terminal::Microphone => TE_UNDERFLOW_CONTROL,
+        };
+        self.raise(&status.underflow, self.status_entity(terminal), selector);
+        self.raise(
+            &status.data_underrun,
+            Origin::Endpoint(terminal),
+            EP_DATA_UNDERRUN_CONTROL,
+        );
+    }
+
+    /// Count an overrun of the stream and raise its overflow controls.
+    fn overrun(&self, terminal: Terminal) {
+        let status = self.status(terminal);
+        status.overruns.fetch_add(1, Ordering::Relaxed);
+        let selector = match terminal {
+            Terminal::Speaker => FU_OVERFLOW_CONTROL,
+            Terminal::Microphone => TE_OVERFLOW_CONTROL,
+        };
+        self.raise(&status.overflow, self.status_entity(terminal), selector);
+        self.raise(
+            &status.data_overrun,
+            Origin::Endpoint(terminal),
+            EP_DATA_OVERRUN_CONTROL,
+        );
+    }
+
+    /// Count a clipping ADC and raise the overload control of the microphone Input Terminal.
+    fn overload(&self) {
+        self.mic_status.overloads.fetch_add(1, Ordering::Relaxed);
+        self.raise(
+            &self.mic_status.overload,
+            Origin::Entity(UAC2_ENTITY_MIC_INPUT_TERMINAL),
+            TE_OVERLOAD_CONTROL,
+        );
+    }
+
+    /// Entity carrying the underflow and overflow controls of a stream.
+    fn status_entity(&self, terminal: Terminal) -> Origin {
+        match terminal {
+            Terminal::Speaker => Origin::Entity(UAC2_ENTITY_SPK_FEATURE_UNIT),
+            Terminal::Microphone => Origin::Entity(UAC2_ENTITY_MIC_OUTPUT_TERMINAL),
+        }
+    }
+
+    /// Set a status flag, the host is interrupted when it trips.
+    fn raise(&self, flag: &AtomicBool, origin: Origin, selector: u8) {
+        if !flag.swap(true, Ordering::Relaxed) {
+            self.notify(Notification {
+                origin,
+                selector,
+                channel: 0,
+            });
+        }
+    }
+
+    /// Queue an interrupt for the host, a change already pending is not queued twice.
+    fn notify(&self, notification: Notification) {
+        let queued = self.notifications.lock(|notifications| {
+            let mut notifications = notifications.borrow_mut();
+            if notifications.iter().any(|pending| *pending == notification) {
+                return false;
+            }
+            if notifications.push_back(notification).is_err() {
+                info!("Notification queue full, dropped {}", notification);
+                return false;
+            }
+            true
+        });
+        if queued {
+            self.notification_waker.wake();
+        }
+    }
+
+    /// The valid alternate settings follow the clock rate.
+    fn sample_rate_changed(&self) {
+        for terminal in [Terminal::Speaker, Terminal::Microphone] {
+            self.notify(Notification {
+                origin: Origin::Interface(terminal),
+                selector: AS_VAL_ALT_SETTINGS_CONTROL,
+                channel: 0,
+            });
+        }
+    }
+
+    async fn next_notification(&self) -> Notification {
+        poll_fn(|cx| {
+            //  Register before looking at the queue, a notification queued in between wakes the task again
+            self.notification_waker.register(cx.waker());
+            match self
+                .notifications
+                .lock(|notifications| notifications.borrow_mut().pop_front())
+            {
+                Some(notification) => Poll::Ready(notification),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+/// Controls of the audio function, GET and SET requests are answered from the registered entries.
+struct Controls<'a> {
+    entries: Vec<ControlEntry<'a>, MAX_CONTROLS>,
+    /// Current values of the read-write controls
+    values: Vec<i32, MAX_CONTROL_VALUES>,
+    /// RANGE subranges of the controls
+    ranges: Vec<Range, MAX_CONTROL_RANGES>,
+}
+
+/// A control addressed by entity or endpoint, control selector and channel.
+struct ControlEntry<'a> {
+    origin: Origin,
+    selector: u8,
+    layout: Layout,
+    /// Highest addressable channel number, 0 if the control only exists on the master channel
+    channels: u8,
+    /// [`CONTROL_READ_ONLY`] or [`CONTROL_READ_WRITE`]
+    access: u8,
+    /// Subranges in [`Controls::ranges`], SET CUR has to fall into one of them
+    ranges: core::ops::Range<usize>,
+    storage: Storage<'a>,
+}
+
+/// Backing storage of a control.
+#[derive(Clone, Copy)]
+enum Storage<'a> {
+    Constant(i32),
+    /// One value per channel in [`Controls::values`] starting at the index
+    Values(usize),
+    /// One value per channel, shared with the application
+    Shared(&'a [AtomicI32]),
+    /// Status flag, cleared when read
+    Flag(&'a AtomicBool),
+    /// Connectors Cluster of a terminal instead of a layout 1-3 parameter block, no channels are reported while unplugged
+    Connectors(&'a AtomicBool, [u8; 6]),
+    /// bmValidAltSettings of a streaming interface for each of the sample rates, following the current clock rate
+    ValidAltSettings(&'a [u32], [u8; MAX_SAMPLE_RATES]),
+}
+
+impl Range {
+    fn contains(&self, value: i32) -> bool {
+        (self.min..=self.max).contains(&value)
+            && (self.res <= 0 || (value - self.min) % self.res == 0)
+    }
+}
+
+impl<'a> Controls<'a> {
+    fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            values: Vec::new(),
+            ranges: Vec::new(),
+        }
+    }
+
+    /// Register a read-write control holding one value per channel, starting out as `initial`.
+    fn add_value(
+        &mut self,
+        origin: Origin,
+        selector: u8,
+        layout: Layout,
+        channels: u8,
+        initial: i32,
+        ranges: &[Range],
+    ) {
+        let index = self.alloc(channels, initial);
+        self.add(
+            ControlEntry {
+                origin,
+                selector,
+                layout,
+                channels,
+                access: CONTROL_READ_WRITE,
+                ranges: 0..0,
+                storage: Storage::Values(index),
+            },
+            ranges,
+        );
+    }
+
+    /// Register a read-write control whose values the application can change as well, one per channel.
+    fn add_shared(
+        &mut self,
+        origin: Origin,
+        selector: u8,
+        layout: Layout,
+        values: &'a [AtomicI32],
+        ranges: &[Range],
+    ) {
+        self.add(
+            ControlEntry {
+                origin,
+                selector,
+                layout,
+                channels: values.len() as u8 - 1,
+                access: CONTROL_READ_WRITE,
+                ranges: 0..0,
+                storage: Storage::Shared(values),
+            },
+            ranges,
+        );
+    }
+
+    /// Register a read-only control of the master channel.
+    fn add_read_only(
+        &mut self,
+        origin: Origin,
+        selector: u8,
+        layout: Layout,
+        storage: Storage<'a>,
+    ) {
+        self.add(
+            ControlEntry {
+                origin,
+                selector,
+                layout,
+                channels: 0,
+                access: CONTROL_READ_ONLY,
+                ranges: 0..0,
+                storage,
+            },
+            &[],
+        );
+    }
+
+    /// Allocate one value per channel for a [`Storage::Values`] control.
+    fn alloc(&mut self, channels: u8, initial: i32) -> usize {
+        let index = self.values.len();
+        for _ in 0..=channels {
+            unwrap!(self.values.push(initial).ok());
+        }
+        index
+    }
+
+    fn add(&mut self, mut entry: ControlEntry<'a>, ranges: &[Range]) {
+        assert!(
+            self.find(entry.origin, entry.selector, 0).is_none(),
+            "Duplicate control"
+        );
+        let start = self.ranges.len();
+        unwrap!(self.ranges.extend_from_slice(ranges).ok());
+        entry.ranges = start..self.ranges.len();
+        unwrap!(self.entries.push(entry).ok());
+    }
+
+    fn find(&self, origin: Origin, selector: u8, channel: u8) -> Option<&ControlEntry<'a>> {
+        self.entries.iter().find(|entry| {
+            entry.origin == origin && entry.selector == selector && channel <= entry.channels
+        })
+    }
+
+    /// Answer a GET request, returns the length of the parameter block or `None` to stall.
+    fn get(
+        &self,
+        request: u8,
+        origin: Origin,
+        selector: u8,
+        channel: u8,
+        buf: &mut [u8],
+    ) -> Option<usize> {
+        let Some(entry) = self.find(origin, selector, channel) else {
+            info!("{}: unsupported CS: {} CN: {}", origin, selector, channel);
+            return None;
+        };
+
+        match (request, entry.layout) {
+            (CUR, layout) => match &entry.storage {
+                Storage::Constant(value) => encode_value(buf, layout, *value),
+                Storage::Values(index) => {
+                    encode_value(buf, layout, self.values[index + channel as usize])
+                }
+                Storage::Shared(values) => encode_value(
+                    buf,
+                    layout,
+                    values[channel as usize].load(Ordering::Relaxed),
+                ),
+                Storage::Flag(flag) => encode_flag(buf, flag),
+                Storage::Connectors(connected, cluster) => {
+                    encode_connectors(buf, cluster, connected.load(Ordering::Relaxed))
+                }
+                Storage::ValidAltSettings(sample_rates, bitmaps) => {
+                    let sample_rate =
+                        self.value(Origin::Entity(UAC2_ENTITY_CLOCK), CS_SAM_FREQ_CONTROL, 0)?;
+                    let i = sample_rates
+                        .iter()
+                        .position(|&rate| rate as i32 == sample_rate)?;
+                    //  bControlSize, bmValidAltSettings(5.2.5.2.2)
+                    copy_to_buf(buf, &[1, bitmaps[i]])
+                }
+            },
+            (RANGE, layout @ (Layout::Two | Layout::Three)) if !entry.ranges.is_empty() => {
+                encode_range(buf, layout, &self.ranges[entry.ranges.clone()])
+            }
+            _ => {
+                info!(
+                    "{}: unsupported request {} CS: {}",
+                    origin, request, selector
+                );
+                None
+            }
+        }
+    }
+
+    /// Apply a SET request, returns `false` to stall.
+    fn set(&mut self, request: u8, origin: Origin, selector: u8, channel: u8, data: &[u8]) -> bool {
+        let (storage, layout, ranges) = match self.find(origin, selector, channel) {
+            Some(ControlEntry {
+                access: CONTROL_READ_WRITE,
+                storage,
+                layout,
+                ranges,
+                ..
+            }) if request == CUR => (*storage, *layout, ranges.clone()),
+            _ => {
+                info!(
+                    "{}: unsupported request {} CS: {}",
+                    origin, request, selector
+                );
+                return false;
+            }
+        };
+
+        let Some(value) = decode_value(data, layout) else {
+            return false;
+        };
+        let ranges = &self.ranges[ranges];
+        if !ranges.is_empty() && !ranges.iter().any(|range| range.contains(value)) {
+            info!("{} CS: {}: value {} out of range", origin, selector, value);
+            return false;
+        }
+
+        info!("{} CS: {} CN: {}: {}", origin, selector, channel, value);
+        match storage {
+            Storage::Values(index) => self.values[index + channel as usize] = value,
+            Storage::Shared(values) => values[channel as usize].store(value, Ordering::Relaxed),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Current value of a [`Storage::Values`] or [`Storage::Shared`] control.
+    fn value(&self, origin: Origin, selector: u8, channel: u8) -> Option<i32> {
+        match self.find(origin, selector, channel)?.storage {
+            Storage::Values(index) => Some(self.values[index + channel as usize]),
+            Storage::Shared(values) => Some(values[channel as usize].load(Ordering::Relaxed)),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Control<'a> {
+    /// Only class-specific requests to the interfaces and streaming endpoints of the function are handled,
+    /// everything else is left to the other handlers of the device.
+    fn accepts(&self, req: &Request) -> bool {
+        let index = req.index as u8;
+        req.request_type == RequestType::Class
+            && match req.recipient {
+                Recipient::Interface => {
+                    [self.ac_interface, self.spk_interface, self.mic_interface].contains(&index)
+                }
+                Recipient::Endpoint => [self.spk_ep, self.mic_ep].contains(&index),
+                _ => false,
+            }
+    }
+
+    /// Entity, interface or endpoint addressed by an accepted request.
+    fn origin(&self, req: &Request) -> Origin {
+        let [index, entity_id] = req.index.to_le_bytes();
+        match req.recipient {
+            Recipient::Endpoint if index == self.spk_ep => Origin::Endpoint(Terminal::Speaker),
+            Recipient::Endpoint => Origin::Endpoint(Terminal::Microphone),
+            _ if index == self.ac_interface => Origin::Entity(entity_id),
+            _ if index == self.spk_interface => Origin::Interface(Terminal::Speaker),
+            _ => Origin::Interface(Terminal::Microphone),
+        }
+    }
+
+    fn extension_unit(&mut self, origin: Origin) -> Option<&mut ExtensionUnit<'a>> {
+        match origin {
+            Origin::Entity(entity_id) => self
+                .extension_units
+                .iter_mut()
+                .find(|xu| xu.id == entity_id),
+            Origin::Interface(_) | Origin::Endpoint(_) => None,
+        }
+    }
+
+    /// GET request, returns the length of the parameter block or `None` to stall.
+    fn get(&mut self, req: Request, buf: &mut [u8]) -> Option<usize> {
+        let [cn, cs] = req.value.to_le_bytes();
+        let origin = self.origin(&req);
+
+        info!(
+            "{}, CS: {}, CN:{}, Request: {}",
+            origin, cs, cn, req.request
+        );
+
+        if let (Origin::Endpoint(_), UAC1_GET_CUR, UAC1_SAMPLING_FREQ_CONTROL) =
+            (origin, req.request, cs)
+        {
+            let rate =
+                self.controls
+                    .value(Origin::Entity(UAC2_ENTITY_CLOCK), CS_SAM_FREQ_CONTROL, 0)?;
+            return copy_to_buf(buf, &rate.to_le_bytes()[..3]);
+        }
+
+        if let Some(xu) = self.extension_unit(origin) {
+            return extension_unit_get(xu, req.request, cs, cn, buf);
+        }
+        self.controls.get(req.request, origin, cs, cn, buf)
+    }
+
+    /// SET request, returns `false` to stall.
+    fn set(&mut self, req: Request, data: &[u8]) -> bool {
+        let [cn, cs] = req.value.to_le_bytes();
+        let origin = self.origin(&req);
+
+        //  UAC1 hosts set the sampling frequency on the endpoint with a 3 byte parameter block,
+        //  it shares its selector with the 1 byte pitch control
+        if let (Origin::Endpoint(_), CUR, UAC1_SAMPLING_FREQ_CONTROL, [b0, b1, b2]) =
+            (origin, req.request, cs, data)
+        {
+            let accepted = self.controls.set(
+                CUR,
+                Origin::Entity(UAC2_ENTITY_CLOCK),
+                CS_SAM_FREQ_CONTROL,
+                0,
+                &[*b0, *b1, *b2, 0],
+            );
+            if accepted {
+                self.shared.sample_rate_changed();
+            }
+            return accepted;
+        }
+
+        if let Some(xu) = self.extension_unit(origin) {
+            return extension_unit_set(xu, req.request, cs, cn, data);
+        }
+        if !self.controls.set(req.request, origin, cs, cn, data) {
+            return false;
+        }
+
+        if (origin, cs) == (Origin::Entity(UAC2_ENTITY_CLOCK), CS_SAM_FREQ_CONTROL) {
+            self.shared.sample_rate_changed();
+        }
+        true
+    }
+}
+
+pub struct AudioReaderWriter<'d, D: Driver<'d>> {
+    pub read_ep_spk: D::EndpointOut,
+    pub write_ep_mic: D::EndpointIn,
+    control: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> AudioReaderWriter<'d, D> {
+    pub fn split(self) -> (AudioReader<'d, D>, AudioWriter<'d, D>) {
+        (
+            AudioReader {
+                read_ep_spk: self.read_ep_spk,
+                control: self.control,
+            },
+            AudioWriter {
+                write_ep_mic: self.write_ep_mic,
+                control: self.control,
+            },
+        )
+    }
+}
+
+pub struct AudioReader<'d, D: Driver<'d>> {
+    read_ep_spk: D::EndpointOut,
+    control: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> AudioReader<'d, D> {
+    /// Read a packet. Empty packets count as underrun, packets larger than `buf` as overrun.
+    pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let result = self.read_ep_spk.read(buf).await;
+        match result {
+            Ok(0) => self.control.underrun(Terminal::Speaker),
+            Err(EndpointError::BufferOverflow) => self.control.overrun(Terminal::Speaker),
+            _ => {}
+        }
+        result
+    }
+
+    /// Report that the playback buffer of the application ran empty.
+    pub fn report_underrun(&self) {
+        self.control.underrun(Terminal::Speaker);
+    }
+
+    /// Report that the playback buffer of the application was full and samples were dropped.
+    pub fn report_overrun(&self) {
+        self.control.overrun(Terminal::Speaker);
+    }
+
+    pub async fn wait_enabled(&mut self) {
+        self.read_ep_spk.wait_enabled().await
+    }
+}
+
+pub struct AudioWriter<'d, D: Driver<'d>> {
+    write_ep_mic: D::EndpointIn,
+    control: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> AudioWriter<'d, D> {
+    /// Write a packet. Empty packets count as underrun, packets larger than the endpoint as overrun.
+    pub async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+        if buf.is_empty() {
+            self.control.underrun(Terminal::Microphone);
+        }
+        let result = self.write_ep_mic.write(buf).await;
+        if let Err(EndpointError::BufferOverflow) = result {
+            self.control.overrun(Terminal::Microphone);
+        }
+        result
+    }
+
+    /// Write an empty packet while there is nothing to capture, it is not counted as underrun.
+    pub async fn write_idle(&mut self) -> Result<(), EndpointError> {
+        self.write_ep_mic.write(&[]).await
+    }
+
+    /// Report that the capture buffer of the application ran empty.
+    pub fn report_underrun(&self) {
+        self.control.underrun(Terminal::Microphone);
+    }
+
+    /// Report that the capture buffer of the application was full and samples were dropped.
+    pub fn report_overrun(&self) {
+        self.control.overrun(Terminal::Microphone);
+    }
+
+    pub async fn wait_enabled(&mut self) {
+        self.write_ep_mic.wait_enabled().await
+    }
+}
+
+pub struct MidiReaderWriter<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    write_ep: D::EndpointIn,
+    cables: u8,
+}
+
+impl<'d, D: Driver<'d>> MidiReaderWriter<'d, D> {
+    pub fn split(self) -> (MidiReader<'d, D>, MidiWriter<'d, D>) {
+        (
+            MidiReader {
+                read_ep: self.read_ep,
+                buf: [0; MIDI_PACKET_SIZE as usize],
+                len: 0,
+                pos: 0,
+            },
+            MidiWriter {
+                write_ep: self.write_ep,
+                cables: self.cables,
+            },
+        )
+    }
+}
+
+/// A USB-MIDI Event Packet (USB MIDI 1.0 4 USB-MIDI Event Packets)
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MidiEvent {
+    /// Virtual cable of the event
+    pub cable: u8,
+    /// Code Index Number, classifies the MIDI message
+    pub code_index: u8,
+    data: [u8; 3],
+}
+
+impl MidiEvent {
+    /// MIDI bytes of the event, a System Exclusive message is split across several events.
+    pub fn message(&self) -> &[u8] {
+        &self.data[..MIDI_EVENT_SIZES[self.code_index as usize]]
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MidiError {
+    /// The cable is not configured
+    InvalidCable,
+    /// Not a sequence of complete MIDI 1.0 messages, running status is not supported
+    InvalidMessage,
+    Endpoint(EndpointError),
+}
+
+impl From<EndpointError> for MidiError {
+    fn from(error: EndpointError) -> Self {
+        MidiError::Endpoint(error)
+    }
+}
+
+pub struct MidiReader<'d, D: Driver<'d>> {
+    read_ep: D::EndpointOut,
+    buf: [u8; MIDI_PACKET_SIZE as usize],
+    len: usize,
+    pos: usize,
+}
+
+impl<'d, D: Driver<'d>> MidiReader<'d, D> {
+    /// Read the next event from the host, empty events are skipped.
+    pub async fn read(&mut self) -> Result<MidiEvent, EndpointError> {
+        loop {
+            while self.pos + 4 <= self.len {
+                let event = &self.buf[self.pos..self.pos + 4];
+                self.pos += 4;
+                let code_index = event[0] & 0x0F;
+                if MIDI_EVENT_SIZES[code_index as usize] == 0 {
+                    continue;
+                }
+                return Ok(MidiEvent {
+                    cable: event[0] >> 4,
+                    code_index,
+                    data: [event[1], event[2], event[3]],
+                });
+            }
+            self.len = self.read_ep.read(&mut self.buf).await?;
+            self.pos = 0;
+        }
+    }
+
+    pub async fn wait_enabled(&mut self) {
+        self.read_ep.wait_enabled().await
+    }
+}
+
+pub struct MidiWriter<'d, D: Driver<'d>> {
+    write_ep: D::EndpointIn,
+    cables: u8,
+}
+
+impl<'d, D: Driver<'d>> MidiWriter<'d, D> {
+    /// Send complete MIDI messages on `cable`. System Exclusive messages are split into events,
+    /// nothing is sent if `message` is invalid.
+    pub async fn write(&mut self, cable: u8, message: &[u8]) -> Result<(), MidiError> {
+        if cable >= self.cables {
+            return Err(MidiError::InvalidCable);
+        }
+        let events = MidiEncoder {
+            cable,
+            rest: message,
+            sysex: false,
+        };
+        if !events.clone().all(|event| event.is_some()) {
+            return Err(MidiError::InvalidMessage);
+        }
+
+        let mut packet = [0; MIDI_PACKET_SIZE as usize];
+        let mut len = 0;
+        for event in events.flatten() {
+            packet[len..len + 4].copy_from_slice(&event);
+            len += 4;
+            if len == packet.len() {
+                self.write_ep.write(&packet).await?;
+                len = 0;
+            }
+        }
+        if len > 0 {
+            self.write_ep.write(&packet[..len]).await?;
+        }
+        Ok(())
+    }
+
+    /// Send a single event, for example one received with [`MidiReader::read`].
+    pub async fn write_event(&mut self, event: &MidiEvent) -> Result<(), MidiError> {
+        if event.cable >= self.cables {
+            return Err(MidiError::InvalidCable);
+        }
+        let [data0, data1, data2] = event.data;
+        self.write_ep
+            .write(&[event.cable << 4 | event.code_index, data0, data1, data2])
+            .await?;
+        Ok(())
+    }
+
+    pub async fn wait_enabled(&mut self) {
+        self.write_ep.wait_enabled().await
+    }
+}
+
+/// Splits MIDI messages into USB-MIDI Event Packets, yields `None` for an invalid message.
+#[derive(Clone)]
+struct MidiEncoder<'m> {
+    cable: u8,
+    rest: &'m [u8],
+    /// Inside a System Exclusive message
+    sysex: bool,
+}
+
+impl Iterator for MidiEncoder<'_> {
+    type Item = Option<[u8; 4]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.is_empty() {
+            //  Unterminated System Exclusive message
+            let sysex = self.sysex;
+            self.sysex = false;
+            return sysex.then_some(None);
+        }
+
+        let Some((code_index, size)) = midi_code_index(self.rest, self.sysex) else {
+            self.rest = &[];
+            self.sysex = false;
+            return Some(None);
+        };
+        let mut event = [self.cable << 4 | code_index, 0, 0, 0];
+        event[1..1 + size].copy_from_slice(&self.rest[..size]);
+        self.rest = &self.rest[size..];
+        self.sysex = code_index == CIN_SYSEX;
+        Some(Some(event))
+    }
+}
+
+/// Sends the queued interrupts of the AudioControl interface to the host.
+pub struct Notifier<'d, D: Driver<'d>> {
+    conf_ep: D::EndpointIn,
+    ac_interface: u8,
+    spk_interface: u8,
+    mic_interface: u8,
+    spk_ep: u8,
+    mic_ep: u8,
+    control: &'d ControlShared,
+}
+
+impl<'d, D: Driver<'d>> Notifier<'d, D> {
+    pub async fn run(&mut self) -> ! {
+        loop {
+            self.conf_ep.wait_enabled().await;
+            loop {
+                let notification = self.control.next_notification().await;
+                let (info, index) = match notification.origin {
+                    Origin::Entity(entity_id) => (0b00, [self.ac_interface, entity_id]),
+                    Origin::Interface(Terminal::Speaker) => (0b00, [self.spk_interface, 0]),
+                    Origin::Interface(Terminal::Microphone) => (0b00, [self.mic_interface, 0]),
+                    Origin::Endpoint(Terminal::Speaker) => (0b10, [self.spk_ep, 0]),
+                    Origin::Endpoint(Terminal::Microphone) => (0b10, [self.mic_ep, 0]),
+                };
+                //  Interrupt Data Message(6.1)
+                let message = [
+                    info, //Class-specific, originated by an interface or endpoint
+                    CUR,  //Attribute
+                    notification.channel,
+                    notification.selector,
+                    index[0],
+                    index[1],
+                ];
+                if let Err(error) = self.conf_ep.write(&message).await {
+                    info!("Notification error {:#?}", error);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The class split into handles that own their endpoints, each can be moved into its own task.
+///
+/// Built with [`UAC2::new_static`] the handles are `'static`, so they can be passed to
+/// `#[embassy_executor::task]` functions. The shared state is `Sync`, the reader, writer and
+/// notifier may run on other executors than the USB device, including interrupt executors.
+pub struct Parts<'d, D: Driver<'d>> {
+    pub control: ControlChanged<'d>,
+    /// Speaker stream
+    pub reader: AudioReader<'d, D>,
+    /// Microphone stream
+    pub writer: AudioWriter<'d, D>,
+    /// Has to run for the host to see control changes made by the device
+    pub notifier: Notifier<'d, D>,
+    /// MIDIStreaming interface if [`Config::midi`] is set
+    pub midi: Option<MidiReaderWriter<'d, D>>,
+}
+
+pub struct UAC2<'d, D: Driver<'d>> {
+    conf_ep: D::EndpointIn,
+    ac_interface: u8,
+    spk_interface: u8,
+    mic_interface: u8,
+    read_ep_spk: D::EndpointOut,
+    write_ep_mic: D::EndpointIn,
+    midi: Option<MidiReaderWriter<'d, D>>,
+    control: &'d ControlShared,
+    topology: &'d Topology,
+}
+
+impl<'d, D: Driver<'d>> UAC2<'d, D> {
+    /// [`Config::composite_with_iads`] must be set, this will add an IAD descriptor.
+    /// [`Config::device_class`] = 0xEF
+    /// [`Config::device_sub_class`] = 0x02
+    /// [`Config::device_protocol`] = 0x01
+    pub fn new(builder: &mut Builder<'d, D>, state: &'d mut State<'d>, config: Config<'d>) -> Self {
+        assert!(
+            config.extension_units.len() <= MAX_EXTENSION_UNITS,
+            "Too many Extension Units"
+        );
+        let extension_unit_ids: Vec<u8, MAX_EXTENSION_UNITS> =
+            config.extension_units.iter().map(|xu| xu.id).collect();
+        validate_config(
+            &config.speaker,
+            &config.microphone,
+            config.sample_rates,
+            &extension_unit_ids,
+            config.midi.as_ref(),
+        );
+
+        let spk_channels = config.speaker.channels;
+        let mic_channels = config.microphone.channels;
+        let max_sample_rate = unwrap!(config.sample_rates.iter().copied().max());
+
+        //  String Descriptors, channel names have to be consecutive
+        let mut strings = Vec::new();
+        let function_string = alloc_string(builder, &mut strings, config.function_name);
+        let spk_terminal_string = string_index(alloc_string(
+            builder,
+            &mut strings,
+            config.speaker.terminal_name,
+        ));
+        let spk_channel_names = alloc_channel_names(
+            builder,
+            &mut strings,
+            config.speaker.channel_names,
+            spk_channels,
+        );
+        let mic_terminal_string = string_index(alloc_string(
+            builder,
+            &mut strings,
+            config.microphone.terminal_name,
+        ));
+        let mic_channel_names = alloc_channel_names(
+            builder,
+            &mut strings,
+            config.microphone.channel_names,
+            mic_channels,
+        );
+        let midi_string = alloc_string(
+            builder,
+            &mut strings,
+            config.midi.and_then(|midi| midi.name),
+        );
+        let spk_terminal_type = config
+            .speaker
+            .terminal_type
+            .map_or(OUTPUT_SPEAKER, u16::to_le_bytes);
+        let mic_terminal_type = config
+            .microphone
+            .terminal_type
+            .map_or(INPUT_MICROPHONE, u16::to_le_bytes);
+        let spk_channel_config = config.speaker.channel_config.to_le_bytes();
+        let mic_channel_config = config.microphone.channel_config.to_le_bytes();
+        let connector_controls = |stream: &StreamConfig| {
+            if stream.jack_detect {
+                CONTROL_READ_ONLY << 2 //Connector RO
+            } else {
+                0b00_00_00_00 //Controls connector none
+            }
+        };
+        let spk_connector_controls = connector_controls(&config.speaker);
+        let mic_connector_controls = connector_controls(&config.microphone);
+
+        let mut fun = builder.function(
+            AUDIO_FUNCTION,
+            FUNCTION_PROTOCOL_UNDEFINED,
+            AF_VERSION_02_00,
+        );
+        if let Some(index) = function_string {
+            fun.function_string(index);
+        }
+
+        //Standard AC Interface Descriptor(4.7.1)
+        let mut int = fun.interface();
+        let ac_interface = int.interface_number().into();
+        let mut alt_ac = int.alt_setting(AUDIO, AUDIOCONTROL, IP_VERSION_02_00, function_string);
+
+        //  The Output Terminal of the speaker follows the last Extension Unit
+        let spk_ot_source = config
+            .extension_units
+            .last()
+            .map_or(UAC2_ENTITY_SPK_FEATURE_UNIT, |xu| xu.id);
+        let fu_spk_len = feature_unit_len(spk_channels);
+
+        //  Class-Specific AC Interface Header Descriptor(4.7.2), wTotalLength includes all CS AC IF descriptors
+        let descr_buf_ac_len = (AC_HEADER_LEN
+            + CLOCK_SOURCE_LEN
+            + 2 * INPUT_TERMINAL_LEN
+            + fu_spk_len
+            + 2 * OUTPUT_TERMINAL_LEN
+            + config.extension_units.len() * EXTENSION_UNIT_LEN)
+            as u16;
+        let descr_buf_ac_len = descr_buf_ac_len.to_le_bytes();
+        alt_ac.descriptor(
+            CS_INTERFACE,
+            &[
+                HEADER,
+                0x00, //UAC Version BCD (2.0)
+                0x02, //
+                0x0A, //USB Audio 2.0, PRO-AUDIO
+                descr_buf_ac_len[0],
+                descr_buf_ac_len[1],
+                0,
+            ],
+        );
+
+        //  Clock Source Descriptor(4.7.2.1)
+        let descr_buf_clock: [u8; CLOCK_SOURCE_LEN] = [
+            CLOCK_SOURCE_LEN as u8,
+            CS_INTERFACE,
+            CLOCK_SOURCE,
+            UAC2_ENTITY_CLOCK, //Clocksource ID
+            0b0_11,            //internal programmable clock
+            0b01_11,           //frequency RW, validity RO
+            0,
+            0,
+        ];
+        alt_ac.descriptor(CS_INTERFACE, &descr_buf_clock[2..]);
+
+        //  Input Terminal Descriptor(4.7.2.4)
+        let descr_buf_it_spk: [u8; INPUT_TERMINAL_LEN] = [
+            INPUT_TERMINAL_LEN as u8,
+            CS_INTERFACE,
+            INPUT_TERMINAL,
+            UAC2_ENTITY_SPK_INPUT_TERMINAL, //Terminal ID
+            USB_STREAM[0],                  //Terminal Type
+            USB_STREAM[1],
+            0x00,                  //No associated terminal
+            UAC2_ENTITY_CLOCK,     //Clocksource ID
+            spk_channels,          //Logical audio channels
+            spk_channel_config[0], //Channel config
+            spk_channel_config[1],
+            spk_channel_config[2],
+            spk_channel_config[3],
+            spk_channel_names, //Channel names string index
+            0b00_00_00_00,     //Controls connector none
+            0b00_00,
+            0x00, //Terminal description string index
+        ];
+        alt_ac.descriptor(CS_INTERFACE, &descr_buf_it_spk[2..]);
+
+        //  Feature Unit Descriptor(4.7.2.8)
+        let mut descr_buf_fu_spk: Vec<u8, { feature_unit_len(MAX_CHANNELS) }> = Vec::new();
+        unwrap!(descr_buf_fu_spk
+            .extend_from_slice(&[
+                fu_spk_len as u8,
+                CS_INTERFACE,
+                FEATURE_UNIT,
+                UAC2_ENTITY_SPK_FEATURE_UNIT,   //Unit ID
+                UAC2_ENTITY_SPK_INPUT_TERMINAL, //Source ID
+            ])
+            .ok());
+        //  Controls of the master channel: Mute RW, Volume RW, Underflow RO, Overflow RO
+        unwrap!(descr_buf_fu_spk
+            .extend_from_slice(&[
+                0b00_00_11_11,
+                0x00,
+                0x00,
+                CONTROL_READ_ONLY << 4 | CONTROL_READ_ONLY << 2,
+            ])
+            .ok());
+        //  Controls of channel 1..n: Mute RW, Volume RW
+        for _ in 0..spk_channels {
+            unwrap!(descr_buf_fu_spk
+                .extend_from_slice(&[0b00_00_11_11, 0x00, 0x00, 0x00])
+                .ok());
+        }
+        unwrap!(descr_buf_fu_spk.push(0x00).ok()); //No String Descriptor
+        alt_ac.descriptor(CS_INTERFACE, &descr_buf_fu_spk[2..]);
+
+        //  Output Terminal Descriptor(4.7.2.5)
+        let descr_buf_ot_spk: [u8; OUTPUT_TERMINAL_LEN] = [
+            OUTPUT_TERMINAL_LEN as u8,
+            CS_INTERFACE,
+            OUTPUT_TERMINAL,
+            UAC2_ENTITY_SPK_OUTPUT_TERMINAL, // Terminal ID
+            spk_terminal_type[0],            //Terminal Type
+            spk_terminal_type[1],
+            0x00,                   //No associated terminal
+            spk_ot_source,          //Source ID
+            UAC2_ENTITY_CLOCK,      //Clocksource ID
+            spk_connector_controls, //Controls connector
+            0x00,
+            spk_terminal_string, //Terminal description string index
+        ];
+        alt_ac.descriptor(CS_INTERFACE, &descr_buf_ot_spk[2..]);
+
+        //  Input Terminal Descriptor(4.7.2.4)
+        let descr_buf_it_mic: [u8; INPUT_TERMINAL_LEN] = [
+            INPUT_TERMINAL_LEN as u8,
+            CS_INTERFACE,
+            INPUT_TERMINAL,
+            UAC2_ENTITY_MIC_INPUT_TERMINAL, //Terminal ID
+            mic_terminal_type[0],           //Terminal Type
+            mic_terminal_type[1],
+            0x00,                  //No associated terminal
+            UAC2_ENTITY_CLOCK,     //Clocksource ID
+            mic_channels,          //Logical audio channels
+            mic_channel_config[0], //Channel config
+            mic_channel_config[1],
+            mic_channel_config[2],
+            mic_channel_config[3],
+            mic_channel_names, //Channel names string index
+            mic_connector_controls | CONTROL_READ_ONLY << 4, //Controls connector, overload RO
+            0b00_00,
+            mic_terminal_string, //Terminal description string index
+        ];
+        alt_ac.descriptor(CS_INTERFACE, &descr_buf_it_mic[2..]);
+
+        //  Output Terminal Descriptor(4.7.2.5)
+        let descr_buf_ot_mic: [u8; OUTPUT_TERMINAL_LEN] = [
+            OUTPUT_TERMINAL_LEN as u8,
+            CS_INTERFACE,
+            OUTPUT_TERMINAL,
+            UAC2_ENTITY_MIC_OUTPUT_TERMINAL, // Terminal ID
+            USB_STREAM[0],                   //Terminal Type
+            USB_STREAM[1],
+            0x00,                           //No associated terminal
+            UAC2_ENTITY_MIC_INPUT_TERMINAL, //Source ID
+            UAC2_ENTITY_CLOCK,              //Clocksource ID
+            CONTROL_READ_ONLY << 6,         //Underflow RO
+            CONTROL_READ_ONLY,              //Overflow RO
+            0x00,                           //No string
+        ];
+        alt_ac.descriptor(CS_INTERFACE, &descr_buf_ot_mic[2..]);
+
+        //  Extension Unit Descriptors(4.7.2.12), chained after the speaker Feature Unit
+        let mut xu_source = UAC2_ENTITY_SPK_FEATURE_UNIT;
+        for xu in config.extension_units.iter() {
+            let code = xu.extension_code.to_le_bytes();
+            let descr_buf_xu: [u8; EXTENSION_UNIT_LEN] = [
+                EXTENSION_UNIT_LEN as u8, //Length 15+p for p=1
+                CS_INTERFACE,
+                EXTENSION_UNIT,
+                xu.id,   //Unit ID
+                code[0], //Extension code
+                code[1],
+                1,                     //1 input pin
+                xu_source,             //Source ID
+                spk_channels,          //Logical output channels
+                spk_channel_config[0], //Channel config
+                spk_channel_config[1],
+                spk_channel_config[2],
+                spk_channel_config[3],
+                spk_channel_names, //Channel names string index
+                0b00_00,           //No standard controls, vendor controls only
+                0x00,              //No String Descriptor
+            ];
+            alt_ac.descriptor(CS_INTERFACE, &descr_buf_xu[2..]);
+            xu_source = xu.id;
+        }
+
+        //  Standard AC Interrupt Endpoint Descriptor(4.8.2.1)
+        let conf_ep = alt_ac.endpoint_interrupt_in(INTERRUPT_PACKET_SIZE, 0x01);
+
+        //Streams for speaker
+        //  Standard AS Interface Descriptor(4.9.1)
+        let mut int_as_spk = fun.interface();
+        let spk_interface = int_as_spk.interface_number().into();
+
+        //  Interface 1, Alternate 0 - default alternate setting with 0 bandwidth
+        int_as_spk.alt_setting(AUDIO, AUDIOSTREAMING, IP_VERSION_02_00, None);
+
+        //  Class-Specific AS Interface Descriptor(4.9.2)
+        let descr_buf_header_as_spk = &[
+            AS_GENERAL,                                 //
+            UAC2_ENTITY_SPK_INPUT_TERMINAL,             //Connected Terminal
+            CONTROL_READ_ONLY << 2 | CONTROL_READ_ONLY, //Valid Alt Settings RO, Active Alt Setting RO
+            0x01,                                       //AUDIO_FORMAT_TYPE_I (1 byte)
+            0x01, //AUDIO_DATA_FORMAT_TYPE_I_PCM (4 bytes) 0x00000001   A.2.1 Audio Data Format Type I Bit Allocations
+            0x00, //
+            0x00, //
+            0x00, //
+            spk_channels, //Number of channels
+            spk_channel_config[0], //Channel config
+            spk_channel_config[1], //
+            spk_channel_config[2], //
+            spk_channel_config[3], //
+            spk_channel_names, //StringIndex Channel name
+        ];
+
+        //  Data Overrun RO, Data Underrun RO, Pitch RW for the adaptive endpoint
+        let spk_ep_controls = CONTROL_READ_ONLY << 4 | CONTROL_READ_ONLY << 2 | CONTROL_READ_WRITE;
+
+        //  Class-Specific AS Isochronous Audio Data Endpoint Descriptor(4.10.1.2)
+        let descr_ep_spk = &[
+            EP_GENERAL,      //
+            0x00,            //Non-max packet size okay
+            spk_ep_controls, //Controls
+            0x01,            //Lock Delay Unit (Milliseconds)
+            0x01,            //Lock Delay 1 ms, little-endian like every multi-byte field
+            0x00,            //
+        ];
+
+        //  Interface 1, Alternate 1..n - alternate interfaces for data streaming, one per format
+        let mut read_ep_spk: Option<D::EndpointOut> = None;
+        for format in config.speaker.formats {
+            let mut alt_as_spk =
+                int_as_spk.alt_setting(AUDIO, AUDIOSTREAMING, IP_VERSION_02_00, None);
+            alt_as_spk.descriptor(CS_INTERFACE, descr_buf_header_as_spk);
+
+            //  Type I Format Type Descriptor(2.3.1.6 - Audio Formats)
+            let descr_format_spk = &[
+                FORMAT_TYPE,
+                FORMAT_TYPE_I,         //Format Type
+                format.subslot_size,   //Subslot Size
+                format.bit_resolution, //Resolution
+            ];
+            alt_as_spk.descriptor(CS_INTERFACE, descr_format_spk);
+
+            //  Standard AS Isochronous Audio Data Endpoint Descriptor(4.10.1.1
+            let max_packet_size = unwrap!(endpoint_packet_size(
+                config.sample_rates,
+                &config.speaker,
+                format,
+                &config.microphone
+            ));
+            match read_ep_spk.as_mut() {
+                None => {
+                    read_ep_spk = Some(alt_as_spk.endpoint_isochronous_out(
+                        max_packet_size,
+                        1,
+                        SynchronizationType::Adaptive,
+                        UsageType::DataEndpoint,
+                        &[],
+                    ))
+                }
+                Some(read_ep_spk) => {
+                    alt_as_spk.endpoint_isochronous_out_allocated(
+                        max_packet_size,
+                        1,
+                        SynchronizationType::Adaptive,
+                        UsageType::DataEndpoint,
+                        &[],
+                        read_ep_spk,
+                    );
+                }
+            }
+
+            alt_as_spk.descriptor(CS_ENDPOINT, descr_ep_spk);
+        }
+        let read_ep_spk = unwrap!(read_ep_spk);
+
+        //Streams for mic
+        //  Standard AS Interface Descriptor(4.9.1)
+        let mut int_as_mic = fun.interface();
+        let mic_interface = int_as_mic.interface_number().into();
+
+        //  Interface 2, Alternate 0 - default alternate setting with 0 bandwidth
+        int_as_mic.alt_setting(AUDIO, AUDIOSTREAMING, IP_VERSION_02_00, None);
+
+        //  Class-Specific AS Interface Descriptor(4.9.2)
+        let descr_buf_header_as_mic = &[
+            AS_GENERAL,                                 //
+            UAC2_ENTITY_MIC_OUTPUT_TERMINAL,            //Connected Terminal
+            CONTROL_READ_ONLY << 2 | CONTROL_READ_ONLY, //Valid Alt Settings RO, Active Alt Setting RO
+            0x01,                                       //AUDIO_FORMAT_TYPE_I (1 byte)
+            0x01, //AUDIO_DATA_FORMAT_TYPE_I_PCM (4 bytes) 0x00000001   A.2.1 Audio Data Format Type I Bit Allocations
+            0x00, //
+            0x00, //
+            0x00, //
+            mic_channels, //Number of channels
+            mic_channel_config[0], //Channel config
+            mic_channel_config[1], //
+            mic_channel_config[2], //
+            mic_channel_config[3], //
+            mic_channel_names, //StringIndex Channel name
+        ];
+
+        //  Class-Specific AS Isochronous Audio Data Endpoint Descriptor(4.10.1.2)
+        let descr_ep_mic = &[
+            EP_GENERAL,                                      //
+            0x00,                                            //Non-max packet size okay
+            CONTROL_READ_ONLY << 4 | CONTROL_READ_ONLY << 2, //Data Overrun RO, Data Underrun RO
+            0x00,                                            //Lock Delay Unit undefined
+            0x00,                                            //Lock Delay undefined
+            0x00,                                            //
+        ];
+
+        //  Interface 2, Alternate 1..n - alternate interfaces for data streaming, one per format
+        let mut write_ep_mic: Option<D::EndpointIn> = None;
+        for format in config.microphone.formats {
+            let mut alt_as_mic =
+                int_as_mic.alt_setting(AUDIO, AUDIOSTREAMING, IP_VERSION_02_00, None);
+            alt_as_mic.descriptor(CS_INTERFACE, descr_buf_header_as_mic);
+
+            //  Type I Format Type Descriptor(2.3.1.6 - Audio Formats)
+            let descr_format_mic = &[
+                FORMAT_TYPE,
+                FORMAT_TYPE_I,         //Format Type
+                format.subslot_size,   //Subslot Size
+                format.bit_resolution, //Resolution
+            ];
+            alt_as_mic.descriptor(CS_INTERFACE, descr_format_mic);
+
+            //  Standard AS Isochronous Audio Data Endpoint Descriptor(4.10.1.1
+            let max_packet_size = unwrap!(endpoint_packet_size(
+                config.sample_rates,
+                &config.microphone,
+                format,
+                &config.speaker
+            ));
+            match write_ep_mic.as_mut() {
+                None => {
+                    write_ep_mic = Some(alt_as_mic.endpoint_isochronous_in(
+                        max_packet_size,
+                        1,
+                        SynchronizationType::Asynchronous,
+                        UsageType::DataEndpoint,
+                        &[],
+                    ))
+                }
+                Some(write_ep_mic) => {
+                    alt_as_mic.endpoint_isochronous_in_allocated(
+                        max_packet_size,
+                        1,
+                        SynchronizationType::Asynchronous,
+                        UsageType::DataEndpoint,
+                        &[],
+                        write_ep_mic,
+                    );
+                }
+            }
+
+            alt_as_mic.descriptor(CS_ENDPOINT, descr_ep_mic);
+        }
+        let write_ep_mic = unwrap!(write_ep_mic);
+
+        //MIDI
+        let mut midi_interface = None;
+        let midi = config.midi.map(|midi| {
+            //  Standard MS Interface Descriptor(USB MIDI 1.0 6.1.2.1)
+            let mut int_ms = fun.interface();
+            midi_interface = Some(int_ms.interface_number().into());
+            let mut alt_ms = int_ms.alt_setting(AUDIO, MIDISTREAMING, 0x00, midi_string);
+
+            //  Class-Specific MS Interface Header Descriptor(6.1.2.1), wTotalLength includes the jack descriptors
+            let descr_buf_ms_len = (MS_HEADER_LEN + midi.cables as usize * MIDI_JACKS_LEN) as u16;
+            let descr_buf_ms_len = descr_buf_ms_len.to_le_bytes();
+            alt_ms.descriptor(
+                CS_INTERFACE,
+                &[
+                    MS_HEADER,
+                    0x00, //MIDIStreaming Version BCD (1.0)
+                    0x01, //
+                    descr_buf_ms_len[0],
+                    descr_buf_ms_len[1],
+                ],
+            );
+
+            //  One embedded and one external jack per direction and cable, the IDs of cable n start at 1+4n
+            for cable in 0..midi.cables {
+                let jack = 1 + 4 * cable;
+                //  MIDI IN Jack Descriptor(6.1.2.2), fed by the OUT endpoint
+                alt_ms.descriptor(
+                    CS_INTERFACE,
+                    &[
+                        MIDI_IN_JACK,
+                        JACK_EMBEDDED,
+                        jack, //Jack ID
+                        0x00, //No String Descriptor
+                    ],
+                );
+                //  MIDI OUT Jack Descriptor(6.1.2.3), the physical MIDI OUT
+                alt_ms.descriptor(
+                    CS_INTERFACE,
+                    &[
+                        MIDI_OUT_JACK,
+                        JACK_EXTERNAL,
+                        jack + 1, //Jack ID
+                        1,        //1 input pin
+                        jack,     //Source ID
+                        1,        //Source pin
+                        0x00,     //No String Descriptor
+                    ],
+                );
+                //  MIDI IN Jack Descriptor(6.1.2.2), the physical MIDI IN
+                alt_ms.descriptor(
+                    CS_INTERFACE,
+                    &[
+                        MIDI_IN_JACK,
+                        JACK_EXTERNAL,
+                        jack + 2, //Jack ID
+                        0x00,     //No String Descriptor
+                    ],
+                );
+                //  MIDI OUT Jack Descriptor(6.1.2.3), feeding the IN endpoint
+                alt_ms.descriptor(
+                    CS_INTERFACE,
+                    &[
+                        MIDI_OUT_JACK,
+                        JACK_EMBEDDED,
+                        jack + 3, //Jack ID
+                        1,        //1 input pin
+                        jack + 2, //Source ID
+                        1,        //Source pin
+                        0x00,     //No String Descriptor
+                    ],
+                );
+            }
+
+            //  Standard MS Bulk Data Endpoint Descriptors(6.2.1) with the Class-Specific MS Bulk Data Endpoint Descriptors(6.2.2)
+            let mut descr_ep_ms: Vec<u8, { 2 + MAX_MIDI_CABLES as usize }> = Vec::new();
+            unwrap!(descr_ep_ms
+                .extend_from_slice(&[MS_GENERAL, midi.cables])
+                .ok());
+            let read_ep = alt_ms.endpoint_bulk_out(MIDI_PACKET_SIZE);
+            descr_ep_ms.extend((0..midi.cables).map(|cable| 1 + 4 * cable)); //Embedded MIDI IN Jacks
+            alt_ms.descriptor(CS_ENDPOINT, &descr_ep_ms);
+            descr_ep_ms.truncate(2);
+            let write_ep = alt_ms.endpoint_bulk_in(MIDI_PACKET_SIZE);
+            descr_ep_ms.extend((0..midi.cables).map(|cable| 4 + 4 * cable)); //Embedded MIDI OUT Jacks
+            alt_ms.descriptor(CS_ENDPOINT, &descr_ep_ms);
+
+            MidiReaderWriter {
+                read_ep,
+                write_ep,
+                cables: midi.cables,
+            }
+        });
+
+        let shared = &state.shared;
+        let mut controls = Controls::new();
+
+        let clock = Origin::Entity(UAC2_ENTITY_CLOCK);
+        let sample_rates: Vec<Range, MAX_SAMPLE_RATES> = config
+            .sample_rates
+            .iter()
+            .map(|&rate| Range {
+                min: rate as i32,
+                max: rate as i32,
+                res: 0,
+            })
+            .collect();
+        shared
+            .sample_rate
+            .store(max_sample_rate as i32, Ordering::Relaxed);
+        controls.add_shared(
+            clock,
+            CS_SAM_FREQ_CONTROL,
+            Layout::Three,
+            slice::from_ref(&shared.sample_rate),
+            &sample_rates,
+        );
+        controls.add_read_only(
+            clock,
+            CS_CLOCK_VALID_CONTROL,
+            Layout::One,
+            Storage::Constant(1),
+        );
+
+        let spk_fu = Origin::Entity(UAC2_ENTITY_SPK_FEATURE_UNIT);
+        controls.add_shared(
+            spk_fu,
+            FU_MUTE_CONTROL,
+            Layout::One,
+            &shared.spk_mute[..=spk_channels as usize],
+            &[BOOLEAN_RANGE],
+        );
+        controls.add_shared(
+            spk_fu,
+            FU_VOLUME_CONTROL,
+            Layout::Two,
+            &shared.spk_volume[..=spk_channels as usize],
+            &[VOLUME_RANGE],
+        );
+        controls.add_read_only(
+            spk_fu,
+            FU_UNDERFLOW_CONTROL,
+            Layout::One,
+            Storage::Flag(&shared.spk_status.underflow),
+        );
+        controls.add_read_only(
+            spk_fu,
+            FU_OVERFLOW_CONTROL,
+            Layout::One,
+            Storage::Flag(&shared.spk_status.overflow),
+        );
+        controls.add_read_only(
+            Origin::Entity(UAC2_ENTITY_SPK_OUTPUT_TERMINAL),
+            TE_CONNECTOR_CONTROL,
+            Layout::One,
+            Storage::Connectors(
+                &shared.spk_connected,
+                connectors_cluster(
+                    spk_channels,
+                    config.speaker.channel_config,
+                    spk_channel_names,
+                ),
+            ),
+        );
+
+        let mic_it = Origin::Entity(UAC2_ENTITY_MIC_INPUT_TERMINAL);
+        controls.add_read_only(
+            mic_it,
+            TE_CONNECTOR_CONTROL,
+            Layout::One,
+            Storage::Connectors(
+                &shared.mic_connected,
+                connectors_cluster(
+                    mic_channels,
+                    config.microphone.channel_config,
+                    mic_channel_names,
+                ),
+            ),
+        );
+        controls.add_read_only(
+            mic_it,
+            TE_OVERLOAD_CONTROL,
+            Layout::One,
+            Storage::Flag(&shared.mic_status.overload),
+        );
+        let mic_ot = Origin::Entity(UAC2_ENTITY_MIC_OUTPUT_TERMINAL);
+        controls.add_read_only(
+            mic_ot,
+            TE_UNDERFLOW_CONTROL,
+            Layout::One,
+            Storage::Flag(&shared.mic_status.underflow),
+        );
+        controls.add_read_only(
+            mic_ot,
+            TE_OVERFLOW_CONTROL,
+            Layout::One,
+            Storage::Flag(&shared.mic_status.overflow),
+        );
+
+        //  The adaptive speaker endpoint lets the host enable pitch control
+        controls.add_value(
+            Origin::Endpoint(Terminal::Speaker),
+            EP_PITCH_CONTROL,
+            Layout::One,
+            0,
+            0,
+            &[BOOLEAN_RANGE],
+        );
+        for (terminal, stream, other) in [
+            (Terminal::Speaker, &config.speaker, &config.microphone),
+            (Terminal::Microphone, &config.microphone, &config.speaker),
+        ] {
+            controls.add_read_only(
+                Origin::Interface(terminal),
+                AS_ACT_ALT_SETTING_CONTROL,
+                Layout::One,
+                Storage::Shared(slice::from_ref(shared.alt_setting(terminal))),
+            );
+            let mut bitmaps = [0; MAX_SAMPLE_RATES];
+            for (bitmap, &sample_rate) in bitmaps.iter_mut().zip(config.sample_rates) {
+                *bitmap = valid_alt_settings(sample_rate, stream, other);
+            }
+            controls.add_read_only(
+                Origin::Interface(terminal),
+                AS_VAL_ALT_SETTINGS_CONTROL,
+                Layout::One,
+                Storage::ValidAltSettings(config.sample_rates, bitmaps),
+            );
+        }
+        for terminal in [Terminal::Speaker, Terminal::Microphone] {
+            let status = shared.status(terminal);
+            controls.add_read_only(
+                Origin::Endpoint(terminal),
+                EP_DATA_OVERRUN_CONTROL,
+                Layout::One,
+                Storage::Flag(&status.data_overrun),
+            );
+            controls.add_read_only(
+                Origin::Endpoint(terminal),
+                EP_DATA_UNDERRUN_CONTROL,
+                Layout::One,
+                Storage::Flag(&status.data_underrun),
+            );
+        }
+
+        let topology = state.topology.write(Topology {
+            ac_interface,
+            clock_source: UAC2_ENTITY_CLOCK,
+            sample_rates: unwrap!(Vec::from_slice(config.sample_rates)),
+            speaker: stream_topology(
+                &config,
+                Terminal::Speaker,
+                spk_interface,
+                read_ep_spk.info().addr.into(),
+            ),
+            microphone: stream_topology(
+                &config,
+                Terminal::Microphone,
+                mic_interface,
+                write_ep_mic.info().addr.into(),
+            ),
+            extension_units: config.extension_units.iter().map(|xu| xu.id).collect(),
+            midi_interface,
+        });
+
+        let control = state.control.write(Control {
+            shared,
+            extension_units: config.extension_units,
+            strings,
+            controls,
+            spk_ep: read_ep_spk.info().addr.into(),
+            mic_ep: write_ep_mic.info().addr.into(),
+            ac_interface,
+            spk_interface,
+            mic_interface,
+        });
+
+        drop(fun);
+
+        builder.handler(control);
+
+        let control_shared = &state.shared;
+
+        UAC2 {
+            conf_ep,
+            ac_interface,
+            spk_interface,
+            mic_interface,
+            read_ep_spk,
+            write_ep_mic,
+            midi,
+            control: control_shared,
+            topology,
+        }
+    }
+
+    pub fn split(
+        self,
+    ) -> (
+        ControlChanged<'d>,
+        AudioReaderWriter<'d, D>,
+        Notifier<'d, D>,
+        Option<MidiReaderWriter<'d, D>>,
+    ) {
+        let notifier = Notifier {
+            conf_ep: self.conf_ep,
+            ac_interface: self.ac_interface,
+            spk_interface: self.spk_interface,
+            mic_interface: self.mic_interface,
+            spk_ep: self.read_ep_spk.info().addr.into(),
+            mic_ep: self.write_ep_mic.info().addr.into(),
+            control: self.control,
+        };
+        (
+            ControlChanged {
+                control: self.control,
+                topology: self.topology,
+            },
+            AudioReaderWriter {
+                read_ep_spk: self.read_ep_spk,
+                write_ep_mic: self.write_ep_mic,
+                control: self.control,
+            },
+            notifier,
+            self.midi,
+        )
+    }
+
+    /// Split the class into [`Parts`], with the speaker and microphone streams already separated.
+    pub fn into_parts(self) -> Parts<'d, D> {
+        let (control, reader_writer, notifier, midi) = self.split();
+        let (reader, writer) = reader_writer.split();
+        Parts {
+            control,
+            reader,
+            writer,
+            notifier,
+            midi,
+        }
+    }
+}
+
+impl<D: Driver<'static>> UAC2<'static, D> {
+    /// Build the class with its state in `state` and split it into `'static` [`Parts`].
+    ///
+    /// Panics if `state` has already been initialized.
+    pub fn new_static(
+        builder: &mut Builder<'static, D>,
+        state: &'static StaticCell<State<'static>>,
+        config: Config<'static>,
+    ) -> Parts<'static, D> {
+        Self::new(builder, state.init(State::new()), config).into_parts()
+    }
+}
+
+impl<'d> Handler for Control<'d> {
+    fn enabled(&mut self, _enabled: bool) {
+        info!("enabled");
+    }
+
+    fn reset(&mut self) {
+        info!("reset");
+    }
+
+    fn addressed(&mut self, _addr: u8) {
+        info!("addressed {}", _addr);
+    }
+
+    fn configured(&mut self, _configured: bool) {
+        info!("configured");
+    }
+
+    fn suspended(&mut self, _suspended: bool) {
+        info!("suspended");
+    }
+
+    fn remote_wakeup_enabled(&mut self, _enabled: bool) {
+        info!("remote_wakeup_enabled");
+    }
+    fn set_alternate_setting(&mut self, iface: InterfaceNumber, alternate_setting: u8) {
+        let iface: u8 = iface.into();
+        info!("set_alternate_setting {} {}", iface, alternate_setting);
+
+        let terminal = if iface == self.spk_interface {
+            Terminal::Speaker
+        } else if iface == self.mic_interface {
+            Terminal::Microphone
+        } else {
+            return;
+        };
+        self.shared
+            .alt_setting(terminal)
+            .store(alternate_setting as i32, Ordering::Relaxed);
+    }
+
+    fn get_string(&mut self, index: StringIndex, _lang_id: u16) -> Option<&str> {
+        self.strings
+            .iter()
+            .find(|(string_index, _)| *string_index == index)
+            .map(|(_, string)| *string)
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        info!("control_out {:#?}", req);
+
+        if self.set(req, data) {
+            Some(OutResponse::Accepted)
+        } else {
+            info!("Rejected!");
+            Some(OutResponse::Rejected)
+        }
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !self.accepts(&req) {
+            return None;
+        }
+        info!("control_in {:#?}", req);
+
+        match self.get(req, buf) {
+            //  Only the first wLength bytes of the parameter block are returned(5.2.2)
+            Some(len) => Some(InResponse::Accepted(&buf[..len.min(req.length as usize)])),
+            None => {
+                info!("Rejected!");
+                Some(InResponse::Rejected)
+            }
+        }
+    }
+}
+
+/// Length of a Feature Unit Descriptor with a master channel and `channels` logical channels (4.7.2.8)
+const fn feature_unit_len(channels: u8) -> usize {
+    6 + (channels as usize + 1) * 4
+}
+
+/// Maximum packet size of an isochronous endpoint at `sample_rate`, allowing one extra sample per frame.
+///
+/// Kept in `u32` so a packet beyond the full-speed limit cannot wrap into range before it is checked.
+const fn max_packet_size(sample_rate: u32, channels: u8, format: &Format) -> u32 {
+    (sample_rate / 1000 + 1) * channels as u32 * format.subslot_size as u32
+}
+
+/// Check the largest alternate settings against the full-speed isochronous limits and the RP2040 endpoint buffer memory.
+fn validate_packet_budget(
+    sample_rates: &[u32],
+    speaker: &StreamConfig,
+    microphone: &StreamConfig,
+    midi: bool,
+) -> Result<(), &'static str> {
+    for (stream, other) in [(speaker, microphone), (microphone, speaker)] {
+        for &sample_rate in sample_rates {
+            check(
+                stream
+                    .formats
+                    .iter()
+                    .any(|format| format_fits(sample_rate, stream, format, other)),
+                "No format fits the full-speed budget at a sample rate",
+            )?;
+        }
+        for format in stream.formats {
+            check(
+                endpoint_packet_size(sample_rates, stream, format, other).is_some(),
+                "Format does not fit the full-speed budget at any sample rate",
+            )?;
+        }
+    }
+
+    let largest = |stream: &StreamConfig, other: &StreamConfig| {
+        stream
+            .formats
+            .iter()
+            .filter_map(|format| endpoint_packet_size(sample_rates, stream, format, other))
+            .max()
+            .unwrap_or(0)
+    };
+    let blocks = |size: usize| size.div_ceil(RP2040_DPRAM_BLOCK) * RP2040_DPRAM_BLOCK;
+    let dpram = blocks(largest(speaker, microphone) as usize)
+        + blocks(largest(microphone, speaker) as usize)
+        + blocks(INTERRUPT_PACKET_SIZE as usize)
+        + if midi {
+            2 * blocks(MIDI_PACKET_SIZE as usize)
+        } else {
+            0
+        };
+    check(
+        dpram <= RP2040_DPRAM_ENDPOINT_BUFFERS,
+        "Endpoint buffers exceed the RP2040 USB DPRAM",
+    )
+}
+
+/// Whether `format` of `stream` fits a full-speed isochronous packet at `sample_rate`,
+/// leaving enough periodic bandwidth for the smallest format of the `other` stream.
+fn format_fits(
+    sample_rate: u32,
+    stream: &StreamConfig,
+    format: &Format,
+    other: &StreamConfig,
+) -> bool {
+    let packet = max_packet_size(sample_rate, stream.channels, format);
+    let other_packet = other
+        .formats
+        .iter()
+        .map(|format| max_packet_size(sample_rate, other.channels, format))
+        .min()
+        .unwrap_or(0);
+    let periodic = packet as usize
+        + other_packet as usize
+        + 2 * FS_ISO_TRANSACTION_OVERHEAD
+        + INTERRUPT_PACKET_SIZE as usize
+        + FS_INTERRUPT_TRANSACTION_OVERHEAD;
+    packet <= FS_ISO_MAX_PACKET_SIZE && periodic <= FS_PERIODIC_BYTES_PER_FRAME
+}
+
+/// Check a configuration the way [`UAC2::new`] does, for example from a build script.
+///
+/// Panics with the error of [`try_validate_config`].
+pub fn validate_config(
+    speaker: &StreamConfig,
+    microphone: &StreamConfig,
+    sample_rates: &[u32],
+    extension_unit_ids: &[u8],
+    midi: Option<&MidiConfig>,
+) {
+    unwrap!(try_validate_config(
+        speaker,
+        microphone,
+        sample_rates,
+        extension_unit_ids,
+        midi
+    ));
+}
+
+/// Check a configuration the way [`UAC2::new`] does, without panicking.
+///
+/// Returns a description of the first problem: a stream or sample rate count out of range,
+/// a format with an unsupported subslot size or bit resolution, an Extension Unit ID that is 0,
+/// duplicated or taken by a class entity, or a format set exceeding the full-speed bandwidth or
+/// the endpoint buffer memory.
+pub fn try_validate_config(
+    speaker: &StreamConfig,
+    microphone: &StreamConfig,
+    sample_rates: &[u32],
+    extension_unit_ids: &[u8],
+    midi: Option<&MidiConfig>,
+) -> Result<(), &'static str> {
+    check(
+        extension_unit_ids.len() <= MAX_EXTENSION_UNITS,
+        "Too many Extension Units",
+    )?;
+    for (i, id) in extension_unit_ids.iter().enumerate() {
+        check(
+            *id != 0 && !ENTITY_IDS.contains(id),
+            "Extension Unit ID collides with a class entity",
+        )?;
+        check(
+            !extension_unit_ids[..i].contains(id),
+            "Duplicate Extension Unit ID",
+        )?;
+    }
+
+    for stream in [speaker, microphone] {
+        check(
+            (1..=MAX_CHANNELS).contains(&stream.channels),
+            "Unsupported number of channels",
+        )?;
+        check(
+            stream.channel_config.count_ones() <= stream.channels as u32,
+            "More spatial locations than logical channels",
+        )?;
+        check(
+            stream.channel_names.is_empty()
+                || stream.channel_names.len() == stream.channels as usize,
+            "Channel names must be empty or name every logical channel",
+        )?;
+        check(
+            (1..=MAX_FORMATS).contains(&stream.formats.len()),
+            "Unsupported number of formats",
+        )?;
+        for format in stream.formats {
+            check(
+                (1..=4).contains(&format.subslot_size),
+                "Unsupported subslot size",
+            )?;
+            check(
+                (1..=8 * format.subslot_size).contains(&format.bit_resolution),
+                "Bit resolution exceeds the subslot size",
+            )?;
+        }
+        check(
+            stream
+                .terminal_type
+                .is_none_or(|terminal_type| terminal_type >> 8 != 0x01),
+            "USB Terminal Types are not physical terminals",
+        )?;
+    }
+    check(
+        (1..=MAX_SAMPLE_RATES).contains(&sample_rates.len()),
+        "Unsupported number of sample rates",
+    )?;
+    if let Some(midi) = midi {
+        check(
+            (1..=MAX_MIDI_CABLES).contains(&midi.cables),
+            "Unsupported number of MIDI cables",
+        )?;
+    }
+    validate_packet_budget(sample_rates, speaker, microphone, midi.is_some())
+}
+
+/// `Err(error)` unless `condition` holds.
+fn check(condition: bool, error: &'static str) -> Result<(), &'static str> {
+    if condition {
+        Ok(())
+    } else {
+        Err(error)
+    }
+}
+
+/// Endpoint size of the alternate setting of `format` of `stream`, the largest packet over the sample rates it is valid at.
+/// `None` if the format does not fit the full-speed budget at any of them.
+pub fn endpoint_packet_size(
+    sample_rates: &[u32],
+    stream: &StreamConfig,
+    format: &Format,
+    other: &StreamConfig,
+) -> Option<u16> {
+    sample_rates
+        .iter()
+        .filter(|&&sample_rate| format_fits(sample_rate, stream, format, other))
+        .map(|&sample_rate| max_packet_size(sample_rate, stream.channels, format))
+        .max()
+        .map(|size| size as u16)
+}
+
+/// bmValidAltSettings of a streaming interface at `sample_rate`, alternate setting 0 is always valid.
+fn valid_alt_settings(sample_rate: u32, stream: &StreamConfig, other: &StreamConfig) -> u8 {
+    stream
+        .formats
+        .iter()
+        .enumerate()
+        .filter(|(_, format)| format_fits(sample_rate, stream, format, other))
+        .fold(0b1, |bitmap, (i, _)| bitmap | 1 << (i + 1))
+}
+
+/// Connectors Cluster of a plugged-in terminal (5.2.5.1.2 Connector Control)
+fn connectors_cluster(channels: u8, channel_config: u32, channel_names: u8) -> [u8; 6] {
+    let config = channel_config.to_le_bytes();
+    [
+        channels,
+        config[0],
+        config[1],
+        config[2],
+        config[3],
+        channel_names,
+    ]
+}
+
+/// Write the Connectors Cluster, an unplugged terminal reports no channels.
+fn encode_connectors(buf: &mut [u8], cluster: &[u8; 6], connected: bool) -> Option<usize> {
+    if connected {
+        copy_to_buf(buf, cluster)
+    } else {
+        copy_to_buf(buf, &[0; 6])
+    }
+}
+
+/// Write a status flag as layout 1 CUR and clear it.
+fn encode_flag(buf: &mut [u8], flag: &AtomicBool) -> Option<usize> {
+    encode_value(buf, Layout::One, flag.swap(false, Ordering::Relaxed) as i32)
+}
+
+/// Terminals and alternate settings of the streaming interface of `terminal`.
+fn stream_topology(
+    config: &Config,
+    terminal: Terminal,
+    interface: u8,
+    endpoint: u8,
+) -> StreamTopology {
+    let (stream, other, input_terminal, feature_unit, output_terminal) = match terminal {
+        Terminal::Speaker => (
+            &config.speaker,
+            &config.microphone,
+            UAC2_ENTITY_SPK_INPUT_TERMINAL,
+            Some(UAC2_ENTITY_SPK_FEATURE_UNIT),
+            UAC2_ENTITY_SPK_OUTPUT_TERMINAL,
+        ),
+        Terminal::Microphone => (
+            &config.microphone,
+            &config.speaker,
+            UAC2_ENTITY_MIC_INPUT_TERMINAL,
+            None,
+            UAC2_ENTITY_MIC_OUTPUT_TERMINAL,
+        ),
+    };
+    StreamTopology {
+        interface,
+        endpoint,
+        channels: stream.channels,
+        channel_config: stream.channel_config,
+        input_terminal,
+        feature_unit,
+        output_terminal,
+        alt_settings: stream
+            .formats
+            .iter()
+            .map(|format| {
+                let size = endpoint_packet_size(config.sample_rates, stream, format, other);
+                (*format, unwrap!(size))
+            })
+            .collect(),
+    }
+}
+
+/// Code Index Number and size of the next USB-MIDI event of `message` (USB MIDI 1.0 Table 4-1),
+/// `sysex` continues a System Exclusive message.
+fn midi_code_index(message: &[u8], sysex: bool) -> Option<(u8, usize)> {
+    let status = *message.first()?;
+    if sysex || status == 0xF0 {
+        let chunk = &message[..message.len().min(3)];
+        return match chunk.iter().position(|&byte| byte == 0xF7) {
+            Some(end) => Some((CIN_SYSEX_END_1 + end as u8, end + 1)),
+            None if chunk.len() == 3 => Some((CIN_SYSEX, 3)),
+            None => None,
+        };
+    }
+
+    let (code_index, size) = match status {
+        0x80..=0xEF => (status >> 4, MIDI_EVENT_SIZES[(status >> 4) as usize]),
+        0xF1 | 0xF3 => (CIN_SYSTEM_COMMON_2, 2),
+        0xF2 => (CIN_SYSTEM_COMMON_3, 3),
+        0xF6 => (CIN_SYSEX_END_1, 1),
+        0xF8..=0xFF => (CIN_SINGLE_BYTE, 1),
+        _ => return None,
+    };
+    let data = message.get(1..size)?;
+    data.iter()
+        .all(|byte| byte & 0x80 == 0)
+        .then_some((code_index, size))
+}
+
+/// Allocate a string descriptor for `string`.
+fn alloc_string<'d, D: Driver<'d>>(
+    builder: &mut Builder<'d, D>,
+    strings: &mut Vec<(StringIndex, &'d str), MAX_STRINGS>,
+    string: Option<&'d str>,
+) -> Option<StringIndex> {
+    string.map(|string| {
+        let index = builder.string();
+        unwrap!(strings.push((index, string)).ok());
+        index
+    })
+}
+
+/// String index field of a descriptor, 0 for no string.
+#[inline]
+fn string_index(index: Option<StringIndex>) -> u8 {
+    index.map_or(0, u8::from)
+}
+
+/// Allocate consecutive string descriptors for the channel names, returns the index of the first one or 0 for no names.
+fn alloc_channel_names<'d, D: Driver<'d>>(
+    builder: &mut Builder<'d, D>,
+    strings: &mut Vec<(StringIndex, &'d str), MAX_STRINGS>,
+    names: &'d [&'d str],
+    channels: u8,
+) -> u8 {
+    debug_assert!(names.is_empty() || names.len() == channels as usize);
+    names.iter().fold(0, |first, name| {
+        let index = string_index(alloc_string(builder, strings, Some(name)));
+        if first == 0 {
+            index
+        } else {
+            first
+        }
+    })
+}
+
+fn extension_unit_get(
+    xu: &mut ExtensionUnit<'_>,
+    request: u8,
+    cs: u8,
+    cn: u8,
+    buf: &mut [u8],
+) -> Option<usize> {
+    let Some(layout) = xu.handler.layout(cs) else {
+        info!("XU {}: unsupported CS: {}", xu.id, cs);
+        return None;
+    };
+
+    match (request, layout) {
+        (CUR, _) => encode_value(buf, layout, xu.handler.get_cur(cs, cn)?),
+        (RANGE, Layout::Two | Layout::Three) => {
+            encode_range(buf, layout, &[xu.handler.get_range(cs, cn)?])
+        }
+        _ => {
+            info!("XU {}: unsupported request {} CS: {}", xu.id, request, cs);
+            None
+        }
+    }
+}
+
+fn extension_unit_set(
+    xu: &mut ExtensionUnit<'_>,
+    request: u8,
+    cs: u8,
+    cn: u8,
+    data: &[u8],
+) -> bool {
+    let value = match (request, xu.handler.layout(cs)) {
+        (CUR, Some(layout)) => decode_value(data, layout),
+        _ => None,
+    };
+
+    match value {
+        Some(value) => xu.handler.set_cur(cs, cn, value),
+        None => {
+            info!("XU {}: unsupported request {} CS: {}", xu.id, request, cs);
+            false
+        }
+    }
+}
+
+/// Write `value` in the wire format of `layout`, returns the number of bytes written or `None` if `buf` is too small.
+fn encode_value(buf: &mut [u8], layout: Layout, value: i32) -> Option<usize> {
+    copy_to_buf(buf, &value.to_le_bytes()[..layout.size()])
+}
+
+/// Read a SET CUR parameter block, which has to match the size of `layout` exactly. Layout 2 values are sign-extended.
+fn decode_value(data: &[u8], layout: Layout) -> Option<i32> {
+    if data.len() != layout.size() {
+        info!("Invalid parameter block length: {}", data.len());
+        return None;
+    }
+    match layout {
+        Layout::One => Some(data[0] as i32),
+        Layout::Two => Some(i16::from_le_bytes([data[0], data[1]]) as i32),
+        Layout::Three => Some(i32::from_le_bytes([data[0], data[1], data[2], data[3]])),
+    }
+}
+
+/// Write a RANGE parameter block (5.2.3.2 / 5.2.3.3), returns the number of bytes written or `None` if `buf` is too small.
+fn encode_range(buf: &mut [u8], layout: Layout, subranges: &[Range]) -> Option<usize> {
+    let mut len = copy_to_buf(buf, &(subranges.len() as u16).to_le_bytes())?;
+    for range in subranges {
+        for value in [range.min, range.max, range.res] {
+            len += encode_value(buf.get_mut(len..)?, layout, value)?;
+        }
+    }
+    Some(len)
+}
+
+#[inline]
+fn copy_to_buf(buf: &mut [u8], src: &[u8]) -> Option<usize> {
+    buf.get_mut(..src.len())?.copy_from_slice(src);
+    Some(src.len())
+}
+
+// UAC2 standard
+const AUDIO: u8 = 0x01;
+const AUDIOCONTROL: u8 = 0x01;
+const AUDIOSTREAMING: u8 = 0x02;
+const IP_VERSION_02_00: u8 = 0x20;
+
+const AUDIO_FUNCTION: u8 = AUDIO;
+const FUNCTION_PROTOCOL_UNDEFINED: u8 = 0x00;
+const AF_VERSION_02_00: u8 = IP_VERSION_02_00;
+
+const CS_INTERFACE: u8 = 0x24;
+const CS_ENDPOINT: u8 = 0x25;
+
+// USB MIDI 1.0
+const MIDISTREAMING: u8 = 0x03;
+
+const MS_HEADER: u8 = 0x01;
+const MIDI_IN_JACK: u8 = 0x02;
+const MIDI_OUT_JACK: u8 = 0x03;
+
+const MS_GENERAL: u8 = 0x01;
+
+//Descriptor lengths, the MIDI IN and OUT jacks of one cable take 6+9+6+9 bytes
+const MS_HEADER_LEN: usize = 7;
+const MIDI_JACKS_LEN: usize = 30;
+
+const JACK_EMBEDDED: u8 = 0x01;
+const JACK_EXTERNAL: u8 = 0x02;
+
+//Code Index Numbers (USB MIDI 1.0 4 USB-MIDI Event Packets)
+const CIN_SYSTEM_COMMON_2: u8 = 0x2;
+const CIN_SYSTEM_COMMON_3: u8 = 0x3;
+const CIN_SYSEX: u8 = 0x4;
+const CIN_SYSEX_END_1: u8 = 0x5;
+const CIN_SINGLE_BYTE: u8 = 0xF;
+
+//MIDI bytes per event for each Code Index Number, 0 for reserved ones
+const MIDI_EVENT_SIZES: [usize; 16] = [0, 0, 2, 3, 3, 1, 2, 3, 3, 3, 3, 3, 2, 2, 3, 1];
+
+const MIDI_PACKET_SIZE: u16 = 64;
+
+const AS_GENERAL: u8 = 0x01;
+const FORMAT_TYPE: u8 = 0x02;
+
+const EP_GENERAL: u8 = 0x01;
+
+const HEADER: u8 = 0x01;
+const INPUT_TERMINAL: u8 = 0x02;
+const OUTPUT_TERMINAL: u8 = 0x03;
+const FEATURE_UNIT: u8 = 0x06;
+const EXTENSION_UNIT: u8 = 0x09;
+const CLOCK_SOURCE: u8 = 0x0A;
+
+//Descriptor lengths, the Feature Unit length depends on the channels, see feature_unit_len
+const AC_HEADER_LEN: usize = 9;
+const CLOCK_SOURCE_LEN: usize = 8;
+const INPUT_TERMINAL_LEN: usize = 17;
+const OUTPUT_TERMINAL_LEN: usize = 12;
+const EXTENSION_UNIT_LEN: usize = 16;
+
+//Mute and pitch enable
+const BOOLEAN_RANGE: Range = Range {
+    min: 0,
+    max: 1,
+    res: 1,
+};
+
+//Volume in 1/256 dB (5.2.5.7.2 Volume Control)
+const VOLUME_RANGE: Range = Range {
+    min: -0x7FFF,
+    max: 0x7FFF,
+    res: 1,
+};
+
+//Interrupt Data Message size (6.1 Interrupt Data Message)
+const INTERRUPT_PACKET_SIZE: u16 = 6;
+const MAX_PENDING_NOTIFICATIONS: usize = 8;
+
+//Control bitmap values (4.1 bmControls)
+const CONTROL_READ_ONLY: u8 = 0b01;
+const CONTROL_READ_WRITE: u8 = 0b11;
+
+//Requests
+const CUR: u8 = 0x01;
+const RANGE: u8 = 0x02;
+
+//UAC1 requests and endpoint controls sent by UAC1 hosts (UAC1 5.2.1 and 5.2.3.2.3)
+const UAC1_GET_CUR: u8 = 0x81;
+const UAC1_SAMPLING_FREQ_CONTROL: u8 = 0x01;
+
+const TE_CONNECTOR_CONTROL: u8 = 0x02;
+const TE_OVERLOAD_CONTROL: u8 = 0x03;
+const TE_UNDERFLOW_CONTROL: u8 = 0x05;
+const TE_OVERFLOW_CONTROL: u8 = 0x06;
+
+const CS_SAM_FREQ_CONTROL: u8 = 0x01;
+const CS_CLOCK_VALID_CONTROL: u8 = 0x02;
+
+const FU_MUTE_CONTROL: u8 = 0x01;
+const FU_VOLUME_CONTROL: u8 = 0x02;
+const FU_UNDERFLOW_CONTROL: u8 = 0x0E;
+const FU_OVERFLOW_CONTROL: u8 = 0x0F;
+
+const AS_ACT_ALT_SETTING_CONTROL: u8 = 0x01;
+const AS_VAL_ALT_SETTINGS_CONTROL: u8 = 0x02;
+
+const EP_PITCH_CONTROL: u8 = 0x01;
+const EP_DATA_OVERRUN_CONTROL: u8 = 0x02;
+const EP_DATA_UNDERRUN_CONTROL: u8 = 0x03;
+
+//USB Terminal Types
+const USB_STREAM: [u8; 2] = 0x0101u16.to_le_bytes();
+
+//Input Terminal Types
+const INPUT_MICROPHONE: [u8; 2] = 0x0201u16.to_le_bytes();
+
+//OUTPUT Terminal Types
+const OUTPUT_SPEAKER: [u8; 2] = 0x0301u16.to_le_bytes();
+
+//Spatial locations (4.1 Audio Channel Cluster Descriptor)
+pub const CHANNEL_FRONT_LEFT: u32 = 1 << 0;
+pub const CHANNEL_FRONT_RIGHT: u32 = 1 << 1;
+pub const CHANNEL_FRONT_CENTER: u32 = 1 << 2;
+pub const CHANNEL_LOW_FREQUENCY_EFFECTS: u32 = 1 << 3;
+pub const CHANNEL_BACK_LEFT: u32 = 1 << 4;
+pub const CHANNEL_BACK_RIGHT: u32 = 1 << 5;
+pub const CHANNEL_FRONT_LEFT_OF_CENTER: u32 = 1 << 6;
+pub const CHANNEL_FRONT_RIGHT_OF_CENTER: u32 = 1 << 7;
+pub const CHANNEL_BACK_CENTER: u32 = 1 << 8;
+pub const CHANNEL_SIDE_LEFT: u32 = 1 << 9;
+pub const CHANNEL_SIDE_RIGHT: u32 = 1 << 10;
+pub const CHANNEL_TOP_CENTER: u32 = 1 << 11;
+pub const CHANNEL_TOP_FRONT_LEFT: u32 = 1 << 12;
+pub const CHANNEL_TOP_FRONT_CENTER: u32 = 1 << 13;
+pub const CHANNEL_TOP_FRONT_RIGHT: u32 = 1 << 14;
+pub const CHANNEL_TOP_BACK_LEFT: u32 = 1 << 15;
+pub const CHANNEL_TOP_BACK_CENTER: u32 = 1 << 16;
+pub const CHANNEL_TOP_BACK_RIGHT: u32 = 1 << 17;
+pub const CHANNEL_TOP_FRONT_LEFT_OF_CENTER: u32 = 1 << 18;
+pub const CHANNEL_TOP_FRONT_RIGHT_OF_CENTER: u32 = 1 << 19;
+pub const CHANNEL_LEFT_LOW_FREQUENCY_EFFECTS: u32 = 1 << 20;
+pub const CHANNEL_RIGHT_LOW_FREQUENCY_EFFECTS: u32 = 1 << 21;
+pub const CHANNEL_TOP_SIDE_LEFT: u32 = 1 << 22;
+pub const CHANNEL_TOP_SIDE_RIGHT: u32 = 1 << 23;
+pub const CHANNEL_BOTTOM_CENTER: u32 = 1 << 24;
+pub const CHANNEL_BACK_LEFT_OF_CENTER: u32 = 1 << 25;
+pub const CHANNEL_BACK_RIGHT_OF_CENTER: u32 = 1 << 26;
+pub const CHANNEL_RAW_DATA: u32 = 1 << 31;
+
+//FORMAT Type Codes
+const FORMAT_TYPE_I: u8 = 0x01;
+
+//Demo constants
+
+// Unit numbers are arbitrary selected
+const UAC2_ENTITY_CLOCK: u8 = 0x04;
+// Speaker path
+const UAC2_ENTITY_SPK_INPUT_TERMINAL: u8 = 0x01;
+const UAC2_ENTITY_SPK_FEATURE_UNIT: u8 = 0x02;
+const UAC2_ENTITY_SPK_OUTPUT_TERMINAL: u8 = 0x03;
+// Microphone path
+const UAC2_ENTITY_MIC_INPUT_TERMINAL: u8 = 0x11;
+const UAC2_ENTITY_MIC_OUTPUT_TERMINAL: u8 = 0x13;
+
+pub const MAX_CHANNELS: u8 = 8;
+
+/// Alternate settings 1 to n have to fit the one byte bmValidAltSettings bitmap.
+pub const MAX_FORMATS: usize = 7;
+
+/// Limited by the RANGE reply of the sampling frequency control, 2 + 12 * n bytes have to fit the 64 byte control buffer.
+pub const MAX_SAMPLE_RATES: usize = 5;
+
+// Clock source, speaker Feature Unit, terminal status, AS interface and endpoint controls
+const MAX_CONTROLS: usize = 21;
+// Pitch
+const MAX_CONTROL_VALUES: usize = 1;
+// One subrange per sampling frequency, mute, volume and pitch
+const MAX_CONTROL_RANGES: usize = MAX_SAMPLE_RATES + 3;
+
+pub const MAX_EXTENSION_UNITS: usize = 8;
+
+/// The Cable Number of a USB-MIDI event is 4 bits.
+pub const MAX_MIDI_CABLES: u8 = 16;
+
+// Function name, two terminal names, the MIDI interface name and one name per channel
+const MAX_STRINGS: usize = 4 + 2 * MAX_CHANNELS as usize;
+
+// Full-speed isochronous limits (USB 2.0 5.6.3 and 5.6.4)
+const FS_ISO_MAX_PACKET_SIZE: u32 = 1023;
+const FS_PERIODIC_BYTES_PER_FRAME: usize = 1500 * 90 / 100;
+const FS_ISO_TRANSACTION_OVERHEAD: usize = 9;
+const FS_INTERRUPT_TRANSACTION_OVERHEAD: usize = 13;
+
+// RP2040 USB DPRAM minus the registers and EP0 buffers, endpoint buffers are allocated in 64 byte blocks
+const RP2040_DPRAM_ENDPOINT_BUFFERS: usize = 4096 - 0x180;
+const RP2040_DPRAM_BLOCK: usize = 64;
+
+const ENTITY_IDS: [u8; 6] = [
+    UAC2_ENTITY_CLOCK,
+    UAC2_ENTITY_SPK_INPUT_TERMINAL,
+    UAC2_ENTITY_SPK_FEATURE_UNIT,
+    UAC2_ENTITY_SPK_OUTPUT_TERMINAL,
+    UAC2_ENTITY_MIC_INPUT_TERMINAL,
+    UAC2_ENTITY_MIC_OUTPUT_TERMINAL,
+];
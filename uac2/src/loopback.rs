@@ -0,0 +1,359 @@
+//! Speaker-to-microphone loopback with a round-trip latency measurement.
+//!
+//! A [`Loopback`] is shared by the two stream tasks: its [`LoopbackSink`] buffers the speaker frames and its
+//! [`LoopbackSource`] plays them on the microphone, mapped to the microphone channels by the [`Downmix`].
+
+use core::cell::RefCell;
+use core::convert::Infallible;
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::blocking_mutex::Mutex;
+use heapless::Deque;
+
+use crate::{AudioSink, AudioSource, StreamFormat, MAX_CHANNELS};
+
+/// Mapping of the speaker channels to the microphone channels.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Downmix {
+    /// The average of all speaker channels on every microphone channel
+    Mono,
+    /// Speaker channel n on microphone channel n, the speaker channels repeat if the microphone has more
+    Direct,
+    /// One speaker channel, counted from 0, on every microphone channel
+    Channel(u8),
+}
+
+impl Downmix {
+    /// Sample of microphone `channel` from the samples of a speaker frame.
+    fn mix(&self, frame: &[i32], channel: usize) -> i32 {
+        match *self {
+            Downmix::Mono => {
+                let sum: i64 = frame.iter().map(|&sample| sample as i64).sum();
+                (sum / frame.len() as i64) as i32
+            }
+            Downmix::Direct => frame[channel % frame.len()],
+            Downmix::Channel(channel) => frame.get(channel as usize).copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Result of the latency measurement started with [`Loopback::measure_latency`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Latency {
+    /// No measurement was started
+    Idle,
+    /// The impulse is on its way through the host
+    Measuring,
+    /// Frames from the impulse leaving on the microphone to its return on the speaker
+    Measured(u32),
+    /// The impulse did not return within a second, or the speaker stream stopped
+    TimedOut,
+}
+
+/// The speaker frames of a packet did not fit into the loopback buffer, the oldest frames were dropped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Overrun;
+
+/// Loopback buffer of `N` samples shared by a [`LoopbackSink`] and a [`LoopbackSource`].
+///
+/// The buffer holds interleaved speaker frames, it needs room for a few packets of the largest speaker format.
+/// The source starts playing once [`LOOPBACK_PREFILL_MS`] of frames are buffered and starts over after an underrun.
+///
+/// For the latency measurement the host has to loop the microphone back to the speaker. The source sends an
+/// impulse instead of the buffered frames, the sink counts the speaker frames until it returns.
+pub struct Loopback<const N: usize> {
+    inner: Mutex<CriticalSectionRawMutex, RefCell<Inner<N>>>,
+}
+
+/// Buffered time before the source starts playing, covering the jitter between the two streams.
+pub const LOOPBACK_PREFILL_MS: u32 = 2;
+
+/// Level of the measurement impulse, half of full scale.
+const IMPULSE: i32 = i32::MAX / 2;
+/// A speaker sample above this level is the returning impulse, allowing 18 dB of attenuation on the host.
+const IMPULSE_THRESHOLD: u32 = (IMPULSE / 8) as u32;
+
+struct Inner<const N: usize> {
+    samples: Deque<i32, N>,
+    /// Format of the running speaker stream
+    speaker: Option<StreamFormat>,
+    /// The microphone stream is running, the speaker frames are only buffered while it is
+    capturing: bool,
+    /// The source plays from the buffer, cleared until the buffer is filled again
+    primed: bool,
+    downmix: Downmix,
+    /// Speaker frames since the start, the time base of the measurement
+    played: u32,
+    measurement: Measurement,
+}
+
+#[derive(Clone, Copy)]
+enum Measurement {
+    /// The source sends the impulse with its next packet while the speaker stream runs
+    Armed,
+    /// The impulse was sent at speaker frame `sent`
+    Sent {
+        sent: u32,
+    },
+    Finished(Latency),
+}
+
+impl<const N: usize> Loopback<N> {
+    pub const fn new(downmix: Downmix) -> Self {
+        Self {
+            inner: Mutex::new(RefCell::new(Inner {
+                samples: Deque::new(),
+                speaker: None,
+                capturing: false,
+                primed: false,
+                downmix,
+                played: 0,
+                measurement: Measurement::Finished(Latency::Idle),
+            })),
+        }
+    }
+
+    /// The sink for the speaker stream, one per loopback.
+    pub fn sink(&self) -> LoopbackSink<'_, N> {
+        LoopbackSink {
+            loopback: self,
+            format: None,
+        }
+    }
+
+    /// The source for the microphone stream, one per loopback.
+    pub fn source(&self) -> LoopbackSource<'_, N> {
+        LoopbackSource {
+            loopback: self,
+            format: None,
+        }
+    }
+
+    pub fn downmix(&self) -> Downmix {
+        self.lock(|inner| inner.downmix)
+    }
+
+    pub fn set_downmix(&self, downmix: Downmix) {
+        self.lock(|inner| inner.downmix = downmix)
+    }
+
+    /// Buffered samples and the capacity of the buffer.
+    pub fn fill(&self) -> (usize, usize) {
+        self.lock(|inner| (inner.samples.len(), N))
+    }
+
+    /// Send an impulse on the microphone and wait for it to return on the speaker, see [`latency`](Self::latency).
+    /// The loopback is silent until the measurement finishes.
+    pub fn measure_latency(&self) {
+        self.lock(|inner| inner.measurement = Measurement::Armed)
+    }
+
+    pub fn latency(&self) -> Latency {
+        self.lock(|inner| match inner.measurement {
+            Measurement::Armed | Measurement::Sent { .. } => Latency::Measuring,
+            Measurement::Finished(latency) => latency,
+        })
+    }
+
+    fn lock<R>(&self, f: impl FnOnce(&mut Inner<N>) -> R) -> R {
+        self.inner.lock(|inner| f(&mut inner.borrow_mut()))
+    }
+}
+
+impl<const N: usize> Inner<N> {
+    fn measuring(&self) -> bool {
+        matches!(
+            self.measurement,
+            Measurement::Armed | Measurement::Sent { .. }
+        )
+    }
+
+    /// Start over with an empty buffer.
+    fn restart(&mut self) {
+        self.samples.clear();
+        self.primed = false;
+    }
+
+    fn speaker_changed(&mut self, speaker: Option<StreamFormat>) {
+        self.speaker = speaker;
+        self.restart();
+        if let Measurement::Sent { .. } = self.measurement {
+            self.measurement = Measurement::Finished(Latency::TimedOut);
+        }
+    }
+
+    /// Advance the time base by a speaker frame peaking at `peak`.
+    fn played_frame(&mut self, peak: u32, sample_rate: u32) {
+        if let Measurement::Sent { sent } = self.measurement {
+            let elapsed = self.played.wrapping_sub(sent);
+            let latency = if peak >= IMPULSE_THRESHOLD {
+                Some(Latency::Measured(elapsed))
+            } else if elapsed > sample_rate {
+                Some(Latency::TimedOut)
+            } else {
+                None
+            };
+            if let Some(latency) = latency {
+                info!("Loopback latency {}", latency);
+                self.measurement = Measurement::Finished(latency);
+                self.restart();
+            }
+        }
+        self.played = self.played.wrapping_add(1);
+    }
+
+    /// Fill the microphone frames, returns the bytes filled. Less than `frames` is an underrun.
+    fn fill(&mut self, format: StreamFormat, frames: &mut [u8]) -> usize {
+        let subslot_size = format.format.subslot_size as usize;
+        let frame_size = format.frame_size();
+        let speaker = match (self.measurement, self.speaker) {
+            (Measurement::Armed, Some(_)) => {
+                frames.fill(0);
+                if let Some(frame) = frames.chunks_exact_mut(frame_size).next() {
+                    for subslot in frame.chunks_exact_mut(subslot_size) {
+                        format.format.encode(IMPULSE, subslot);
+                    }
+                }
+                self.measurement = Measurement::Sent { sent: self.played };
+                return frames.len();
+            }
+            (Measurement::Sent { .. }, _) | (_, None) => {
+                frames.fill(0);
+                return frames.len();
+            }
+            (_, Some(speaker)) => speaker,
+        };
+
+        let channels = speaker.channels as usize;
+        if !self.primed {
+            let prefill = (speaker.sample_rate * LOOPBACK_PREFILL_MS / 1000) as usize * channels;
+            if self.samples.len() < prefill {
+                frames.fill(0);
+                return frames.len();
+            }
+            self.primed = true;
+        }
+
+        let mut input = [0; MAX_CHANNELS as usize];
+        for (i, frame) in frames.chunks_exact_mut(frame_size).enumerate() {
+            if self.samples.len() < channels {
+                self.primed = false;
+                return i * frame_size;
+            }
+            for sample in &mut input[..channels] {
+                *sample = self.samples.pop_front().unwrap_or(0);
+            }
+            for (channel, subslot) in frame.chunks_exact_mut(subslot_size).enumerate() {
+                format
+                    .format
+                    .encode(self.downmix.mix(&input[..channels], channel), subslot);
+            }
+        }
+        frames.len()
+    }
+}
+
+/// Speaker side of a [`Loopback`].
+pub struct LoopbackSink<'a, const N: usize> {
+    loopback: &'a Loopback<N>,
+    format: Option<StreamFormat>,
+}
+
+impl<const N: usize> AudioSink for LoopbackSink<'_, N> {
+    type Error = Overrun;
+
+    async fn start(&mut self, format: StreamFormat) -> Result<(), Overrun> {
+        self.format = Some(format);
+        self.loopback
+            .lock(|inner| inner.speaker_changed(Some(format)));
+        Ok(())
+    }
+
+    async fn write(&mut self, frames: &[u8]) -> Result<(), Overrun> {
+        let Some(format) = self.format else {
+            return Ok(());
+        };
+        let subslot_size = format.format.subslot_size as usize;
+        let channels = format.channels as usize;
+        self.loopback.lock(|inner| {
+            let mut overrun = false;
+            for frame in frames.chunks_exact(format.frame_size()) {
+                let store = inner.capturing && !inner.measuring();
+                if store && N - inner.samples.len() < channels {
+                    for _ in 0..channels {
+                        inner.samples.pop_front();
+                    }
+                    overrun = true;
+                }
+                let mut peak = 0;
+                for subslot in frame.chunks_exact(subslot_size) {
+                    let sample = format.format.decode(subslot);
+                    peak = peak.max(sample.unsigned_abs());
+                    if store {
+                        let _ = inner.samples.push_back(sample);
+                    }
+                }
+                inner.played_frame(peak, format.sample_rate);
+            }
+            if overrun {
+                Err(Overrun)
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    async fn stop(&mut self) {
+        self.format = None;
+        self.loopback.lock(|inner| inner.speaker_changed(None));
+    }
+
+    /// The buffered frames.
+    fn latency(&self) -> u32 {
+        let channels = self.format.map_or(1, |format| format.channels as usize);
+        self.loopback
+            .lock(|inner| (inner.samples.len() / channels) as u32)
+    }
+}
+
+/// Microphone side of a [`Loopback`].
+pub struct LoopbackSource<'a, const N: usize> {
+    loopback: &'a Loopback<N>,
+    format: Option<StreamFormat>,
+}
+
+impl<const N: usize> AudioSource for LoopbackSource<'_, N> {
+    type Error = Infallible;
+
+    async fn start(&mut self, format: StreamFormat) -> Result<(), Infallible> {
+        self.format = Some(format);
+        self.loopback.lock(|inner| {
+            inner.capturing = true;
+            inner.restart();
+        });
+        Ok(())
+    }
+
+    async fn read(&mut self, frames: &mut [u8]) -> Result<usize, Infallible> {
+        let Some(format) = self.format else {
+            return Ok(0);
+        };
+        Ok(self.loopback.lock(|inner| inner.fill(format, frames)))
+    }
+
+    async fn stop(&mut self) {
+        self.format = None;
+        self.loopback.lock(|inner| {
+            inner.capturing = false;
+            inner.restart();
+        });
+    }
+
+    /// The frames come straight out of the buffer, its delay is on the sink side.
+    fn latency(&self) -> u32 {
+        0
+    }
+}
@@ -0,0 +1,234 @@
+//! Backends of the audio streams and the pumps moving packets between them and the endpoints.
+//!
+//! An I2S codec, a PWM output, a test generator or a WAV file implements [`AudioSink`] or
+//! [`AudioSource`], [`pump_playback`] and [`pump_capture`] take care of the USB side.
+
+use embassy_usb::driver::Driver;
+
+use crate::{AudioReader, AudioWriter, ControlChanged, Format, Terminal};
+
+/// Format of a running stream, negotiated by the host through the clock and the alternate setting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StreamFormat {
+    /// Sampling frequency in Hz
+    pub sample_rate: u32,
+    /// Logical channels of a frame
+    pub channels: u8,
+    /// Subslot size and bit resolution of the samples
+    pub format: Format,
+}
+
+impl StreamFormat {
+    /// Format of the active alternate setting of `terminal`, `None` while the host has the stream closed.
+    pub fn active(control: &ControlChanged, terminal: Terminal) -> Option<Self> {
+        let stream = match terminal {
+            Terminal::Speaker => &control.topology().speaker,
+            Terminal::Microphone => &control.topology().microphone,
+        };
+        Some(Self {
+            sample_rate: control.sample_rate(),
+            channels: stream.channels,
+            format: control.format(terminal)?,
+        })
+    }
+
+    /// Bytes per frame, one subslot per channel.
+    pub fn frame_size(&self) -> usize {
+        self.channels as usize * self.format.subslot_size as usize
+    }
+}
+
+impl Format {
+    /// Store a sample, full scale over the `i32` range, in a subslot: little-endian and MSB-justified,
+    /// the bits below the bit resolution are cleared.
+    pub fn encode(&self, sample: i32, subslot: &mut [u8]) {
+        let size = self.subslot_size as usize;
+        let bytes = (sample & self.mask()).to_le_bytes();
+        subslot[..size].copy_from_slice(&bytes[4 - size..]);
+    }
+
+    /// Read the sample of a subslot, full scale over the `i32` range.
+    pub fn decode(&self, subslot: &[u8]) -> i32 {
+        let size = self.subslot_size as usize;
+        let mut bytes = [0; 4];
+        bytes[4 - size..].copy_from_slice(&subslot[..size]);
+        i32::from_le_bytes(bytes) & self.mask()
+    }
+
+    fn mask(&self) -> i32 {
+        (!0u32)
+            .checked_shl(32 - self.bit_resolution as u32)
+            .unwrap_or(0) as i32
+    }
+}
+
+/// Consumer of the playback stream, for example a codec on I2S.
+///
+/// Frames are interleaved little-endian subslots in the [`StreamFormat`] given to [`start`](Self::start).
+#[allow(async_fn_in_trait)]
+pub trait AudioSink {
+    type Error;
+
+    /// Whether the sink can play `format`, the stream stays silent for formats it cannot.
+    fn supports(&self, format: &StreamFormat) -> bool {
+        let _ = format;
+        true
+    }
+
+    /// The host started streaming in `format`.
+    async fn start(&mut self, format: StreamFormat) -> Result<(), Self::Error>;
+
+    /// Consume the frames of one packet. An error counts as an overrun of the stream.
+    async fn write(&mut self, frames: &[u8]) -> Result<(), Self::Error>;
+
+    /// The host closed the stream or changed its format.
+    async fn stop(&mut self);
+
+    /// Delay in frames from [`write`](Self::write) to the output.
+    fn latency(&self) -> u32;
+}
+
+/// Producer of the capture stream, for example an ADC or a signal generator.
+///
+/// Frames are interleaved little-endian subslots in the [`StreamFormat`] given to [`start`](Self::start).
+#[allow(async_fn_in_trait)]
+pub trait AudioSource {
+    type Error;
+
+    /// Whether the source can produce `format`, the stream is sent empty for formats it cannot.
+    fn supports(&self, format: &StreamFormat) -> bool {
+        let _ = format;
+        true
+    }
+
+    /// The host started streaming in `format`.
+    async fn start(&mut self, format: StreamFormat) -> Result<(), Self::Error>;
+
+    /// Fill `frames` with the frames of one packet, returns the number of bytes filled.
+    /// Filling less or an error counts as an underrun of the stream.
+    async fn read(&mut self, frames: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// The host closed the stream or changed its format.
+    async fn stop(&mut self);
+
+    /// Delay in frames from the input to [`read`](Self::read).
+    fn latency(&self) -> u32;
+}
+
+/// Start and stop of a backend following the format of the stream.
+struct Session {
+    format: Option<StreamFormat>,
+    running: bool,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            format: None,
+            running: false,
+        }
+    }
+}
+
+/// Move the packets of the speaker stream into `sink`. `buf` must hold the largest packet of the stream.
+pub async fn pump_playback<'d, D: Driver<'d>, S: AudioSink>(
+    reader: &mut AudioReader<'d, D>,
+    control: &ControlChanged<'d>,
+    sink: &mut S,
+    buf: &mut [u8],
+) -> ! {
+    loop {
+        reader.wait_enabled().await;
+        info!("Playback enabled");
+        let mut session = Session::new();
+        loop {
+            let Ok(n) = reader.read(buf).await else {
+                break;
+            };
+            let format = StreamFormat::active(control, Terminal::Speaker);
+            if format != session.format {
+                if session.running {
+                    sink.stop().await;
+                }
+                session.format = format;
+                session.running = match format {
+                    Some(format) if sink.supports(&format) => sink.start(format).await.is_ok(),
+                    _ => false,
+                };
+                info!("Playback format {}, running {}", format, session.running);
+            }
+            if session.running && n > 0 && sink.write(&buf[..n]).await.is_err() {
+                reader.report_overrun();
+            }
+        }
+        if session.running {
+            sink.stop().await;
+        }
+        info!("Playback disabled");
+    }
+}
+
+/// Fill the microphone stream from `source`, one packet per frame with the frames of 1 ms.
+/// `buf` must hold the largest packet of the stream.
+pub async fn pump_capture<'d, D: Driver<'d>, S: AudioSource>(
+    writer: &mut AudioWriter<'d, D>,
+    control: &ControlChanged<'d>,
+    source: &mut S,
+    buf: &mut [u8],
+) -> ! {
+    loop {
+        writer.wait_enabled().await;
+        info!("Capture enabled");
+        let mut session = Session::new();
+        let mut remainder = 0;
+        loop {
+            let format = StreamFormat::active(control, Terminal::Microphone);
+            if format != session.format {
+                if session.running {
+                    source.stop().await;
+                }
+                session.format = format;
+                session.running = match format {
+                    Some(format) if source.supports(&format) => source.start(format).await.is_ok(),
+                    _ => false,
+                };
+                remainder = 0;
+                info!("Capture format {}, running {}", format, session.running);
+            }
+
+            //  The endpoint paces the loop, also while the session is not running
+            let result = match (session.running, session.format) {
+                (true, Some(format)) => {
+                    let (frames, next) = frames_per_packet(format.sample_rate, remainder);
+                    remainder = next;
+                    let len = (frames * format.frame_size()).min(buf.len());
+                    let n = match source.read(&mut buf[..len]).await {
+                        Ok(n) => n.min(len),
+                        Err(_) => 0,
+                    };
+                    //  An empty packet is counted as underrun by the writer
+                    if n > 0 && n < len {
+                        writer.report_underrun();
+                    }
+                    writer.write(&buf[..n]).await
+                }
+                _ => writer.write_idle().await,
+            };
+            if result.is_err() {
+                break;
+            }
+        }
+        if session.running {
+            source.stop().await;
+        }
+        info!("Capture disabled");
+    }
+}
+
+/// Frames in the next 1 ms packet at `sample_rate`, carrying the fraction of a frame over in `remainder`.
+/// Returns the frames and the remainder for the next packet, 44.1 kHz alternates between 44 and 45 frames.
+pub fn frames_per_packet(sample_rate: u32, remainder: u32) -> (usize, u32) {
+    let total = sample_rate + remainder;
+    ((total / 1000) as usize, total % 1000)
+}
@@ -0,0 +1,55 @@
+//! Configuration checks of [`uac2::validate_config`] that run without a USB driver.
+
+use uac2::{Format, StreamConfig, FORMAT_16_BIT};
+
+/// 16.433 MHz stereo 16 bit needs 65736 byte packets, 200 once narrowed to `u16`.
+const WRAPPING_RATE: u32 = 16_433_000;
+
+#[test]
+fn packet_size_is_checked_before_narrowing() {
+    let stream = stereo(&[FORMAT_16_BIT]);
+    assert_eq!(
+        uac2::endpoint_packet_size(&[WRAPPING_RATE], &stream, &FORMAT_16_BIT, &stream),
+        None
+    );
+}
+
+#[test]
+#[should_panic(expected = "No format fits the full-speed budget at a sample rate")]
+fn oversized_packet_is_rejected() {
+    let stream = stereo(&[FORMAT_16_BIT]);
+    uac2::validate_config(&stream, &stream, &[48000, WRAPPING_RATE], &[], None);
+}
+
+#[test]
+#[should_panic(expected = "Unsupported subslot size")]
+fn oversized_subslot_is_rejected() {
+    let stream = stereo(&[Format {
+        subslot_size: 5,
+        bit_resolution: 24,
+    }]);
+    uac2::validate_config(&stream, &stream, &[48000], &[], None);
+}
+
+#[test]
+fn bit_resolution_is_bounded_by_the_subslot() {
+    for bit_resolution in [0, 17] {
+        let formats = [Format {
+            subslot_size: 2,
+            bit_resolution,
+        }];
+        let stream = stereo(&formats);
+        assert_eq!(
+            uac2::try_validate_config(&stream, &stream, &[48000], &[], None),
+            Err("Bit resolution exceeds the subslot size")
+        );
+    }
+}
+
+fn stereo<'a>(formats: &'a [Format]) -> StreamConfig<'a> {
+    StreamConfig {
+        channels: 2,
+        formats,
+        ..StreamConfig::default()
+    }
+}
@@ -0,0 +1,270 @@
+//! Class-specific control requests sent through embassy-usb to the control handler over [`MockDriver`].
+//!
+//! Entity IDs and control selectors are the wire values of the descriptors the class writes.
+
+use embassy_futures::block_on;
+use embassy_futures::select::{select, Either};
+use uac2::{State, UAC2};
+use uac2_sim::{audio_function, AudioFunction, Host, MockDriver, Stall};
+
+/// Address assigned to the device during enumeration.
+const ADDRESS: u8 = 7;
+
+#[test]
+fn get_is_truncated_to_wlength() {
+    run(uac2::Config::default(), async |host, function| {
+        let clock = Entity(function.clock_source, function.ac_interface);
+        let rate = host
+            .control_in(
+                GET,
+                CUR,
+                clock.control(CS_SAM_FREQ_CONTROL, 0),
+                clock.index(),
+                4,
+            )
+            .await
+            .unwrap();
+        assert_eq!(rate, 48000u32.to_le_bytes());
+
+        let rate = host
+            .control_in(
+                GET,
+                CUR,
+                clock.control(CS_SAM_FREQ_CONTROL, 0),
+                clock.index(),
+                2,
+            )
+            .await
+            .unwrap();
+        assert_eq!(rate, 48000u32.to_le_bytes()[..2]);
+
+        //  Hosts read wNumSubRanges first to size the full request
+        let range = host
+            .control_in(
+                GET,
+                RANGE,
+                clock.control(CS_SAM_FREQ_CONTROL, 0),
+                clock.index(),
+                2,
+            )
+            .await
+            .unwrap();
+        assert_eq!(range, 2u16.to_le_bytes());
+        let range = host
+            .control_in(
+                GET,
+                RANGE,
+                clock.control(CS_SAM_FREQ_CONTROL, 0),
+                clock.index(),
+                256,
+            )
+            .await
+            .unwrap();
+        assert_eq!(range.len(), 2 + 2 * 12);
+    });
+}
+
+#[test]
+fn every_sample_rate_has_a_subrange() {
+    let config = uac2::Config {
+        sample_rates: &[32000, 44100, 48000, 88200, 96000],
+        ..uac2::Config::default()
+    };
+    assert_eq!(config.sample_rates.len(), uac2::MAX_SAMPLE_RATES);
+    run(config, async |host, function| {
+        let clock = Entity(function.clock_source, function.ac_interface);
+        let range = host
+            .control_in(
+                GET,
+                RANGE,
+                clock.control(CS_SAM_FREQ_CONTROL, 0),
+                clock.index(),
+                256,
+            )
+            .await
+            .unwrap();
+        assert_eq!(range[..2], 5u16.to_le_bytes());
+        assert_eq!(range.len(), 2 + 5 * 12);
+        //  dMIN of the last subrange
+        assert_eq!(range[2 + 4 * 12..][..4], 96000u32.to_le_bytes());
+    });
+}
+
+#[test]
+fn set_checks_payload_size() {
+    run(uac2::Config::default(), async |host, function| {
+        let clock = Entity(function.clock_source, function.ac_interface);
+        let feature_unit = Entity(SPK_FEATURE_UNIT, function.ac_interface);
+        let set = |entity: Entity, selector: u8, channel: u8, data: &'static [u8]| {
+            host.control_out(
+                SET,
+                CUR,
+                entity.control(selector, channel),
+                entity.index(),
+                data,
+            )
+        };
+
+        assert_eq!(
+            set(feature_unit, FU_MUTE_CONTROL, 0, &[1, 0]).await,
+            Err(Stall)
+        );
+        assert_eq!(set(feature_unit, FU_MUTE_CONTROL, 0, &[]).await, Err(Stall));
+        assert_eq!(
+            set(feature_unit, FU_VOLUME_CONTROL, 1, &[0]).await,
+            Err(Stall)
+        );
+        assert_eq!(
+            set(feature_unit, FU_VOLUME_CONTROL, 1, &[0, 0, 0, 0]).await,
+            Err(Stall)
+        );
+        assert_eq!(
+            set(clock, CS_SAM_FREQ_CONTROL, 0, &[0x44, 0xAC, 0x00]).await,
+            Err(Stall)
+        );
+
+        //  A rejected request leaves the control alone
+        let mute = host
+            .control_in(
+                GET,
+                CUR,
+                feature_unit.control(FU_MUTE_CONTROL, 0),
+                feature_unit.index(),
+                1,
+            )
+            .await;
+        assert_eq!(mute, Ok(vec![0]));
+
+        assert_eq!(set(feature_unit, FU_MUTE_CONTROL, 0, &[1]).await, Ok(()));
+        assert_eq!(
+            set(feature_unit, FU_VOLUME_CONTROL, 1, &[0x00, 0xF6]).await,
+            Ok(())
+        );
+        assert_eq!(
+            set(clock, CS_SAM_FREQ_CONTROL, 0, &[0x44, 0xAC, 0x00, 0x00]).await,
+            Ok(())
+        );
+        let mute = host
+            .control_in(
+                GET,
+                CUR,
+                feature_unit.control(FU_MUTE_CONTROL, 0),
+                feature_unit.index(),
+                1,
+            )
+            .await;
+        assert_eq!(mute, Ok(vec![1]));
+    });
+}
+
+#[test]
+fn unknown_controls_stall() {
+    run(uac2::Config::default(), async |host, function| {
+        let clock = Entity(function.clock_source, function.ac_interface);
+        let feature_unit = Entity(SPK_FEATURE_UNIT, function.ac_interface);
+        let get = |entity: Entity, request: u8, selector: u8, channel: u8| {
+            host.control_in(
+                GET,
+                request,
+                entity.control(selector, channel),
+                entity.index(),
+                64,
+            )
+        };
+
+        //  Unknown selector, entity and channel
+        assert_eq!(get(feature_unit, CUR, 0x1F, 0).await, Err(Stall));
+        assert_eq!(
+            get(Entity(0x7F, function.ac_interface), CUR, 0x01, 0).await,
+            Err(Stall)
+        );
+        assert_eq!(get(feature_unit, CUR, FU_MUTE_CONTROL, 3).await, Err(Stall));
+        //  Unknown request code, and RANGE of a control without one
+        assert_eq!(get(clock, 0x03, CS_SAM_FREQ_CONTROL, 0).await, Err(Stall));
+        assert_eq!(
+            get(feature_unit, RANGE, FU_MUTE_CONTROL, 0).await,
+            Err(Stall)
+        );
+        //  Interface the function does not own
+        assert_eq!(
+            get(Entity(clock.0, 0x0F), CUR, CS_SAM_FREQ_CONTROL, 0).await,
+            Err(Stall)
+        );
+
+        //  Read-only control, and a request only defined for GET
+        let set = |entity: Entity, request: u8, selector: u8, data: &'static [u8]| {
+            host.control_out(
+                SET,
+                request,
+                entity.control(selector, 0),
+                entity.index(),
+                data,
+            )
+        };
+        assert_eq!(
+            set(clock, CUR, CS_CLOCK_VALID_CONTROL, &[1]).await,
+            Err(Stall)
+        );
+        assert_eq!(
+            set(feature_unit, RANGE, FU_MUTE_CONTROL, &[1]).await,
+            Err(Stall)
+        );
+        assert_eq!(set(feature_unit, CUR, 0x1F, &[1]).await, Err(Stall));
+    });
+}
+
+/// Entity ID and AudioControl interface, the wIndex of a request to the entity.
+#[derive(Clone, Copy)]
+struct Entity(u8, u8);
+
+impl Entity {
+    fn index(self) -> u16 {
+        (self.0 as u16) << 8 | self.1 as u16
+    }
+
+    /// wValue of control `selector` of `channel`
+    fn control(self, selector: u8, channel: u8) -> u16 {
+        (selector as u16) << 8 | channel as u16
+    }
+}
+
+/// Build the class from `config` over the mock driver and run `test` once the host enumerated it.
+fn run(config: uac2::Config<'static>, test: impl AsyncFnOnce(&Host, AudioFunction)) {
+    let (driver, host) = MockDriver::new();
+    //  The class borrows its state and the buffers for as long as the config, a test can afford to leak them
+    let mut builder = embassy_usb::Builder::new(
+        driver,
+        embassy_usb::Config::new(0xc0de, 0xcafe),
+        Box::leak(Box::new([0; 1024])),
+        Box::leak(Box::new([0; 256])),
+        Box::leak(Box::new([0; 256])),
+        Box::leak(Box::new([0; 256])),
+    );
+    let state = Box::leak(Box::new(State::new()));
+    let _parts = UAC2::new(&mut builder, state, config).into_parts();
+    let mut usb = builder.build();
+
+    let host = async {
+        host.connect();
+        let configuration = host.enumerate(ADDRESS).await.expect("enumeration stalled");
+        let function = audio_function(&configuration).expect("no UAC2 audio function");
+        test(&host, function).await
+    };
+    match block_on(select(usb.run(), host)) {
+        Either::First(_) => unreachable!(),
+        Either::Second(()) => {}
+    }
+}
+
+const GET: u8 = 0xA1;
+const SET: u8 = 0x21;
+
+const CUR: u8 = 0x01;
+const RANGE: u8 = 0x02;
+
+const SPK_FEATURE_UNIT: u8 = 0x02;
+
+const CS_SAM_FREQ_CONTROL: u8 = 0x01;
+const CS_CLOCK_VALID_CONTROL: u8 = 0x02;
+const FU_MUTE_CONTROL: u8 = 0x01;
+const FU_VOLUME_CONTROL: u8 = 0x02;
@@ -0,0 +1,200 @@
+//! Frequency, level and continuity of the generator signals, measured on the samples of the stream.
+
+use embassy_futures::block_on;
+use uac2::{AudioSource, Generator, Signal, StreamFormat, FORMAT_24_BIT};
+
+#[test]
+fn sine_frequency() {
+    for sample_rate in [44100, 48000, 96000] {
+        for frequency in [20, 440, 997, 1000, 10000, 20000] {
+            let generator = Generator::new(Signal::Sine(frequency), 0);
+            let samples = render(&generator, sample_rate, sample_rate as usize);
+            let measured = self::frequency(&samples, sample_rate);
+            //  Within 20 ppm, better than the crystal the stream is clocked from
+            assert!(
+                (measured - frequency as f64).abs() < frequency as f64 * 20e-6,
+                "{frequency} Hz at {sample_rate} Hz measured {measured} Hz"
+            );
+        }
+    }
+}
+
+#[test]
+fn sweep_covers_its_range() {
+    //  Four octaves in a second, the cycles are the integral of the exponential frequency
+    let (start, end) = (100, 1600);
+    let generator = Generator::new(
+        Signal::Sweep {
+            start,
+            end,
+            duration_ms: 1000,
+        },
+        0,
+    );
+    let samples = render(&generator, 48000, 48000);
+    let cycles = (end - start) as f64 / (end as f64 / start as f64).ln();
+    //  Whole cycles only, and the sweep starts on the crossing of frame 0 which is not counted
+    let counted = crossings(&samples).len() as f64;
+    assert!(
+        (counted - cycles).abs() < 2.0,
+        "{counted} of {cycles} cycles"
+    );
+
+    //  The first and the last period are at the ends of the range
+    let periods = crossings(&samples);
+    let first = 48000.0 / (periods[1] - periods[0]);
+    let last = 48000.0 / (periods[periods.len() - 1] - periods[periods.len() - 2]);
+    assert!(
+        (first / start as f64 - 1.0).abs() < 0.05,
+        "starts at {first} Hz"
+    );
+    assert!((last / end as f64 - 1.0).abs() < 0.05, "ends at {last} Hz");
+}
+
+#[test]
+fn sine_level() {
+    for level in [0, -3, -6, -20, -60] {
+        let generator = Generator::new(Signal::Sine(1000), level);
+        let samples = render(&generator, 48000, 48000);
+        let peak = samples
+            .iter()
+            .map(|sample| sample.unsigned_abs())
+            .max()
+            .unwrap();
+        assert_db(peak as f64, level as f64, "peak");
+        //  The RMS of a sine is 3 dB below its peak
+        assert_db(rms(&samples), level as f64 - 10.0 * 2f64.log10(), "RMS");
+    }
+}
+
+#[test]
+fn white_noise_level() {
+    for level in [0, -20] {
+        let generator = Generator::new(Signal::WhiteNoise, level);
+        let samples = render(&generator, 48000, 48000);
+        //  Uniform noise peaking at the level, its RMS is 4.8 dB below
+        assert_db(rms(&samples), level as f64 - 10.0 * 3f64.log10(), "RMS");
+    }
+}
+
+#[test]
+fn silence_is_digital_zero() {
+    let generator = Generator::new(Signal::Silence, 0);
+    assert!(render(&generator, 48000, 4800)
+        .iter()
+        .all(|&sample| sample == 0));
+}
+
+#[test]
+fn phase_continues_across_packets() {
+    let signals = [
+        Signal::Sine(997),
+        Signal::Sweep {
+            start: 20,
+            end: 20000,
+            duration_ms: 50,
+        },
+        Signal::PinkNoise,
+    ];
+    for signal in signals {
+        let generator = Generator::new(signal, -6);
+        let whole = render(&generator, 44100, 4410);
+
+        //  The packets of a 44.1 kHz stream alternate between 44 and 45 frames, odd splits on top
+        let mut source = generator.source();
+        let mut split = Vec::new();
+        block_on(async {
+            source.start(format(44100)).await.unwrap();
+            for frames in [44, 45, 44, 1, 0, 7, 100].into_iter().cycle() {
+                if split.len() >= whole.len() {
+                    break;
+                }
+                split.extend(read(&mut source, frames).await);
+            }
+        });
+        split.truncate(whole.len());
+        assert!(split == whole, "{signal:?} differs when read in packets");
+    }
+}
+
+#[test]
+fn sine_has_no_step_at_packet_boundaries() {
+    let generator = Generator::new(Signal::Sine(1000), 0);
+    let samples = render(&generator, 48000, 4800);
+    //  The largest step of a full scale sine is 2 pi f / fs of full scale
+    let max_step = 2.0 * std::f64::consts::PI * 1000.0 / 48000.0 * i32::MAX as f64;
+    for (i, pair) in samples.windows(2).enumerate() {
+        let step = (pair[1] as f64 - pair[0] as f64).abs();
+        assert!(step <= max_step * 1.01, "step of {step} at frame {i}");
+    }
+}
+
+fn format(sample_rate: u32) -> StreamFormat {
+    StreamFormat {
+        sample_rate,
+        channels: 1,
+        format: FORMAT_24_BIT,
+    }
+}
+
+/// `frames` mono 24 bit samples of the generator, read in 1 ms packets.
+fn render(generator: &Generator, sample_rate: u32, frames: usize) -> Vec<i32> {
+    let mut source = generator.source();
+    let packet = sample_rate as usize / 1000;
+    let mut samples = Vec::with_capacity(frames);
+    block_on(async {
+        source.start(format(sample_rate)).await.unwrap();
+        while samples.len() < frames {
+            samples.extend(read(&mut source, packet.min(frames - samples.len())).await);
+        }
+    });
+    samples
+}
+
+async fn read(source: &mut impl AudioSource, frames: usize) -> Vec<i32> {
+    let mut bytes = vec![0; frames * 4];
+    let Ok(filled) = source.read(&mut bytes).await else {
+        panic!("source failed");
+    };
+    assert_eq!(filled, bytes.len());
+    bytes
+        .as_chunks::<4>()
+        .0
+        .iter()
+        .map(|subslot| FORMAT_24_BIT.decode(subslot))
+        .collect()
+}
+
+/// Rising zero crossings, interpolated between the frames around them.
+fn crossings(samples: &[i32]) -> Vec<f64> {
+    samples
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] < 0 && pair[1] >= 0)
+        .map(|(i, pair)| {
+            let (a, b) = (pair[0] as f64, pair[1] as f64);
+            i as f64 + -a / (b - a)
+        })
+        .collect()
+}
+
+/// Frequency in Hz from the time between the first and the last rising zero crossing.
+fn frequency(samples: &[i32], sample_rate: u32) -> f64 {
+    let crossings = crossings(samples);
+    let (first, last) = (crossings[0], crossings[crossings.len() - 1]);
+    (crossings.len() - 1) as f64 * sample_rate as f64 / (last - first)
+}
+
+fn rms(samples: &[i32]) -> f64 {
+    let sum: f64 = samples.iter().map(|&sample| (sample as f64).powi(2)).sum();
+    (sum / samples.len() as f64).sqrt()
+}
+
+/// `value` relative to full scale is `expected` dBFS, within 0.05 dB.
+fn assert_db(value: f64, expected: f64, what: &str) {
+    let db = 20.0 * (value / i32::MAX as f64).log10();
+    assert!(
+        (db - expected).abs() < 0.05,
+        "{what} of {db:.3} dBFS, expected {expected:.3}"
+    );
+}
@@ -0,0 +1,200 @@
+//! Speaker-to-microphone loopback driven the way the stream pumps drive a sink and a source.
+
+use embassy_futures::block_on;
+use uac2::{
+    AudioSink, AudioSource, Downmix, Latency, Loopback, StreamFormat, FORMAT_16_BIT,
+    LOOPBACK_PREFILL_MS,
+};
+
+const SAMPLE_RATE: u32 = 48000;
+/// Frames of a 1 ms packet
+const PACKET: usize = SAMPLE_RATE as usize / 1000;
+/// Frames the source waits for before it plays
+const PREFILL: usize = PACKET * LOOPBACK_PREFILL_MS as usize;
+
+const LEFT: i32 = 1000 << 16;
+const RIGHT: i32 = 3000 << 16;
+
+#[test]
+fn source_waits_for_the_prefill() {
+    let loopback = Loopback::<1024>::new(Downmix::Direct);
+    let (mut sink, mut source) = (loopback.sink(), loopback.source());
+    block_on(async {
+        source.start(format(2)).await.unwrap();
+        sink.start(format(2)).await.unwrap();
+
+        for n in 0..PREFILL / PACKET {
+            let packet: Vec<[i32; 2]> = (0..PACKET).map(|i| numbered(n * PACKET + i)).collect();
+            //  Silence of a full packet until the prefill is buffered
+            assert_eq!(read(&mut source, PACKET).await, vec![[0, 0]; PACKET]);
+            sink.write(&encode(&packet)).await.unwrap();
+        }
+        let expected: Vec<[i32; 2]> = (0..PACKET).map(numbered).collect();
+        assert_eq!(read(&mut source, PACKET).await, expected);
+    });
+}
+
+#[test]
+fn underrun_primes_the_source_again() {
+    let loopback = Loopback::<1024>::new(Downmix::Direct);
+    let (mut sink, mut source) = (loopback.sink(), loopback.source());
+    block_on(async {
+        source.start(format(2)).await.unwrap();
+        sink.start(format(2)).await.unwrap();
+        write_frames(&mut sink, PREFILL, [LEFT, RIGHT]).await;
+        assert_eq!(
+            read(&mut source, PREFILL).await,
+            vec![[LEFT, RIGHT]; PREFILL]
+        );
+
+        //  The buffer ran dry half way through the packet
+        write_frames(&mut sink, PACKET / 2, [LEFT, RIGHT]).await;
+        assert_eq!(
+            read(&mut source, PACKET).await,
+            vec![[LEFT, RIGHT]; PACKET / 2]
+        );
+
+        //  Silent again until the prefill is buffered anew
+        write_frames(&mut sink, PREFILL - 1, [LEFT, RIGHT]).await;
+        assert_eq!(read(&mut source, PACKET).await, vec![[0, 0]; PACKET]);
+        write_frames(&mut sink, 1, [LEFT, RIGHT]).await;
+        assert_eq!(read(&mut source, PACKET).await, vec![[LEFT, RIGHT]; PACKET]);
+    });
+}
+
+#[test]
+fn downmix_maps_the_speaker_channels() {
+    let mix = |downmix, microphone: u8| {
+        let loopback = Loopback::<1024>::new(downmix);
+        let (mut sink, mut source) = (loopback.sink(), loopback.source());
+        block_on(async {
+            source.start(format(microphone)).await.unwrap();
+            sink.start(format(2)).await.unwrap();
+            write_frames(&mut sink, PREFILL, [LEFT, RIGHT]).await;
+            let mut frame = vec![0; format(microphone).frame_size()];
+            assert_eq!(source.read(&mut frame).await, Ok(frame.len()));
+            decode(&frame)
+        })
+    };
+
+    assert_eq!(mix(Downmix::Mono, 1), [2000 << 16]);
+    assert_eq!(mix(Downmix::Mono, 2), [2000 << 16, 2000 << 16]);
+    assert_eq!(mix(Downmix::Direct, 1), [LEFT]);
+    assert_eq!(mix(Downmix::Direct, 2), [LEFT, RIGHT]);
+    assert_eq!(mix(Downmix::Direct, 3), [LEFT, RIGHT, LEFT]);
+    assert_eq!(mix(Downmix::Channel(1), 2), [RIGHT, RIGHT]);
+    //  A channel the speaker does not have is silent
+    assert_eq!(mix(Downmix::Channel(2), 1), [0]);
+}
+
+#[test]
+fn impulse_returning_on_the_speaker_measures_the_latency() {
+    let loopback = Loopback::<1024>::new(Downmix::Direct);
+    let (mut sink, mut source) = (loopback.sink(), loopback.source());
+    block_on(async {
+        source.start(format(2)).await.unwrap();
+        sink.start(format(2)).await.unwrap();
+        assert_eq!(loopback.latency(), Latency::Idle);
+
+        loopback.measure_latency();
+        assert_eq!(loopback.latency(), Latency::Measuring);
+        let frames = read(&mut source, PACKET).await;
+        let impulse = frames[0][0];
+        assert!(impulse > i32::MAX / 4, "impulse {impulse:#x}");
+        assert_eq!(frames[0], [impulse, impulse]);
+        assert!(frames[1..].iter().all(|frame| *frame == [0, 0]));
+
+        //  The loopback stays silent while the impulse travels, and does not buffer
+        write_frames(&mut sink, 10, [0, 0]).await;
+        assert_eq!(read(&mut source, PACKET).await, vec![[0, 0]; PACKET]);
+        assert_eq!(loopback.fill().0, 0);
+        assert_eq!(loopback.latency(), Latency::Measuring);
+
+        //  An impulse attenuated by the host still counts
+        write_frames(&mut sink, 1, [0, impulse / 4]).await;
+        assert_eq!(loopback.latency(), Latency::Measured(10));
+    });
+}
+
+#[test]
+fn impulse_lost_on_the_host_times_out() {
+    let loopback = Loopback::<1024>::new(Downmix::Direct);
+    let (mut sink, mut source) = (loopback.sink(), loopback.source());
+    block_on(async {
+        source.start(format(2)).await.unwrap();
+        sink.start(format(2)).await.unwrap();
+        loopback.measure_latency();
+        read(&mut source, PACKET).await;
+
+        //  A second of frames after the one carrying the impulse
+        write_frames(&mut sink, SAMPLE_RATE as usize + 1, [0, 0]).await;
+        assert_eq!(loopback.latency(), Latency::Measuring);
+        write_frames(&mut sink, 1, [0, 0]).await;
+        assert_eq!(loopback.latency(), Latency::TimedOut);
+    });
+}
+
+#[test]
+fn speaker_stopping_ends_the_measurement() {
+    let loopback = Loopback::<1024>::new(Downmix::Direct);
+    let (mut sink, mut source) = (loopback.sink(), loopback.source());
+    block_on(async {
+        source.start(format(2)).await.unwrap();
+        //  Armed, the impulse waits for the speaker stream
+        loopback.measure_latency();
+        assert_eq!(read(&mut source, PACKET).await, vec![[0, 0]; PACKET]);
+        assert_eq!(loopback.latency(), Latency::Measuring);
+
+        sink.start(format(2)).await.unwrap();
+        read(&mut source, PACKET).await;
+        sink.stop().await;
+        assert_eq!(loopback.latency(), Latency::TimedOut);
+    });
+}
+
+/// 16 bit stream with `channels` at [`SAMPLE_RATE`].
+fn format(channels: u8) -> StreamFormat {
+    StreamFormat {
+        sample_rate: SAMPLE_RATE,
+        channels,
+        format: FORMAT_16_BIT,
+    }
+}
+
+/// Frame `n` of a ramp, so a frame out of order or twice is told apart.
+fn numbered(n: usize) -> [i32; 2] {
+    [(n as i32) << 16, -(n as i32) << 16]
+}
+
+fn encode<const C: usize>(frames: &[[i32; C]]) -> Vec<u8> {
+    let mut bytes = vec![0; frames.len() * C * 2];
+    for (sample, subslot) in frames.iter().flatten().zip(bytes.as_chunks_mut::<2>().0) {
+        FORMAT_16_BIT.encode(*sample, subslot);
+    }
+    bytes
+}
+
+fn decode(bytes: &[u8]) -> Vec<i32> {
+    bytes
+        .as_chunks::<2>()
+        .0
+        .iter()
+        .map(|subslot| FORMAT_16_BIT.decode(subslot))
+        .collect()
+}
+
+async fn write_frames(sink: &mut impl AudioSink, frames: usize, frame: [i32; 2]) {
+    for start in (0..frames).step_by(PACKET) {
+        let packet = vec![frame; PACKET.min(frames - start)];
+        assert!(sink.write(&encode(&packet)).await.is_ok());
+    }
+}
+
+/// Read a packet of `frames` stereo frames, only the frames filled are returned.
+async fn read(source: &mut impl AudioSource, frames: usize) -> Vec<[i32; 2]> {
+    let mut bytes = vec![0; frames * 2 * 2];
+    let Ok(filled) = source.read(&mut bytes).await else {
+        panic!("source failed");
+    };
+    decode(&bytes[..filled]).as_chunks::<2>().0.to_vec()
+}